@@ -8,7 +8,6 @@ pub fn generate(output_dir: &Path) -> IoResult<()> {
 // This automatically generated file is included in request.rs.
 {
     use method::{Connect, Delete, Get, Head, Options, Patch, Post, Put, Trace, ExtensionMethod};
-    use server::request::MAX_METHOD_LEN;
     use rfc2616::{SP, is_token_item};
 
 ")));
@@ -29,7 +28,7 @@ pub fn generate(output_dir: &Path) -> IoResult<()> {
         1,
         "self.stream.read_byte()",
         "SP",
-        "MAX_METHOD_LEN",
+        "self.limits.max_method_len",
         "is_token_item(b)",
         "ExtensionMethod({})"));
     writer.write(bytes!("}\n"))
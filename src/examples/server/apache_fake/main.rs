@@ -12,7 +12,7 @@ use std::vec::Vec;
 use std::io::net::ip::{SocketAddr, Ipv4Addr};
 use std::io::Writer;
 
-use http::server::{Config, Server, Request, ResponseWriter};
+use http::server::{Config, Server, Request, RequestBody, ResponseWriter, ServerStream};
 use http::headers;
 
 #[deriving(Clone)]
@@ -20,10 +20,11 @@ struct ApacheFakeServer;
 
 impl Server for ApacheFakeServer {
     fn get_config(&self) -> Config {
-        Config { bind_address: SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 } }
+        Config::new(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 })
     }
 
-    fn handle_request(&self, _r: &Request, w: &mut ResponseWriter) {
+    fn handle_request(&self, _r: &Request, _b: &mut RequestBody<ServerStream>,
+                      w: &mut ResponseWriter) {
         w.headers.date = Some(time::now_utc());
         w.headers.server = Some(~"Apache/2.2.22 (Ubuntu)");
         //w.headers.last_modified = Some(~"Thu, 05 May 2011 11:46:42 GMT");
@@ -8,7 +8,7 @@ extern crate http;
 use std::io::net::ip::{SocketAddr, Ipv4Addr};
 use std::io::Writer;
 
-use http::server::{Config, Server, Request, ResponseWriter};
+use http::server::{Config, Server, Request, RequestBody, ResponseWriter, ServerStream};
 use http::headers::content_type::MediaType;
 
 #[deriving(Clone)]
@@ -16,10 +16,11 @@ struct HelloWorldServer;
 
 impl Server for HelloWorldServer {
     fn get_config(&self) -> Config {
-        Config { bind_address: SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 } }
+        Config::new(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 })
     }
 
-    fn handle_request(&self, _r: &Request, w: &mut ResponseWriter) {
+    fn handle_request(&self, _r: &Request, _b: &mut RequestBody<ServerStream>,
+                      w: &mut ResponseWriter) {
         w.headers.date = Some(time::now_utc());
         w.headers.content_length = Some(14);
         w.headers.content_type = Some(MediaType {
@@ -9,7 +9,7 @@ extern crate http;
 use std::io::net::ip::{SocketAddr, Ipv4Addr};
 use std::io::Writer;
 
-use http::server::{Config, Server, Request, ResponseWriter};
+use http::server::{Config, Server, Request, RequestBody, ResponseWriter, ServerStream};
 use http::headers::HeaderEnum;
 use http::headers::content_type::MediaType;
 
@@ -18,10 +18,11 @@ struct InfoServer;
 
 impl Server for InfoServer {
     fn get_config(&self) -> Config {
-        Config { bind_address: SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 } }
+        Config::new(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 })
     }
 
-    fn handle_request(&self, r: &Request, w: &mut ResponseWriter) {
+    fn handle_request(&self, r: &Request, b: &mut RequestBody<ServerStream>,
+                      w: &mut ResponseWriter) {
         w.headers.date = Some(time::now_utc());
         w.headers.content_type = Some(MediaType {
             type_: ~"text",
@@ -54,7 +55,7 @@ impl Server for InfoServer {
         }
         w.write(bytes!("</tbody></table>")).unwrap();
         w.write(bytes!("<h2>Body</h2><pre>")).unwrap();
-        w.write(r.body.as_bytes()).unwrap();
+        w.write(b.read_to_end().unwrap()).unwrap();
         w.write(bytes!("</pre>")).unwrap();
 
         w.write(bytes!("<h1>Response</h1>")).unwrap();
@@ -14,7 +14,7 @@ use std::vec::Vec;
 use std::io::net::ip::{SocketAddr, Ipv4Addr};
 use std::io::Writer;
 
-use http::server::{Config, Server, Request, ResponseWriter};
+use http::server::{Config, Server, Request, RequestBody, ResponseWriter, ServerStream};
 use http::server::request::{Star, AbsoluteUri, AbsolutePath, Authority};
 use http::status::{BadRequest, MethodNotAllowed};
 use http::method::{Get, Head, Post, Put, Delete, Trace, Options, Connect, Patch};
@@ -25,10 +25,11 @@ struct RequestUriServer;
 
 impl Server for RequestUriServer {
     fn get_config(&self) -> Config {
-        Config { bind_address: SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 } }
+        Config::new(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 })
     }
 
-    fn handle_request(&self, r: &Request, w: &mut ResponseWriter) {
+    fn handle_request(&self, r: &Request, _b: &mut RequestBody<ServerStream>,
+                      w: &mut ResponseWriter) {
         w.headers.date = Some(time::now_utc());
         w.headers.server = Some(~"Rust Thingummy/0.1-pre");
 
@@ -0,0 +1,783 @@
+/*!
+
+A cache of responses: a private cache, in the terms of RFC 7234, for a `Session`.
+
+A `Session` with a `Cache` stores the responses to its GET requests which may be cached, and
+serves them again for as long as they are fresh without making a request at all. Once one is
+stale, it is revalidated: the request is sent with If-None-Match and If-Modified-Since (from the
+stored ETag and Last-Modified), and if the server says that the stored response will still do (304,
+Not Modified), that is what is returned. A response which was chosen according to request headers
+named by its Vary header is only used for requests with the same values of those headers. Entries
+are keyed by URL alone, though, so there is only ever one response stored for a URL: responses which
+`Vary` evict each other, as requests with different values of those headers alternate.
+
+```rust
+use http::client::cache::Cache;
+use http::client::session::Session;
+use std::io::net::tcp::TcpStream;
+
+let mut session: Session<TcpStream> = Session::new();
+session.cache = Some(Cache::in_memory());
+// Or, to keep responses between runs of the program:
+session.cache = Some(Cache::on_disk(Path::new("/var/cache/my-program")).unwrap());
+
+let config = session.get("http://example.com/config.json").unwrap();
+```
+
+What is stored, and for how long, follows the response's Cache-Control header (`max-age`,
+`no-store` and `no-cache` are heeded), or failing that its Expires header, or failing that a tenth
+of the time since its Last-Modified date; a request can ask for a fresher response with
+`Cache-Control: max-age=...` or `no-cache` of its own. Responses reached by following a redirect are
+not stored, and a successful POST, PUT or DELETE (or other unsafe method) removes whatever is
+stored for its URL.
+
+Entries are kept by a `CacheStorage`: `MemoryStorage`, which keeps them until the cache is cleared,
+or `DiskStorage`, which keeps them in files in a directory.
+
+*/
+
+use std::ascii::StrAsciiExt;
+use std::cmp::max;
+use std::hash;
+use std::io::{IoError, IoResult, OtherIoError, MemReader, MemWriter, Stream, UserDir};
+use std::io::fs;
+use std::io::fs::File;
+use std::rand;
+use std::str;
+use collections::hashmap::HashMap;
+use sync::{Arc, Mutex};
+use time::get_time;
+use url::Url;
+
+use buffer::BufferedStream;
+use common::parse_http_date;
+use connecter::Connecter;
+use headers::HeaderEnum;
+use headers::request::HeaderCollection;
+use headers::response;
+use method::{Get, Head, Options, Trace};
+use rfc2616::LF;
+use status;
+use status::Status;
+use client::request::RequestWriter;
+use client::response::read_response_head;
+use client::session::Response;
+
+/// The Cache-Control directives which the cache heeds (RFC 7234, §5.2).
+#[deriving(Eq, Show)]
+struct CacheControl {
+    no_store: bool,
+    /// A `no-cache` with field names is taken as applying to the whole response, which is the
+    /// cautious reading of it
+    no_cache: bool,
+    max_age: Option<i64>,
+}
+
+impl CacheControl {
+    fn parse(value: Option<&~str>) -> CacheControl {
+        let mut directives = CacheControl {
+            no_store: false,
+            no_cache: false,
+            max_age: None,
+        };
+        let value = match value {
+            Some(value) => value.as_slice(),
+            None => return directives,
+        };
+        for directive in value.split(',') {
+            let (name, argument) = match directive.find('=') {
+                Some(i) => (directive.slice_to(i), Some(directive.slice_from(i + 1).trim())),
+                None => (directive, None),
+            };
+            match name.trim().to_ascii_lower().as_slice() {
+                "no-store" => directives.no_store = true,
+                "no-cache" => directives.no_cache = true,
+                "max-age" => directives.max_age = argument.and_then(|age| from_str::<i64>(age)),
+                _ => (),
+            }
+        }
+        directives
+    }
+}
+
+/// Whether a response with the status may be stored without explicit freshness information, its
+/// freshness then being estimated (RFC 7231, §6.1; RFC 7538, §3).
+fn is_cacheable_by_default(status: &Status) -> bool {
+    match status.code() {
+        200 | 203 | 204 | 300 | 301 | 308 | 404 | 405 | 410 | 414 | 501 => true,
+        _ => false,
+    }
+}
+
+/// The values which the request has of the headers named by the value of a Vary header, with the
+/// names in lower case, and `None` for any header which the request lacks.
+fn vary_values(vary: &str, request: &HeaderCollection) -> Vec<(~str, Option<~str>)> {
+    vary.split(',').map(|name| name.trim()).filter(|name| name.len() > 0).map(|name| {
+        let value = request.iter().find(|header| {
+            header.header_name().eq_ignore_ascii_case(name)
+        }).map(|header| header.header_value());
+        (name.to_ascii_lower(), value)
+    }).collect()
+}
+
+fn corrupt_entry_err() -> IoError {
+    IoError {
+        kind: OtherIoError,
+        desc: "corrupt cache entry",
+        detail: None,
+    }
+}
+
+/// A stored response, with what is needed to tell whether it is fresh and whether it suits a
+/// request.
+#[deriving(Clone)]
+pub struct CacheEntry {
+    /// The URL which the response came from
+    url: Url,
+
+    /// The HTTP version number of the response
+    version: (uint, uint),
+
+    /// The status of the response
+    status: Status,
+
+    /// The headers of the response (updated by any revalidation since)
+    headers: ~response::HeaderCollection,
+
+    /// The body of the response
+    body: ~[u8],
+
+    /// The values which the request had of the headers named by the response's Vary header (see
+    /// `vary_values`)
+    vary: Vec<(~str, Option<~str>)>,
+
+    /// When the request was sent, in seconds since the epoch
+    request_time: i64,
+
+    /// When the response was received, in seconds since the epoch
+    response_time: i64,
+}
+
+impl CacheEntry {
+    /// An entry for the response, if it may be stored, to the request with the given headers.
+    fn from_response(response: &Response, request: &HeaderCollection, request_time: i64,
+                     response_time: i64) -> Option<CacheEntry> {
+        let cache_control = CacheControl::parse(response.headers.cache_control.as_ref());
+        if cache_control.no_store || response.redirect_chain.len() > 0 ||
+                response.status == status::PartialContent ||
+                response.status == status::NotModified {
+            return None;
+        }
+        let explicit = cache_control.max_age.is_some() || response.headers.expires.is_some();
+        if !explicit && !is_cacheable_by_default(&response.status) {
+            return None;
+        }
+        let vary = match response.headers.vary {
+            // The response may vary by anything at all, so it suits no other request
+            Some(ref vary) if vary.trim() == "*" => return None,
+            Some(ref vary) => vary_values(*vary, request),
+            None => Vec::new(),
+        };
+        let entry = CacheEntry {
+            url: response.url.clone(),
+            version: response.version,
+            status: response.status.clone(),
+            headers: response.headers.clone(),
+            body: response.bytes().to_owned(),
+            vary: vary,
+            request_time: request_time,
+            response_time: response_time,
+        };
+        // A response which is never fresh and cannot be revalidated is no use stored
+        if entry.freshness_lifetime() <= 0 && entry.headers.etag.is_none() &&
+                entry.headers.last_modified.is_none() {
+            return None;
+        }
+        Some(entry)
+    }
+
+    /// The time that the response was generated, in seconds since the epoch: its Date, or if it
+    /// has none, when it was received.
+    fn date(&self) -> i64 {
+        match self.headers.date {
+            Some(ref date) => date.to_timespec().sec,
+            None => self.response_time,
+        }
+    }
+
+    /// How long the response is fresh for after it was generated, in seconds (RFC 7234, §4.2.1).
+    pub fn freshness_lifetime(&self) -> i64 {
+        match CacheControl::parse(self.headers.cache_control.as_ref()).max_age {
+            Some(max_age) => return max_age,
+            None => (),
+        }
+        match self.headers.expires {
+            // An invalid date, such as "0", means that it has already expired
+            Some(ref expires) => return match parse_http_date(*expires) {
+                Some(expires) => expires.to_timespec().sec - self.date(),
+                None => 0,
+            },
+            None => (),
+        }
+        // Failing those, a tenth of the time since it was last modified (§4.2.2)
+        match self.headers.last_modified {
+            Some(ref last_modified) if is_cacheable_by_default(&self.status) => {
+                max(0, (self.date() - last_modified.to_timespec().sec) / 10)
+            },
+            _ => 0,
+        }
+    }
+
+    /// How old the response is at `now` (in seconds since the epoch), in seconds (RFC 7234,
+    /// §4.2.3).
+    pub fn current_age(&self, now: i64) -> i64 {
+        let apparent_age = max(0, self.response_time - self.date());
+        let age_value = self.headers.age.as_ref().and_then(|age| from_str::<i64>(age.trim()));
+        let response_delay = self.response_time - self.request_time;
+        let corrected_initial_age = max(apparent_age, age_value.unwrap_or(0) + response_delay);
+        corrected_initial_age + now - self.response_time
+    }
+
+    /// Whether the response may be used without revalidation at `now`.
+    pub fn is_fresh(&self, now: i64) -> bool {
+        !CacheControl::parse(self.headers.cache_control.as_ref()).no_cache &&
+            self.freshness_lifetime() > self.current_age(now)
+    }
+
+    /// Whether the response suits a request with the given headers, as far as its Vary header is
+    /// concerned.
+    fn matches(&self, request: &HeaderCollection) -> bool {
+        self.vary.iter().all(|&(ref name, ref value)| {
+            let actual = request.iter().find(|header| {
+                header.header_name().eq_ignore_ascii_case(*name)
+            }).map(|header| header.header_value());
+            actual == *value
+        })
+    }
+
+    /// Update the entry from a 304 (Not Modified) response to revalidating it (RFC 7234, §4.3.4).
+    fn freshen(&mut self, headers: &response::HeaderCollection, request_time: i64,
+               response_time: i64) {
+        for header in headers.iter() {
+            match header {
+                // These describe the 304's (empty) body, not the stored one
+                response::ContentLength(_) | response::TransferEncoding(_) => (),
+                header => self.headers.insert(header),
+            }
+        }
+        self.request_time = request_time;
+        self.response_time = response_time;
+    }
+
+    /// The stored response
+    fn response(&self) -> Response {
+        Response::new(self.url.clone(), self.version, self.status.clone(), self.headers.clone(),
+                      self.body.clone())
+    }
+
+    /// The entry in the form in which `DiskStorage` keeps it: the URL, the request and response
+    /// times, and the Vary values, a line each, then a blank line and the response as it would be
+    /// sent (but with the body as decoded).
+    pub fn to_bytes(&self) -> IoResult<~[u8]> {
+        let mut w = MemWriter::new();
+        try!(write!(&mut w as &mut Writer, "{}\n{} {}\n", self.url.to_str(), self.request_time,
+                    self.response_time));
+        for &(ref name, ref value) in self.vary.iter() {
+            try!(match *value {
+                Some(ref value) => write!(&mut w as &mut Writer, "{}: {}\n", *name, *value),
+                None => write!(&mut w as &mut Writer, "{}\n", *name),
+            });
+        }
+        let (major, minor) = self.version;
+        try!(write!(&mut w as &mut Writer, "\nHTTP/{}.{} {}\r\n", major, minor,
+                    self.status.to_str()));
+        try!(self.headers.write_all(&mut w));
+        try!(w.write(self.body));
+        Ok(w.unwrap())
+    }
+
+    /// Read an entry written by `to_bytes`.
+    pub fn from_bytes(bytes: ~[u8]) -> IoResult<CacheEntry> {
+        let mut stream = BufferedStream::new(EntryStream(MemReader::new(bytes)));
+        let url = try!(from_str::<Url>(try!(read_line(&mut stream))).ok_or(corrupt_entry_err()));
+        let times = try!(read_line(&mut stream));
+        let times: Vec<Option<i64>> = times.words().map(|time| from_str(time)).collect();
+        let (request_time, response_time) = match times.as_slice() {
+            [Some(request_time), Some(response_time)] => (request_time, response_time),
+            _ => return Err(corrupt_entry_err()),
+        };
+        let mut vary = Vec::new();
+        loop {
+            let line = try!(read_line(&mut stream));
+            if line.len() == 0 {
+                break;
+            }
+            vary.push(match line.find(':') {
+                Some(i) => {
+                    (line.slice_to(i).to_owned(), Some(line.slice_from(i + 1).trim().to_owned()))
+                },
+                None => (line, None),
+            });
+        }
+        let (version, status, headers, _set_cookies) =
+            try!(read_response_head(&mut stream).map_err(|err| err.to_io_error()));
+        let body = try!(stream.read_to_end());
+        Ok(CacheEntry {
+            url: url,
+            version: version,
+            status: status,
+            headers: headers,
+            body: body,
+            vary: vary,
+            request_time: request_time,
+            response_time: response_time,
+        })
+    }
+}
+
+/// A stored entry as a stream, so that the response head can be read from it; it cannot be written
+/// to.
+struct EntryStream(MemReader);
+
+impl Reader for EntryStream {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        let &EntryStream(ref mut reader) = self;
+        reader.read(buf)
+    }
+}
+
+impl Writer for EntryStream {
+    fn write(&mut self, _buf: &[u8]) -> IoResult<()> {
+        Err(corrupt_entry_err())
+    }
+}
+
+/// Read a line ending with LF (which is not included).
+fn read_line<S: Stream>(stream: &mut BufferedStream<S>) -> IoResult<~str> {
+    let mut line = Vec::new();
+    loop {
+        match try!(stream.read_byte()) {
+            b if b == LF => break,
+            b => line.push(b),
+        }
+    }
+    str::from_utf8(line.as_slice()).map(|line| line.to_owned()).ok_or(corrupt_entry_err())
+}
+
+/// Where a `Cache` keeps its entries, which are keyed by URL.
+pub trait CacheStorage {
+    /// The entry for the key, if there is one.
+    fn get(&self, key: &str) -> IoResult<Option<CacheEntry>>;
+
+    /// Store the entry for the key, replacing any which there was.
+    fn put(&self, key: &str, entry: &CacheEntry) -> IoResult<()>;
+
+    /// Remove the entry for the key, if there is one.
+    fn remove(&self, key: &str) -> IoResult<()>;
+
+    /// Remove all the entries.
+    fn clear(&self) -> IoResult<()>;
+}
+
+/// Storage in memory, which lasts as long as the `Cache`. Nothing is removed to make room, so
+/// clear the cache from time to time if many different URLs are requested.
+pub struct MemoryStorage {
+    priv entries: Mutex<HashMap<~str, CacheEntry>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage {
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl CacheStorage for MemoryStorage {
+    fn get(&self, key: &str) -> IoResult<Option<CacheEntry>> {
+        Ok(self.entries.lock().find_equiv(&key).map(|entry| entry.clone()))
+    }
+
+    fn put(&self, key: &str, entry: &CacheEntry) -> IoResult<()> {
+        self.entries.lock().insert(key.to_owned(), entry.clone());
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> IoResult<()> {
+        self.entries.lock().remove(&key.to_owned());
+        Ok(())
+    }
+
+    fn clear(&self) -> IoResult<()> {
+        self.entries.lock().clear();
+        Ok(())
+    }
+}
+
+/// Storage in files in a directory, a file for each entry (see `CacheEntry.to_bytes`), which
+/// lasts from one run of the program to the next. Several caches may share the directory.
+pub struct DiskStorage {
+    priv dir: Path,
+}
+
+static EXTENSION: &'static str = "cache";
+
+impl DiskStorage {
+    /// Storage in the directory, which is created if it does not exist.
+    pub fn new(dir: Path) -> IoResult<DiskStorage> {
+        if !dir.is_dir() {
+            try!(fs::mkdir_recursive(&dir, UserDir));
+        }
+        Ok(DiskStorage {
+            dir: dir,
+        })
+    }
+
+    /// The file for the key, named after its hash.
+    fn path(&self, key: &str) -> Path {
+        self.dir.join(format!("{:016x}.{}", hash::hash(&key), EXTENSION))
+    }
+}
+
+impl CacheStorage for DiskStorage {
+    fn get(&self, key: &str) -> IoResult<Option<CacheEntry>> {
+        let path = self.path(key);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let entry = try!(CacheEntry::from_bytes(try!(File::open(&path).read_to_end())));
+        // Another key with the same hash, maybe
+        if entry.url.to_str().as_slice() != key {
+            return Ok(None);
+        }
+        Ok(Some(entry))
+    }
+
+    fn put(&self, key: &str, entry: &CacheEntry) -> IoResult<()> {
+        // Written in full before it replaces the old file, so that a reader never sees half of it,
+        // and to a file of its own, so that two writers of the same key (in this process or
+        // another sharing the directory) don't write into each other's. Its extension isn't
+        // EXTENSION, so `clear` leaves it be.
+        let path = self.path(key);
+        let temporary = path.with_extension(format!("{:016x}.tmp", rand::random::<u64>()));
+        let bytes = try!(entry.to_bytes());
+        match File::create(&temporary).write(bytes) {
+            Ok(()) => fs::rename(&temporary, &path),
+            Err(e) => {
+                let _ = fs::unlink(&temporary);
+                Err(e)
+            },
+        }
+    }
+
+    fn remove(&self, key: &str) -> IoResult<()> {
+        let path = self.path(key);
+        if path.exists() {
+            try!(fs::unlink(&path));
+        }
+        Ok(())
+    }
+
+    fn clear(&self) -> IoResult<()> {
+        for path in try!(fs::readdir(&self.dir)).iter() {
+            if path.extension_str() == Some(EXTENSION) {
+                try!(fs::unlink(path));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A cache of responses, which may be shared between sessions (and tasks).
+#[deriving(Clone)]
+pub struct Cache {
+    priv storage: Arc<~CacheStorage:Send+Share>,
+}
+
+impl Cache {
+    /// A cache with its entries kept by the given storage.
+    pub fn new(storage: ~CacheStorage:Send+Share) -> Cache {
+        Cache {
+            storage: Arc::new(storage),
+        }
+    }
+
+    /// A cache with its entries kept in memory.
+    pub fn in_memory() -> Cache {
+        Cache::new(~MemoryStorage::new() as ~CacheStorage:Send+Share)
+    }
+
+    /// A cache with its entries kept in files in the directory, which is created if it does not
+    /// exist.
+    pub fn on_disk(dir: Path) -> IoResult<Cache> {
+        let storage = try!(DiskStorage::new(dir));
+        Ok(Cache::new(~storage as ~CacheStorage:Send+Share))
+    }
+
+    /// Forget all the stored responses.
+    pub fn clear(&self) -> IoResult<()> {
+        self.storage.clear()
+    }
+
+    /// Respond to the request from the cache if possible, or otherwise by `send`, which must write
+    /// the body, read the response and return it (as `Session` does), storing the response if it
+    /// may be.
+    ///
+    /// The cache being unreadable or unwritable is not an error: the request is made as though
+    /// there were no cache.
+    pub fn send_with<S: Connecter + Stream + Send>(
+            &self, request: RequestWriter<S>, body: &[u8],
+            send: |RequestWriter<S>, &[u8]| -> IoResult<Response>) -> IoResult<Response> {
+        let key = request.url.to_str();
+        if request.method != Get {
+            let is_unsafe = match request.method {
+                Head | Options | Trace => false,
+                _ => true,
+            };
+            let response = try!(send(request, body));
+            // Whatever is stored is likely to be out of date now (RFC 7234, §4.4)
+            if is_unsafe && !response.is_client_error() && !response.is_server_error() {
+                self.report(self.storage.remove(key));
+            }
+            return Ok(response);
+        }
+
+        let request_cache_control = CacheControl::parse(request.headers.cache_control.as_ref());
+        let entry = match self.storage.get(key) {
+            Ok(Some(entry)) => if entry.matches(&*request.headers) { Some(entry) } else { None },
+            Ok(None) => None,
+            Err(err) => {
+                self.report(Err(err));
+                None
+            },
+        };
+
+        let mut request = request;
+        let mut revalidating = false;
+        match entry {
+            Some(ref entry) => {
+                let now = get_time().sec;
+                let no_cache = request_cache_control.no_cache ||
+                    request.headers.pragma.as_ref().map_or(false, |pragma| {
+                        pragma.as_slice().to_ascii_lower().contains("no-cache")
+                    });
+                let too_old = request_cache_control.max_age.map_or(false, |max_age| {
+                    entry.current_age(now) > max_age
+                });
+                if !no_cache && !too_old && entry.is_fresh(now) {
+                    debug!("responding to {} from the cache", key);
+                    return Ok(entry.response());
+                }
+                // Ask whether it will still do, unless the request has conditions of its own
+                if request.headers.if_none_match.is_none() &&
+                        request.headers.if_modified_since.is_none() {
+                    request.headers.if_none_match = entry.headers.etag.as_ref()
+                                                         .map(|etag| etag.to_str());
+                    request.headers.if_modified_since = entry.headers.last_modified.clone();
+                    revalidating = request.headers.if_none_match.is_some() ||
+                                   request.headers.if_modified_since.is_some();
+                }
+            },
+            None => (),
+        }
+
+        let request_headers = request.headers.clone();
+        let request_time = get_time().sec;
+        let response = try!(send(request, body));
+        let response_time = get_time().sec;
+
+        if revalidating && response.status == status::NotModified &&
+                response.redirect_chain.len() == 0 {
+            debug!("revalidated {} in the cache", key);
+            let mut entry = entry.unwrap();
+            entry.freshen(&*response.headers, request_time, response_time);
+            self.report(self.storage.put(key, &entry));
+            return Ok(entry.response());
+        }
+        if !request_cache_control.no_store {
+            match CacheEntry::from_response(&response, &*request_headers, request_time,
+                                            response_time) {
+                Some(entry) => self.report(self.storage.put(key, &entry)),
+                None if entry.is_some() => self.report(self.storage.remove(key)),
+                None => (),
+            }
+        }
+        Ok(response)
+    }
+
+    /// Log a failure of the storage, which is otherwise ignored.
+    fn report(&self, result: IoResult<()>) {
+        match result {
+            Ok(()) => (),
+            Err(err) => error!("cache storage failed: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::TempDir;
+    use std::io::fs;
+    use time::get_time;
+    use headers::request::HeaderCollection;
+    use headers::response;
+    use status;
+    use client::session::Response;
+    use super::{CacheControl, CacheEntry, CacheStorage, DiskStorage, MemoryStorage};
+
+    /// An entry for a response with the given headers, received just now
+    fn entry(headers: &str) -> CacheEntry {
+        entry_for(headers, &HeaderCollection::new())
+    }
+
+    fn entry_for(headers: &str, request: &HeaderCollection) -> CacheEntry {
+        let raw = format!("{}\n0 0\n\nHTTP/1.1 200 OK\r\n{}\r\nHello", "http://example.com/",
+                          headers);
+        let stored = CacheEntry::from_bytes(raw.as_bytes().to_owned()).unwrap();
+        let response = Response::new(stored.url, stored.version, stored.status, stored.headers,
+                                     stored.body);
+        let now = get_time().sec;
+        CacheEntry::from_response(&response, request, now, now).unwrap()
+    }
+
+    #[test]
+    fn test_cache_control() {
+        let parse = |value: &str| CacheControl::parse(Some(&value.to_owned()));
+        assert_eq!(CacheControl::parse(None),
+                   CacheControl { no_store: false, no_cache: false, max_age: None });
+        assert_eq!(parse("public, Max-Age=60"),
+                   CacheControl { no_store: false, no_cache: false, max_age: Some(60) });
+        assert_eq!(parse("no-store,no-cache=\"Set-Cookie\""),
+                   CacheControl { no_store: true, no_cache: true, max_age: None });
+    }
+
+    #[test]
+    fn test_freshness() {
+        let now = get_time().sec;
+        let fresh = entry("Cache-Control: max-age=60\r\n");
+        assert_eq!(fresh.freshness_lifetime(), 60);
+        assert!(fresh.is_fresh(now));
+        assert!(!fresh.is_fresh(now + 61));
+
+        // Age from upstream caches counts
+        let aged = entry("Cache-Control: max-age=60\r\nAge: 50\r\n");
+        assert!(aged.is_fresh(now));
+        assert!(!aged.is_fresh(now + 20));
+
+        assert!(!entry("Cache-Control: max-age=60, no-cache\r\n").is_fresh(now));
+        assert!(!entry("Expires: 0\r\nETag: \"x\"\r\n").is_fresh(now));
+
+        let expires = entry("Date: Sun, 06 Nov 1994 08:49:37 GMT\r\n\
+                             Expires: Sun, 06 Nov 1994 08:50:37 GMT\r\n");
+        assert_eq!(expires.freshness_lifetime(), 60);
+
+        let heuristic = entry("Date: Sun, 06 Nov 1994 08:49:37 GMT\r\n\
+                               Last-Modified: Sun, 06 Nov 1994 07:49:37 GMT\r\n");
+        assert_eq!(heuristic.freshness_lifetime(), 360);
+    }
+
+    #[test]
+    fn test_not_storable() {
+        let not_storable = |headers: &str, status: status::Status| {
+            let raw = format!("http://example.com/\n0 0\n\nHTTP/1.1 {}\r\n{}\r\n",
+                              status.to_str(), headers);
+            let stored = CacheEntry::from_bytes(raw.as_bytes().to_owned()).unwrap();
+            let response = Response::new(stored.url, stored.version, stored.status,
+                                         stored.headers, stored.body);
+            CacheEntry::from_response(&response, &HeaderCollection::new(), 0, 0).is_none()
+        };
+        assert!(not_storable("Cache-Control: no-store, max-age=60\r\n", status::Ok));
+        assert!(not_storable("Cache-Control: max-age=60\r\nVary: *\r\n", status::Ok));
+        assert!(not_storable("ETag: \"x\"\r\n", status::InternalServerError));
+        // Neither fresh nor revalidatable
+        assert!(not_storable("", status::Ok));
+        assert!(!not_storable("ETag: \"x\"\r\n", status::Ok));
+        assert!(!not_storable("Cache-Control: max-age=60\r\n", status::InternalServerError));
+    }
+
+    #[test]
+    fn test_vary() {
+        let mut request = HeaderCollection::new();
+        request.accept_language = Some(~"en");
+        let entry = entry_for("Cache-Control: max-age=60\r\nVary: Accept-Language, Accept\r\n",
+                              &request);
+        assert_eq!(entry.vary, vec!((~"accept-language", Some(~"en")), (~"accept", None)));
+        assert!(entry.matches(&request));
+        request.accept_language = Some(~"fr");
+        assert!(!entry.matches(&request));
+    }
+
+    #[test]
+    fn test_freshen() {
+        let mut entry = entry("ETag: \"1\"\r\nContent-Length: 5\r\n");
+        let mut headers = response::HeaderCollection::new();
+        headers.cache_control = Some(~"max-age=60");
+        headers.content_length = Some(0);
+        entry.freshen(&headers, 1, 2);
+        assert_eq!(entry.headers.cache_control, Some(~"max-age=60"));
+        assert_eq!(entry.headers.content_length, Some(5));
+        assert_eq!(entry.response_time, 2);
+        assert_eq!(entry.response().bytes(), bytes!("Hello"));
+    }
+
+    fn test_storage(storage: &CacheStorage) {
+        let key = "http://example.com/";
+        assert!(storage.get(key).unwrap().is_none());
+        let mut request = HeaderCollection::new();
+        request.accept_language = Some(~"en");
+        let entry = entry_for("Cache-Control: max-age=60\r\nVary: Accept-Language, Accept\r\n",
+                              &request);
+        storage.put(key, &entry).unwrap();
+        let stored = storage.get(key).unwrap().unwrap();
+        assert_eq!(stored.url, entry.url);
+        assert_eq!(stored.status, status::Ok);
+        assert_eq!(stored.headers.cache_control, Some(~"max-age=60"));
+        assert_eq!(stored.body, bytes!("Hello").to_owned());
+        assert_eq!(stored.vary, entry.vary);
+        assert_eq!(stored.response_time, entry.response_time);
+        assert!(storage.get("http://example.com/other").unwrap().is_none());
+
+        storage.remove(key).unwrap();
+        assert!(storage.get(key).unwrap().is_none());
+        storage.put(key, &entry).unwrap();
+        storage.clear().unwrap();
+        assert!(storage.get(key).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_memory_storage() {
+        test_storage(&MemoryStorage::new());
+    }
+
+    #[test]
+    fn test_disk_storage() {
+        let dir = TempDir::new("rust-http-test").unwrap();
+        test_storage(&DiskStorage::new(dir.path().join("cache")).unwrap());
+    }
+
+    #[test]
+    fn test_disk_storage_concurrent_puts() {
+        let dir = TempDir::new("rust-http-test").unwrap();
+        let path = dir.path().join("cache");
+        let key = "http://example.com/";
+        let storage = DiskStorage::new(path.clone()).unwrap();
+        let (tx, rx) = channel();
+        for _ in range(0, 8) {
+            let (tx, path) = (tx.clone(), path.clone());
+            spawn(proc() {
+                let storage = DiskStorage::new(path).unwrap();
+                let entry = entry("Cache-Control: max-age=60\r\n");
+                for _ in range(0, 20) {
+                    storage.put(key, &entry).unwrap();
+                }
+                tx.send(());
+            });
+        }
+        for _ in range(0, 8) {
+            rx.recv();
+        }
+        assert_eq!(storage.get(key).unwrap().unwrap().body, bytes!("Hello").to_owned());
+        // Every temporary file was renamed into place
+        assert_eq!(fs::readdir(&path).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,384 @@
+/*!
+
+Cookie storage for the client, following RFC 6265.
+
+A `CookieJar` is attached to a `RequestWriter` by setting its `cookie_jar` field. Cookies from the
+Set-Cookie headers of the response are then stored in the jar, and the Cookie header of any request
+made with the jar is filled in with those cookies which apply to the request's URL.
+
+Clones of a jar share the same cookies.
+
+```rust
+use http::client::RequestWriter;
+use http::client::cookie::CookieJar;
+use http::method::Get;
+
+let jar = CookieJar::new();
+let mut request = RequestWriter::new(Get, from_str("http://example.com/login").unwrap()).unwrap();
+request.cookie_jar = Some(jar.clone());
+// ... and cookies set in the response will be sent with later requests using the jar.
+```
+
+The public suffix list is not consulted, so a Domain attribute naming a public suffix (e.g.
+"co.uk") is not rejected, though single-label domains (e.g. "com") are.
+
+*/
+
+use std::ascii::StrAsciiExt;
+use std::io::net::ip::IpAddr;
+use sync::{Arc, Mutex};
+use time;
+use time::strptime;
+use url::Url;
+
+/// A cookie, as stored by a `CookieJar` (RFC 6265, §5.3).
+#[deriving(Clone, Eq)]
+pub struct Cookie {
+    /// The name of the cookie
+    name: ~str,
+
+    /// The value of the cookie
+    value: ~str,
+
+    /// The host or domain to which the cookie is sent (in lower case)
+    domain: ~str,
+
+    /// If `true`, the cookie is only sent to a host identical to `domain`; if `false`, it is also
+    /// sent to its subdomains.
+    host_only: bool,
+
+    /// The path (and those beneath it) for which the cookie is sent
+    path: ~str,
+
+    /// Whether the cookie is only to be sent over secure connections (HTTPS)
+    secure: bool,
+
+    /// Whether the cookie is only for HTTP use (not that that means anything for this client)
+    http_only: bool,
+
+    /// When the cookie expires, in seconds since the epoch; `None` for a session cookie.
+    expires: Option<i64>,
+}
+
+impl Cookie {
+    /// Parse the value of a Set-Cookie header received in response to a request for `url`,
+    /// following the algorithm of RFC 6265, §5.2 and §5.3. `now` is the current time in seconds
+    /// since the epoch, for the interpretation of the Max-Age attribute.
+    ///
+    /// `None` is returned if the cookie is to be ignored.
+    pub fn parse(set_cookie: &str, url: &Url, now: i64) -> Option<Cookie> {
+        let mut parts = set_cookie.split(';');
+        let (name, value) = match parts.next() {
+            Some(pair) => match pair.find('=') {
+                Some(i) => (pair.slice_to(i).trim(), pair.slice_from(i + 1).trim()),
+                None => return None,
+            },
+            None => return None,
+        };
+        if name.len() == 0 {
+            return None;
+        }
+
+        let host = url.host.to_ascii_lower();
+        let mut cookie = Cookie {
+            name: name.to_owned(),
+            value: value.to_owned(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(url.path),
+            secure: false,
+            http_only: false,
+            expires: None,
+        };
+        let mut max_age = None;
+        let mut expires = None;
+
+        for attribute in parts {
+            let (key, value) = match attribute.find('=') {
+                Some(i) => (attribute.slice_to(i).trim(), attribute.slice_from(i + 1).trim()),
+                None => (attribute.trim(), ""),
+            };
+            match key.to_ascii_lower().as_slice() {
+                "expires" => {
+                    match parse_cookie_date(value) {
+                        Some(t) => expires = Some(t),
+                        None => (),
+                    }
+                },
+                "max-age" => {
+                    // Non-positive means "expire now"; anything unparseable is ignored.
+                    match from_str::<i64>(value) {
+                        Some(delta) if delta <= 0 => max_age = Some(::std::i64::MIN),
+                        Some(delta) => max_age = Some(now + delta),
+                        None => (),
+                    }
+                },
+                "domain" if value.len() > 0 => {
+                    let domain = if value.starts_with(".") { value.slice_from(1) } else { value };
+                    let domain = domain.to_ascii_lower();
+                    if domain != host {
+                        // Not for the likes of "com", and of course only for a domain of the host.
+                        if !domain.contains_char('.') || !domain_match(host, domain) {
+                            return None;
+                        }
+                    }
+                    cookie.domain = domain;
+                    cookie.host_only = false;
+                },
+                "path" if value.starts_with("/") => cookie.path = value.to_owned(),
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                _ => (),
+            }
+        }
+
+        // Max-Age takes precedence over Expires
+        cookie.expires = max_age.or(expires);
+        Some(cookie)
+    }
+
+    /// Whether the cookie has expired at the given time (in seconds since the epoch).
+    pub fn is_expired(&self, now: i64) -> bool {
+        match self.expires {
+            Some(expires) => expires <= now,
+            None => false,
+        }
+    }
+
+    /// Whether the cookie is to be sent with a request for the given URL (RFC 6265, §5.4).
+    pub fn matches(&self, url: &Url) -> bool {
+        let host = url.host.to_ascii_lower();
+        let host_matches = if self.host_only {
+            host == self.domain
+        } else {
+            domain_match(host, self.domain)
+        };
+        host_matches &&
+            path_match(if url.path.len() == 0 { "/" } else { url.path.as_slice() }, self.path) &&
+            (!self.secure || url.scheme.as_slice() == "https")
+    }
+}
+
+/// Whether the host domain-matches the domain string (RFC 6265, §5.1.3).
+fn domain_match(host: &str, domain: &str) -> bool {
+    if host == domain {
+        return true;
+    }
+    // IP addresses only match exactly
+    if from_str::<IpAddr>(host).is_some() {
+        return false;
+    }
+    host.len() > domain.len() &&
+        host.ends_with(domain) &&
+        host.char_at(host.len() - domain.len() - 1) == '.'
+}
+
+/// Whether the request path path-matches the cookie path (RFC 6265, §5.1.4).
+fn path_match(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path ||
+        (request_path.starts_with(cookie_path) &&
+         (cookie_path.ends_with("/") || request_path.char_at(cookie_path.len()) == '/'))
+}
+
+/// The default path of a cookie, from the path of the request URL (RFC 6265, §5.1.4).
+fn default_path(path: &str) -> ~str {
+    if !path.starts_with("/") {
+        return ~"/";
+    }
+    match path.rfind('/') {
+        Some(0) | None => ~"/",
+        Some(i) => path.slice_to(i).to_owned(),
+    }
+}
+
+/// Parse the date of the Expires attribute, returning seconds since the epoch.
+///
+/// RFC 6265 (§5.1.1) describes a very lenient algorithm for this; for now, we just try the formats
+/// which are seen in practice.
+fn parse_cookie_date(value: &str) -> Option<i64> {
+    static FORMATS: &'static [&'static str] = &[
+        "%a, %d %b %Y %T GMT",  // RFC 1123
+        "%a, %d-%b-%Y %T GMT",  // Netscape's original format
+        "%A, %d-%b-%y %T GMT",  // RFC 850
+        "%a, %d-%b-%y %T GMT",
+        "%c",                   // asctime()
+    ];
+    for format in FORMATS.iter() {
+        match strptime(value, *format) {
+            Ok(tm) => return Some(tm.to_timespec().sec),
+            Err(_) => (),
+        }
+    }
+    None
+}
+
+/// A collection of cookies, shared between its clones.
+pub struct CookieJar {
+    priv cookies: Arc<Mutex<Vec<Cookie>>>,
+}
+
+impl CookieJar {
+    /// Create an empty cookie jar.
+    pub fn new() -> CookieJar {
+        CookieJar {
+            cookies: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Store the cookies from the values of the Set-Cookie headers of a response to a request for
+    /// the given URL.
+    pub fn store(&self, url: &Url, set_cookies: &[~str]) {
+        let now = time::get_time().sec;
+        let mut cookies = self.cookies.lock();
+        for set_cookie in set_cookies.iter() {
+            let cookie = match Cookie::parse(*set_cookie, url, now) {
+                Some(cookie) => cookie,
+                None => continue,
+            };
+            // A cookie replaces any with the same name, domain and path. (An expired cookie
+            // replaces it with nothing, which is how cookies are deleted.)
+            match cookies.iter().position(|c| c.name == cookie.name &&
+                                              c.domain == cookie.domain &&
+                                              c.path == cookie.path) {
+                Some(i) => { cookies.remove(i); },
+                None => (),
+            }
+            if !cookie.is_expired(now) {
+                cookies.push(cookie);
+            }
+        }
+    }
+
+    /// The value for the Cookie header of a request for the given URL, or `None` if there are no
+    /// cookies for it (RFC 6265, §5.4).
+    pub fn cookie_header(&self, url: &Url) -> Option<~str> {
+        let now = time::get_time().sec;
+        let mut cookies = self.cookies.lock();
+        cookies.retain(|c| !c.is_expired(now));
+
+        let mut matching: Vec<&Cookie> = cookies.iter().filter(|c| c.matches(url)).collect();
+        if matching.len() == 0 {
+            return None;
+        }
+        // Longer paths first; otherwise, in order of creation (which is the order in the jar).
+        // (The sort must be stable for that, which an insertion sort is.)
+        for i in range(1, matching.len()) {
+            let mut j = i;
+            while j > 0 && matching.get(j - 1).path.len() < matching.get(j).path.len() {
+                matching.as_mut_slice().swap(j - 1, j);
+                j -= 1;
+            }
+        }
+        let pairs: Vec<~str> = matching.iter().map(|c| format!("{}={}", c.name, c.value))
+                                              .collect();
+        Some(pairs.connect("; "))
+    }
+
+    /// A copy of all the (unexpired) cookies in the jar.
+    pub fn cookies(&self) -> Vec<Cookie> {
+        let now = time::get_time().sec;
+        self.cookies.lock().iter().filter(|c| !c.is_expired(now)).map(|c| c.clone()).collect()
+    }
+
+    /// Remove all cookies from the jar.
+    pub fn clear(&self) {
+        self.cookies.lock().clear();
+    }
+}
+
+impl Clone for CookieJar {
+    fn clone(&self) -> CookieJar {
+        CookieJar {
+            cookies: self.cookies.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use url::Url;
+    use super::{Cookie, CookieJar, default_path, domain_match, path_match};
+
+    fn url(s: &str) -> Url {
+        from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let cookie = Cookie::parse("SID=31d4d96e407aad42; Path=/; Secure; HttpOnly",
+                                   &url("https://www.example.com/login"), 0).unwrap();
+        assert_eq!(cookie.name, ~"SID");
+        assert_eq!(cookie.value, ~"31d4d96e407aad42");
+        assert_eq!(cookie.domain, ~"www.example.com");
+        assert!(cookie.host_only);
+        assert_eq!(cookie.path, ~"/");
+        assert!(cookie.secure);
+        assert!(cookie.http_only);
+        assert_eq!(cookie.expires, None);
+
+        let cookie = Cookie::parse("lang=en-US; Domain=.Example.com; Max-Age=60",
+                                   &url("http://www.example.com/docs/index.html"), 1000).unwrap();
+        assert_eq!(cookie.domain, ~"example.com");
+        assert!(!cookie.host_only);
+        assert_eq!(cookie.path, ~"/docs");
+        assert_eq!(cookie.expires, Some(1060));
+
+        let cookie = Cookie::parse("lang=en; Expires=Wed, 09 Jun 2021 10:18:14 GMT",
+                                   &url("http://example.com/"), 0).unwrap();
+        assert_eq!(cookie.expires, Some(1623233894));
+
+        // Not for a domain of the host
+        assert_eq!(Cookie::parse("a=b; Domain=example.org", &url("http://example.com/"), 0),
+                   None);
+        // Not for a top-level domain
+        assert_eq!(Cookie::parse("a=b; Domain=com", &url("http://example.com/"), 0), None);
+        // No name
+        assert_eq!(Cookie::parse("=b", &url("http://example.com/"), 0), None);
+        assert_eq!(Cookie::parse("b", &url("http://example.com/"), 0), None);
+    }
+
+    #[test]
+    fn test_matching_rules() {
+        assert!(domain_match("example.com", "example.com"));
+        assert!(domain_match("www.example.com", "example.com"));
+        assert!(!domain_match("wwwexample.com", "example.com"));
+        assert!(!domain_match("example.com", "www.example.com"));
+        assert!(!domain_match("192.168.0.1", "168.0.1"));
+
+        assert!(path_match("/", "/"));
+        assert!(path_match("/docs", "/docs"));
+        assert!(path_match("/docs/web", "/docs"));
+        assert!(path_match("/docs/web", "/docs/"));
+        assert!(!path_match("/docsweb", "/docs"));
+        assert!(!path_match("/", "/docs"));
+
+        assert_eq!(default_path(""), ~"/");
+        assert_eq!(default_path("/"), ~"/");
+        assert_eq!(default_path("/index.html"), ~"/");
+        assert_eq!(default_path("/docs/index.html"), ~"/docs");
+        assert_eq!(default_path("/docs/web/"), ~"/docs/web");
+    }
+
+    #[test]
+    fn test_jar() {
+        let jar = CookieJar::new();
+        let origin = url("http://www.example.com/docs/index.html");
+        jar.store(&origin, [~"a=1", ~"b=2; Path=/", ~"c=3; Domain=example.com; Path=/",
+                            ~"d=4; Secure"]);
+        assert_eq!(jar.cookies().len(), 4);
+
+        assert_eq!(jar.cookie_header(&url("http://www.example.com/docs/")), Some(~"a=1; b=2; c=3"));
+        assert_eq!(jar.cookie_header(&url("https://www.example.com/docs/x")),
+                   Some(~"a=1; d=4; b=2; c=3"));
+        assert_eq!(jar.cookie_header(&url("http://www.example.com/")), Some(~"b=2; c=3"));
+        assert_eq!(jar.cookie_header(&url("http://other.example.com/")), Some(~"c=3"));
+        assert_eq!(jar.cookie_header(&url("http://example.org/")), None);
+
+        // Replacement and deletion
+        jar.store(&origin, [~"a=one", ~"b=gone; Path=/; Max-Age=0"]);
+        assert_eq!(jar.clone().cookie_header(&url("http://www.example.com/docs/")),
+                   Some(~"a=one; c=3"));
+        jar.clear();
+        assert_eq!(jar.cookie_header(&url("http://www.example.com/docs/")), None);
+    }
+}
@@ -0,0 +1,829 @@
+/*!
+
+Decoding of compressed response bodies (the gzip and deflate content-codings of RFC 2616, §3.5).
+
+A request with `decode_content` set asks for a compressed body with the Accept-Encoding header, and
+the `ResponseReader` then decompresses a body which comes with `Content-Encoding: gzip` or
+`Content-Encoding: deflate` as it is read. The body as sent is still to be had with
+`ResponseReader.read_raw`.
+
+As `flate` can only inflate a whole buffer at once, a `Decoder` does the inflating itself (RFC
+1951), reading the body as it needs to and keeping no more than the last 32KiB it has decoded. So
+that a small body cannot be made to decompress to an enormous one, the decoded length is limited
+(see `RequestWriter.max_decoded_len`). At the end, the checksum of the gzip or zlib format (CRC-32
+or Adler-32) is checked, so that a corrupt body is an error.
+
+*/
+
+use std::io::{IoError, IoResult, InvalidInput, OtherIoError, EndOfFile};
+use std::slice;
+
+use rfc2616::content_coding::{ValueToken, Gzip, Deflate};
+
+/// The value of the Accept-Encoding header sent by a request with `decode_content` set.
+pub static ACCEPT_ENCODING: &'static str = "gzip, deflate";
+
+/// By default, a body may decode to no more than 64MiB.
+pub static DEFAULT_MAX_DECODED_LEN: uint = 64 * 1024 * 1024;
+
+/// How far back a match may refer to: 32KiB, a power of two.
+static WINDOW_SIZE: uint = 32768;
+
+/// How much of the encoded body is read at a time.
+static INPUT_SIZE: uint = 4096;
+
+/// The longest a Huffman code can be, in bits. (Arrays indexed by code length have 16 elements.)
+static MAX_CODE_LEN: uint = 15;
+
+fn corrupt_body_err(coding: ValueToken) -> IoError {
+    IoError {
+        kind: OtherIoError,
+        desc: "Could not decode the response body",
+        detail: Some(format!("corrupt {} data", coding)),
+    }
+}
+
+fn too_long_err(max_len: uint) -> IoError {
+    IoError {
+        kind: OtherIoError,
+        desc: "The decoded response body is too long",
+        detail: Some(format!("longer than {} bytes", max_len)),
+    }
+}
+
+fn unsupported_coding_err(coding: ValueToken) -> IoError {
+    IoError {
+        kind: InvalidInput,
+        desc: "Cannot decode the content-coding",
+        detail: Some(format!("{}", coding)),
+    }
+}
+
+/// The content-coding which can be decoded from the value of a Content-Encoding header, if there
+/// is one. Several codings applied in turn, or any unsupported coding, cannot be decoded; nor need
+/// the identity coding be.
+pub fn decodable_coding(content_encoding: &str) -> Option<ValueToken> {
+    match from_str::<ValueToken>(content_encoding.trim()) {
+        Some(Gzip) => Some(Gzip),
+        Some(Deflate) => Some(Deflate),
+        _ => None,
+    }
+}
+
+/// Where a `Decoder` has got to in the body.
+#[deriving(Eq)]
+enum State {
+    /// Nothing has been read: next is the gzip or zlib header, or the first block of bare deflate
+    /// data.
+    Start,
+
+    /// At the start of a deflate block, or after the last one
+    BlockStart,
+
+    /// In a stored (uncompressed) block, with the given number of bytes left
+    Stored(uint),
+
+    /// In a compressed block, which is decoded with `literals` and `distances`
+    Compressed,
+
+    /// The body has been decoded in full, and the gzip or zlib trailer checked
+    Done,
+}
+
+/// A decoder of a body with the gzip or deflate content-coding, which reads the encoded body as it
+/// needs to.
+pub struct Decoder {
+    /// The content-coding being decoded
+    priv coding: ValueToken,
+
+    /// Whether the deflate data is in the zlib format (RFC 1950) rather than bare (RFC 1951)
+    priv zlib: bool,
+
+    priv state: State,
+
+    /// Whether the current block is the last
+    priv last_block: bool,
+
+    /// The literal/length code of the current compressed block
+    priv literals: Huffman,
+
+    /// The distance code of the current compressed block
+    priv distances: Huffman,
+
+    /// How much of a match is still to be copied, and from how far back
+    priv copy_len: uint,
+    priv copy_distance: uint,
+
+    /// The last `WINDOW_SIZE` bytes decoded, for matches to be copied from
+    priv window: ~[u8],
+
+    /// How many bytes have been decoded
+    priv decoded_len: uint,
+
+    /// The most bytes which may be decoded; any more is an error
+    priv max_len: Option<uint>,
+
+    /// The Adler-32 checksum (RFC 1950, §8.2) of the decoded data, in its two halves
+    priv adler: (u32, u32),
+
+    /// The CRC-32 (RFC 1952, §8) of the decoded data, inverted, as it is while being computed
+    priv crc: u32,
+
+    priv input: Input,
+}
+
+impl Decoder {
+    /// A decoder of the given content-coding, which must be gzip or deflate, which is to decode no
+    /// more than `max_len` bytes if that is given.
+    pub fn new(coding: ValueToken, max_len: Option<uint>) -> IoResult<Decoder> {
+        match coding {
+            Gzip | Deflate => (),
+            _ => return Err(unsupported_coding_err(coding)),
+        }
+        Ok(Decoder {
+            coding: coding,
+            zlib: false,
+            state: Start,
+            last_block: false,
+            literals: Huffman::empty(),
+            distances: Huffman::empty(),
+            copy_len: 0,
+            copy_distance: 0,
+            window: slice::from_elem(WINDOW_SIZE, 0u8),
+            decoded_len: 0,
+            max_len: max_len,
+            adler: (1, 0),
+            crc: 0xffffffff,
+            input: Input::new(),
+        })
+    }
+
+    /// Read decoded data into `buf`, reading the encoded body from `raw` as it is needed.
+    ///
+    /// Once some data has been decoded, it is returned rather than waiting for more of the body to
+    /// arrive. At the end of the body, this is an `EndOfFile` error.
+    pub fn read<R: Reader>(&mut self, raw: &mut R, buf: &mut [u8]) -> IoResult<uint> {
+        if buf.len() == 0 {
+            return Ok(0);
+        }
+        match self.decode_into(raw, buf) {
+            Ok(0) => Err(IoError {
+                kind: EndOfFile,
+                desc: "end of the decoded body",
+                detail: None,
+            }),
+            // The body has ended before the data did
+            Err(ref err) if err.kind == EndOfFile => Err(corrupt_body_err(self.coding.clone())),
+            result => result,
+        }
+    }
+
+    fn corrupt(&self) -> IoError {
+        corrupt_body_err(self.coding.clone())
+    }
+
+    /// Decode into `buf` until it is full, the data runs out, or more of the body would have to be
+    /// waited for after something has been decoded.
+    fn decode_into<R: Reader>(&mut self, raw: &mut R, buf: &mut [u8]) -> IoResult<uint> {
+        let mut len = 0;
+        while len < buf.len() && self.state != Done {
+            // Rather than wait for more of the body, or fail for want of room, return what there is
+            if len > 0 && (self.input.is_empty() || self.at_max_len()) {
+                break;
+            }
+            if self.copy_len > 0 {
+                let byte = self.window[(self.decoded_len - self.copy_distance) & (WINDOW_SIZE - 1)];
+                self.copy_len -= 1;
+                try!(self.emit(byte));
+                buf[len] = byte;
+                len += 1;
+                continue;
+            }
+            match self.state {
+                Start => try!(self.read_header(raw)),
+                BlockStart if self.last_block => {
+                    try!(self.read_trailer(raw));
+                    self.state = Done;
+                },
+                BlockStart => try!(self.read_block_header(raw)),
+                Stored(0) => self.state = BlockStart,
+                Stored(left) => {
+                    let byte = try!(self.input.byte(raw));
+                    self.state = Stored(left - 1);
+                    try!(self.emit(byte));
+                    buf[len] = byte;
+                    len += 1;
+                },
+                Compressed => match try!(self.literals.decode(&mut self.input, raw)) {
+                    Some(literal) if literal < 256 => {
+                        try!(self.emit(literal as u8));
+                        buf[len] = literal as u8;
+                        len += 1;
+                    },
+                    Some(256) => self.state = BlockStart,
+                    Some(symbol) => try!(self.read_match(raw, symbol)),
+                    None => return Err(self.corrupt()),
+                },
+                Done => break,
+            }
+        }
+        Ok(len)
+    }
+
+    fn at_max_len(&self) -> bool {
+        self.max_len.map_or(false, |max_len| self.decoded_len >= max_len)
+    }
+
+    /// Count a decoded byte, keeping it for matches to refer back to.
+    fn emit(&mut self, byte: u8) -> IoResult<()> {
+        match self.max_len {
+            Some(max_len) if self.decoded_len >= max_len => return Err(too_long_err(max_len)),
+            _ => (),
+        }
+        self.window[self.decoded_len & (WINDOW_SIZE - 1)] = byte;
+        self.decoded_len += 1;
+        if self.zlib {
+            let (a, b) = self.adler;
+            let a = (a + byte as u32) % 65521;
+            self.adler = (a, (b + a) % 65521);
+        } else if self.coding == Gzip {
+            self.crc = crc32_update(self.crc, byte);
+        }
+        Ok(())
+    }
+
+    /// Read the gzip header (RFC 1952, §2.3), or the zlib header if the deflate data has one. An
+    /// empty body, as for a response to HEAD, needs no decoding whatever its Content-Encoding.
+    fn read_header<R: Reader>(&mut self, raw: &mut R) -> IoResult<()> {
+        if !try!(self.input.buffer(raw, 1)) {
+            self.state = Done;
+            return Ok(());
+        }
+        match self.coding {
+            Gzip => try!(self.read_gzip_header(raw)),
+            _ => {
+                // "deflate" is meant to be the zlib format (RFC 1950), but some servers send bare
+                // deflate data (RFC 1951); the zlib header can tell them apart.
+                self.zlib = try!(self.input.buffer(raw, 2)) && {
+                    let (cmf, flg) = self.input.peek2();
+                    cmf & 0x0f == 8 && ((cmf as uint) << 8 | flg as uint) % 31 == 0
+                };
+                if self.zlib {
+                    let _cmf = try!(self.input.byte(raw));
+                    let flg = try!(self.input.byte(raw));
+                    // A preset dictionary (FDICT) is not something HTTP has any use for
+                    if flg & 0x20 != 0 {
+                        return Err(self.corrupt());
+                    }
+                }
+            },
+        }
+        self.state = BlockStart;
+        Ok(())
+    }
+
+    fn read_gzip_header<R: Reader>(&mut self, raw: &mut R) -> IoResult<()> {
+        // ID1, ID2, CM (8 is deflate), FLG, MTIME (4 bytes), XFL, OS
+        let mut header = [0u8, ..10];
+        for byte in header.mut_iter() {
+            *byte = try!(self.input.byte(raw));
+        }
+        if header[0] != 0x1f || header[1] != 0x8b || header[2] != 8 {
+            return Err(self.corrupt());
+        }
+        let flags = header[3];
+        if flags & FEXTRA != 0 {
+            let extra_len = try!(self.input.bits(raw, 16));
+            for _ in range(0, extra_len) {
+                try!(self.input.byte(raw));
+            }
+        }
+        // The file name and comment are zero-terminated
+        for &flag in [FNAME, FCOMMENT].iter() {
+            if flags & flag != 0 {
+                while try!(self.input.byte(raw)) != 0 {}
+            }
+        }
+        if flags & FHCRC != 0 {
+            try!(self.input.bits(raw, 16));
+        }
+        Ok(())
+    }
+
+    /// Read the header of a deflate block (RFC 1951, §3.2.3), and the codes of a block compressed
+    /// with dynamic Huffman codes.
+    fn read_block_header<R: Reader>(&mut self, raw: &mut R) -> IoResult<()> {
+        self.last_block = try!(self.input.bits(raw, 1)) == 1;
+        match try!(self.input.bits(raw, 2)) {
+            0 => {
+                // The length and its ones' complement follow, from the next byte boundary
+                self.input.align();
+                let len = try!(self.input.bits(raw, 16));
+                let complement = try!(self.input.bits(raw, 16));
+                if len != !complement & 0xffff {
+                    return Err(self.corrupt());
+                }
+                self.state = Stored(len);
+            },
+            1 => {
+                self.literals = Huffman::fixed_literals();
+                self.distances = Huffman::fixed_distances();
+                self.state = Compressed;
+            },
+            2 => {
+                try!(self.read_dynamic_codes(raw));
+                self.state = Compressed;
+            },
+            _ => return Err(self.corrupt()),
+        }
+        Ok(())
+    }
+
+    /// Read the code lengths of a block compressed with dynamic Huffman codes (RFC 1951, §3.2.7).
+    fn read_dynamic_codes<R: Reader>(&mut self, raw: &mut R) -> IoResult<()> {
+        let literal_count = try!(self.input.bits(raw, 5)) + 257;
+        let distance_count = try!(self.input.bits(raw, 5)) + 1;
+        let code_length_count = try!(self.input.bits(raw, 4)) + 4;
+        if literal_count > 286 || distance_count > 30 {
+            return Err(self.corrupt());
+        }
+
+        // The code lengths are themselves Huffman coded
+        let mut code_lengths = [0u8, ..19];
+        for &symbol in CODE_LENGTH_ORDER.slice_to(code_length_count).iter() {
+            code_lengths[symbol] = try!(self.input.bits(raw, 3)) as u8;
+        }
+        let code_length_code = match Huffman::new(code_lengths.as_slice()) {
+            Some(code) => code,
+            None => return Err(self.corrupt()),
+        };
+
+        let count = literal_count + distance_count;
+        let mut lengths: Vec<u8> = Vec::with_capacity(count);
+        while lengths.len() < count {
+            let symbol = match try!(code_length_code.decode(&mut self.input, raw)) {
+                Some(symbol) => symbol,
+                None => return Err(self.corrupt()),
+            };
+            let (length, repeat) = match symbol {
+                0..15 => (symbol as u8, 1),
+                // Repeat the previous length
+                16 => match lengths.last() {
+                    Some(&previous) => (previous, 3 + try!(self.input.bits(raw, 2))),
+                    None => return Err(self.corrupt()),
+                },
+                17 => (0, 3 + try!(self.input.bits(raw, 3))),
+                _ => (0, 11 + try!(self.input.bits(raw, 7))),
+            };
+            if lengths.len() + repeat > count {
+                return Err(self.corrupt());
+            }
+            for _ in range(0, repeat) {
+                lengths.push(length);
+            }
+        }
+        // Without a code for the end of the block, it would never end
+        if *lengths.get(256) == 0 {
+            return Err(self.corrupt());
+        }
+
+        match (Huffman::new(lengths.as_slice().slice_to(literal_count)),
+               Huffman::new(lengths.as_slice().slice_from(literal_count))) {
+            (Some(literals), Some(distances)) => {
+                self.literals = literals;
+                self.distances = distances;
+                Ok(())
+            },
+            _ => Err(self.corrupt()),
+        }
+    }
+
+    /// Read the length and distance of a match, given the symbol which began it.
+    fn read_match<R: Reader>(&mut self, raw: &mut R, symbol: uint) -> IoResult<()> {
+        let index = symbol - 257;
+        if index >= LENGTH_BASE.len() {
+            return Err(self.corrupt());
+        }
+        let len = LENGTH_BASE[index] as uint +
+                  try!(self.input.bits(raw, LENGTH_EXTRA_BITS[index] as uint));
+        let index = match try!(self.distances.decode(&mut self.input, raw)) {
+            Some(index) if index < DISTANCE_BASE.len() => index,
+            _ => return Err(self.corrupt()),
+        };
+        let distance = DISTANCE_BASE[index] as uint +
+                       try!(self.input.bits(raw, DISTANCE_EXTRA_BITS[index] as uint));
+        // A match can't refer back to before the start of the data
+        if distance > self.decoded_len {
+            return Err(self.corrupt());
+        }
+        self.copy_len = len;
+        self.copy_distance = distance;
+        Ok(())
+    }
+
+    /// Check the trailer which follows the deflate data in the gzip and zlib formats: the CRC-32
+    /// and length (modulo 2^32) of the decoded data for gzip (RFC 1952, §2.3.1), or its Adler-32
+    /// checksum for zlib.
+    fn read_trailer<R: Reader>(&mut self, raw: &mut R) -> IoResult<()> {
+        self.input.align();
+        match self.coding {
+            Gzip => {
+                let crc = try!(self.input.bits(raw, 16)) | try!(self.input.bits(raw, 16)) << 16;
+                let isize = try!(self.input.bits(raw, 16)) | try!(self.input.bits(raw, 16)) << 16;
+                if crc as u32 != !self.crc || isize as u32 != self.decoded_len as u32 {
+                    return Err(self.corrupt());
+                }
+            },
+            _ if self.zlib => {
+                // Unlike everything else here, this is big-endian
+                let mut checksum = 0u32;
+                for _ in range(0, 4) {
+                    checksum = checksum << 8 | try!(self.input.byte(raw)) as u32;
+                }
+                let (a, b) = self.adler;
+                if checksum != b << 16 | a {
+                    return Err(self.corrupt());
+                }
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+/// Add a byte to a CRC-32 (RFC 1952, §8), bit by bit.
+fn crc32_update(crc: u32, byte: u8) -> u32 {
+    let mut crc = crc ^ byte as u32;
+    for _ in range(0, 8) {
+        crc = if crc & 1 != 0 { crc >> 1 ^ 0xedb88320 } else { crc >> 1 };
+    }
+    crc
+}
+
+static FHCRC: u8 = 1 << 1;
+static FEXTRA: u8 = 1 << 2;
+static FNAME: u8 = 1 << 3;
+static FCOMMENT: u8 = 1 << 4;
+
+/// The order in which the lengths of the code length code are given.
+static CODE_LENGTH_ORDER: [uint, ..19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2,
+                                          14, 1, 15];
+
+/// The shortest length of a match for each length symbol from 257, and the number of extra bits
+/// which are added to it.
+static LENGTH_BASE: [u16, ..29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35,
+                                   43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+static LENGTH_EXTRA_BITS: [u8, ..29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3,
+                                        3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+
+/// The shortest distance of a match for each distance symbol, and the number of extra bits which
+/// are added to it.
+static DISTANCE_BASE: [u16, ..30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+                                     257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193,
+                                     12289, 16385, 24577];
+static DISTANCE_EXTRA_BITS: [u8, ..30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8,
+                                          8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// The encoded body, as it is read: a buffer of it, and the bits of the current byte which have
+/// not yet been used. Deflate data is packed starting from the least significant bit.
+struct Input {
+    buf: ~[u8],
+    pos: uint,
+    end: uint,
+    bits: u32,
+    bit_count: uint,
+}
+
+impl Input {
+    fn new() -> Input {
+        Input {
+            buf: slice::from_elem(INPUT_SIZE, 0u8),
+            pos: 0,
+            end: 0,
+            bits: 0,
+            bit_count: 0,
+        }
+    }
+
+    /// Whether everything read so far has been used.
+    fn is_empty(&self) -> bool {
+        self.pos == self.end
+    }
+
+    /// Have at least `len` bytes of the body buffered, reading it as necessary; returning whether
+    /// there are, which there aren't if the body ends first.
+    fn buffer<R: Reader>(&mut self, raw: &mut R, len: uint) -> IoResult<bool> {
+        while self.end - self.pos < len {
+            if self.pos > 0 {
+                // Make room after what is left
+                for i in range(self.pos, self.end) {
+                    self.buf[i - self.pos] = self.buf[i];
+                }
+                self.end -= self.pos;
+                self.pos = 0;
+            }
+            match raw.read(self.buf.mut_slice_from(self.end)) {
+                Ok(read) => self.end += read,
+                Err(ref err) if err.kind == EndOfFile => return Ok(false),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(true)
+    }
+
+    /// The next two bytes, which must be buffered.
+    fn peek2(&self) -> (u8, u8) {
+        (self.buf[self.pos], self.buf[self.pos + 1])
+    }
+
+    /// The next byte, ignoring any bits left of the current one; an `EndOfFile` error if the body
+    /// has ended.
+    fn byte<R: Reader>(&mut self, raw: &mut R) -> IoResult<u8> {
+        if !try!(self.buffer(raw, 1)) {
+            return Err(IoError {
+                kind: EndOfFile,
+                desc: "the encoded body ended too soon",
+                detail: None,
+            });
+        }
+        self.pos += 1;
+        Ok(self.buf[self.pos - 1])
+    }
+
+    /// The next `count` bits (no more than 16), as a number.
+    fn bits<R: Reader>(&mut self, raw: &mut R, count: uint) -> IoResult<uint> {
+        while self.bit_count < count {
+            let byte = try!(self.byte(raw));
+            self.bits |= byte as u32 << self.bit_count;
+            self.bit_count += 8;
+        }
+        let value = self.bits & ((1 << count) - 1);
+        self.bits >>= count;
+        self.bit_count -= count;
+        Ok(value as uint)
+    }
+
+    /// Skip to the next byte boundary. As `bits` reads no more bytes than it needs, the bits left
+    /// are all of the current byte.
+    fn align(&mut self) {
+        self.bits = 0;
+        self.bit_count = 0;
+    }
+}
+
+/// A canonical Huffman code (RFC 1951, §3.2.2), as the number of codes of each length and the
+/// symbols in the order of their codes.
+struct Huffman {
+    counts: [u16, ..16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// A code with no symbols, for want of any other.
+    fn empty() -> Huffman {
+        Huffman {
+            counts: [0u16, ..16],
+            symbols: Vec::new(),
+        }
+    }
+
+    /// The code with the given lengths for the symbols from 0 (a length of zero leaving a symbol
+    /// out), or `None` if there are too many codes of some length for them to be distinct.
+    fn new(lengths: &[u8]) -> Option<Huffman> {
+        let mut counts = [0u16, ..16];
+        for &len in lengths.iter() {
+            counts[len as uint] += 1;
+        }
+        counts[0] = 0;
+        // An incomplete code is allowed, but a code which uses up more than the codes of some
+        // length that there are is not
+        let mut left = 1i;
+        for len in range(1, MAX_CODE_LEN + 1) {
+            left = (left << 1) - counts[len] as int;
+            if left < 0 {
+                return None;
+            }
+        }
+
+        // Where the symbols of each length start
+        let mut offsets = [0u, ..16];
+        for len in range(1, MAX_CODE_LEN) {
+            offsets[len + 1] = offsets[len] + counts[len] as uint;
+        }
+        let mut symbols = Vec::from_elem(lengths.len(), 0u16);
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                *symbols.get_mut(offsets[len as uint]) = symbol as u16;
+                offsets[len as uint] += 1;
+            }
+        }
+        Some(Huffman {
+            counts: counts,
+            symbols: symbols,
+        })
+    }
+
+    /// The code of blocks compressed with fixed Huffman codes for literals and lengths (RFC 1951,
+    /// §3.2.6).
+    fn fixed_literals() -> Huffman {
+        let mut lengths = [8u8, ..288];
+        for len in lengths.mut_slice(144, 256).mut_iter() {
+            *len = 9;
+        }
+        for len in lengths.mut_slice(256, 280).mut_iter() {
+            *len = 7;
+        }
+        Huffman::new(lengths.as_slice()).unwrap()
+    }
+
+    /// The code of blocks compressed with fixed Huffman codes for distances.
+    fn fixed_distances() -> Huffman {
+        Huffman::new([5u8, ..30]).unwrap()
+    }
+
+    /// Decode a symbol, a bit at a time; `None` if the bits are not the code of a symbol.
+    fn decode<R: Reader>(&self, input: &mut Input, raw: &mut R) -> IoResult<Option<uint>> {
+        // The bits read so far, the first code of their length, and the index of its symbol
+        let (mut code, mut first, mut index) = (0u, 0u, 0u);
+        for len in range(1, MAX_CODE_LEN + 1) {
+            code |= try!(input.bits(raw, 1));
+            let count = self.counts[len] as uint;
+            if code < first + count {
+                return Ok(Some(*self.symbols.get(index + code - first) as uint));
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::io::{IoResult, InvalidInput, EndOfFile, MemReader};
+    use std::str;
+    use rfc2616::content_coding::{ValueToken, Gzip, Deflate, Identity};
+    use super::{Decoder, decodable_coding, DEFAULT_MAX_DECODED_LEN};
+
+    static GZIP: &'static [u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xf3, 0x48, 0xcd, 0xc9, 0xc9,
+        0xd7, 0x51, 0x28, 0xcf, 0x2f, 0xca, 0x49, 0x51, 0x04, 0x00, 0xe6, 0xc6, 0xe6, 0xeb, 0x0d,
+        0x00, 0x00, 0x00];
+
+    /// "Hello, world!" in a stored block
+    static GZIP_STORED: &'static [u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x01, 0x0d, 0x00, 0xf2, 0xff,
+        0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x2c, 0x20, 0x77, 0x6f, 0x72, 0x6c, 0x64, 0x21, 0xe6, 0xc6,
+        0xe6, 0xeb, 0x0d, 0x00, 0x00, 0x00];
+
+    static ZLIB: &'static [u8] = &[
+        0x78, 0x9c, 0xf3, 0x48, 0xcd, 0xc9, 0xc9, 0xd7, 0x51, 0x28, 0xcf, 0x2f, 0xca, 0x49, 0x51,
+        0x04, 0x00, 0x20, 0x5e, 0x04, 0x8a];
+
+    static RAW_DEFLATE: &'static [u8] = &[
+        0xf3, 0x48, 0xcd, 0xc9, 0xc9, 0xd7, 0x51, 0x28, 0xcf, 0x2f, 0xca, 0x49, 0x51, 0x04, 0x00];
+
+    /// "Hello, world!" in a stored block
+    static ZLIB_STORED: &'static [u8] = &[
+        0x78, 0x01, 0x01, 0x0d, 0x00, 0xf2, 0xff, 0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x2c, 0x20, 0x77,
+        0x6f, 0x72, 0x6c, 0x64, 0x21, 0x20, 0x5e, 0x04, 0x8a];
+
+    /// `pigs()`, compressed with dynamic Huffman codes
+    static ZLIB_DYNAMIC: &'static [u8] = &[
+        0x78, 0xda, 0x65, 0xd0, 0x4b, 0x0a, 0x02, 0x31, 0x14, 0x05, 0xd1, 0xad, 0xbc, 0x05, 0xf4,
+        0x20, 0x37, 0xff, 0xe0, 0x6a, 0x6c, 0xd4, 0x26, 0x10, 0xb0, 0xc1, 0x06, 0xb7, 0xef, 0xf8,
+        0x95, 0xe3, 0xaa, 0xd1, 0x09, 0xb6, 0xe6, 0x75, 0xad, 0xa7, 0x9d, 0xf3, 0xf8, 0x6c, 0x16,
+        0x6c, 0x9f, 0x87, 0xed, 0xf7, 0x87, 0x7d, 0xdf, 0xeb, 0x75, 0x33, 0xf9, 0xdc, 0x90, 0xa3,
+        0xcf, 0xca, 0xe8, 0xc9, 0xf7, 0x28, 0xf4, 0x8c, 0xde, 0xd1, 0x8b, 0xef, 0xa9, 0xa0, 0x57,
+        0xdf, 0x73, 0x44, 0x6f, 0xe8, 0x03, 0xbd, 0xfb, 0x5e, 0x2a, 0xfa, 0xf0, 0xbd, 0x26, 0xf2,
+        0x04, 0xf8, 0xfc, 0xf9, 0x11, 0x90, 0x82, 0x02, 0x61, 0x27, 0xa1, 0x60, 0x38, 0x68, 0x28,
+        0x20, 0x0e, 0x22, 0x0a, 0x8a, 0x0a, 0x64, 0x14, 0x1c, 0x25, 0x42, 0xaa, 0xf1, 0x20, 0xa5,
+        0x60, 0xa9, 0x48, 0x4c, 0x41, 0x53, 0x89, 0x9c, 0x3f, 0xbf, 0x3c, 0xce, 0xb2];
+
+    fn pigs() -> ~str {
+        let mut text = ~"";
+        for i in range(0u, 20) {
+            text.push_str(format!("{} little pigs, {} big bad wolf; ", i, i * 7));
+        }
+        text
+    }
+
+    /// Decode a whole body with the given content-coding.
+    fn decode(coding: ValueToken, body: ~[u8]) -> IoResult<~[u8]> {
+        let mut decoder = try!(Decoder::new(coding, Some(DEFAULT_MAX_DECODED_LEN)));
+        let mut body = MemReader::new(body);
+        let mut decoded = Vec::new();
+        let mut buf = [0u8, ..4096];
+        loop {
+            match decoder.read(&mut body, buf) {
+                Ok(len) => decoded.push_all(buf.slice_to(len)),
+                Err(ref err) if err.kind == EndOfFile => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(decoded.as_slice().to_owned())
+    }
+
+    /// A reader which gives out a byte at a time, as a body trickling in would.
+    struct Trickle {
+        data: &'static [u8],
+    }
+
+    impl Reader for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+            if self.data.len() == 0 {
+                return Err(io::standard_error(EndOfFile));
+            }
+            buf[0] = self.data[0];
+            self.data = self.data.slice_from(1);
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn test_decodable_coding() {
+        assert_eq!(decodable_coding("gzip"), Some(Gzip));
+        assert_eq!(decodable_coding("X-GZIP"), Some(Gzip));
+        assert_eq!(decodable_coding(" deflate "), Some(Deflate));
+        assert_eq!(decodable_coding("identity"), None);
+        assert_eq!(decodable_coding("br"), None);
+        assert_eq!(decodable_coding("deflate, gzip"), None);
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(Gzip, GZIP.to_owned()).unwrap(), bytes!("Hello, world!").to_owned());
+        assert_eq!(decode(Gzip, GZIP_STORED.to_owned()).unwrap(),
+                   bytes!("Hello, world!").to_owned());
+        assert_eq!(decode(Deflate, ZLIB.to_owned()).unwrap(), bytes!("Hello, world!").to_owned());
+        assert_eq!(decode(Deflate, RAW_DEFLATE.to_owned()).unwrap(),
+                   bytes!("Hello, world!").to_owned());
+        assert_eq!(decode(Deflate, ZLIB_STORED.to_owned()).unwrap(),
+                   bytes!("Hello, world!").to_owned());
+        assert_eq!(decode(Deflate, ZLIB_DYNAMIC.to_owned()).unwrap(), pigs().into_bytes());
+        assert_eq!(decode(Gzip, ~[]).unwrap(), ~[]);
+    }
+
+    #[test]
+    fn test_decode_corrupt() {
+        assert!(decode(Gzip, bytes!("Hello, world!").to_owned()).is_err());
+        assert!(decode(Gzip, GZIP.slice_to(20).to_owned()).is_err());
+        // A wrong length in the trailer
+        let mut gzip = GZIP.to_owned();
+        gzip[29] = 0x0e;
+        assert!(decode(Gzip, gzip).is_err());
+        // A byte of the data changed, which only the CRC-32 shows
+        let mut gzip = GZIP_STORED.to_owned();
+        gzip[15] = 0x4a;
+        assert!(decode(Gzip, gzip).is_err());
+        // A wrong checksum
+        let mut zlib = ZLIB.to_owned();
+        zlib[20] = 0x8b;
+        assert!(decode(Deflate, zlib).is_err());
+    }
+
+    #[test]
+    fn test_decode_identity() {
+        let err = decode(Identity, bytes!("Hello, world!").to_owned()).unwrap_err();
+        assert_eq!(err.kind, InvalidInput);
+    }
+
+    #[test]
+    fn test_decoder_streaming() {
+        // What has been decoded is returned without waiting for the rest
+        let mut decoder = Decoder::new(Deflate, None).unwrap();
+        let mut raw = Trickle { data: ZLIB_DYNAMIC };
+        let mut buf = [0u8, ..64];
+        let mut decoded = Vec::new();
+        let mut reads = 0;
+        loop {
+            match decoder.read(&mut raw, buf) {
+                Ok(len) => decoded.push_all(buf.slice_to(len)),
+                Err(err) => {
+                    assert_eq!(err.kind, EndOfFile);
+                    break;
+                },
+            }
+            reads += 1;
+        }
+        assert_eq!(str::from_utf8(decoded.as_slice()), Some(pigs().as_slice()));
+        assert!(reads > pigs().len() / buf.len());
+    }
+
+    #[test]
+    fn test_decoder_max_len() {
+        let mut decoder = Decoder::new(Gzip, Some(5)).unwrap();
+        let mut raw = MemReader::new(GZIP.to_owned());
+        let mut buf = [0u8, ..64];
+        assert_eq!(decoder.read(&mut raw, buf), Ok(5));
+        assert_eq!(buf.slice_to(5), bytes!("Hello"));
+        assert!(decoder.read(&mut raw, buf).is_err());
+
+        let mut decoder = Decoder::new(Gzip, Some(13)).unwrap();
+        let mut raw = MemReader::new(GZIP.to_owned());
+        assert_eq!(decoder.read(&mut raw, buf), Ok(13));
+        assert_eq!(decoder.read(&mut raw, buf).unwrap_err().kind, EndOfFile);
+    }
+}
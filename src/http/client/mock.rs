@@ -0,0 +1,308 @@
+/*!
+
+A pretend server, for testing HTTP client code without sockets.
+
+A `MockServer` is given the responses to send, one `MockResponse` for each connection made to it,
+and records what the client sends. Requests are made to it with `MockStream` as the `Connecter`:
+
+```rust
+use http::client::RequestWriter;
+use http::client::mock::{MockServer, MockResponse, MockStream};
+use http::method::Get;
+
+let server = MockServer::new();
+server.respond(MockResponse::new("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nHello"));
+
+let request: RequestWriter<MockStream> = RequestWriter::new(Get, server.url("/hi")).unwrap();
+let mut response = match request.read_response() {
+    Ok(response) => response,
+    Err((_request, err)) => fail!("{}", err),
+};
+assert_eq!(response.read_to_end().unwrap(), bytes!("Hello").to_owned());
+assert!(server.requests().get(0).as_slice().starts_with(bytes!("GET /hi HTTP/1.1\r\n")));
+```
+
+A response can also be sent in pieces with pauses between them (which a read timeout will cut
+short), cut off part way, or end in an error such as `ConnectionReset`.
+
+`Connecter::connect` has nothing to go by but the address, so servers are found by address in a
+registry local to the task: a `MockServer` can only be connected to from the task which made it.
+
+*/
+
+use std::cmp::min;
+use std::io;
+use std::io::{IoError, IoErrorKind, IoResult, ConnectionRefused, TimedOut};
+use std::io::net::ip::{SocketAddr, Ipv4Addr};
+use std::io::timer;
+use std::local_data;
+use sync::{Arc, Mutex};
+use url::Url;
+
+use connecter::Connecter;
+
+/// Something for a `MockStream` to do when read from.
+#[deriving(Clone)]
+enum Event {
+    /// Provide these bytes
+    Data(~[u8]),
+    /// Wait this many milliseconds before going on
+    Pause(u64),
+    /// Fail with an error of this kind, and keep failing
+    Fail(IoErrorKind),
+}
+
+/// What a `MockServer` sends on a connection. This is usually a single response, but may be
+/// several (for a persistent connection), or anything else.
+///
+/// Once everything has been sent, the connection is closed; so a response which is shorter than
+/// its headers say is a truncated one, and a `MockResponse` with nothing in it is a connection
+/// closed without a response.
+#[deriving(Clone)]
+pub struct MockResponse {
+    priv events: Vec<Event>,
+}
+
+impl MockResponse {
+    /// Send the given text, typically a complete response.
+    pub fn new(raw: &str) -> MockResponse {
+        let mut response = MockResponse::empty();
+        response.send(raw.as_bytes());
+        response
+    }
+
+    /// Send nothing at all, but close the connection.
+    pub fn empty() -> MockResponse {
+        MockResponse {
+            events: Vec::new(),
+        }
+    }
+
+    /// Send the given bytes after whatever has gone before.
+    pub fn send(&mut self, bytes: &[u8]) {
+        self.events.push(Data(bytes.to_owned()));
+    }
+
+    /// Send the given chunks with the chunked transfer-coding, then the last-chunk and an empty
+    /// trailer. The status line and headers must already have been sent.
+    pub fn send_chunked(&mut self, chunks: &[&str]) {
+        for chunk in chunks.iter() {
+            self.send(format!("{:x}\r\n{}\r\n", chunk.len(), *chunk).as_bytes());
+        }
+        self.send(bytes!("0\r\n\r\n"));
+    }
+
+    /// Wait before sending what comes next. If the client's read timeout is shorter than this, its
+    /// read times out (but the pause continues with the next read).
+    pub fn pause(&mut self, ms: u64) {
+        self.events.push(Pause(ms));
+    }
+
+    /// Instead of closing the connection, fail with an error of the given kind, such as
+    /// `ConnectionReset`.
+    pub fn fail(&mut self, kind: IoErrorKind) {
+        self.events.push(Fail(kind));
+    }
+}
+
+struct ServerState {
+    /// The responses for the connections yet to be made, in order
+    responses: Vec<MockResponse>,
+    /// What has been written on each connection made
+    connections: Vec<Arc<Mutex<Vec<u8>>>>,
+}
+
+/// A pretend server, at an address which only `MockStream` can connect to.
+#[deriving(Clone)]
+pub struct MockServer {
+    priv addr: SocketAddr,
+    priv state: Arc<Mutex<ServerState>>,
+}
+
+local_data_key!(servers: Vec<MockServer>)
+
+impl MockServer {
+    /// A server with no responses to send yet, at an address of its own.
+    pub fn new() -> MockServer {
+        let mut server = None;
+        local_data::modify(servers, |existing| {
+            let mut existing = existing.unwrap_or(Vec::new());
+            let new = MockServer {
+                // TEST-NET-1 (RFC 5737), which is never to be routed anywhere real
+                addr: SocketAddr { ip: Ipv4Addr(192, 0, 2, 1), port: existing.len() as u16 + 1 },
+                state: Arc::new(Mutex::new(ServerState {
+                    responses: Vec::new(),
+                    connections: Vec::new(),
+                })),
+            };
+            server = Some(new.clone());
+            existing.push(new);
+            Some(existing)
+        });
+        server.unwrap()
+    }
+
+    /// The address of the server
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// A URL on the server, with the given path (and query)
+    pub fn url(&self, path: &str) -> Url {
+        from_str(format!("http://{}{}", self.addr, path)).unwrap()
+    }
+
+    /// Add the response to send on the next connection without one; further connections are
+    /// refused.
+    pub fn respond(&self, response: MockResponse) {
+        self.state.lock().responses.push(response);
+    }
+
+    /// What has been sent on each connection so far, in the order they were made
+    pub fn requests(&self) -> Vec<~[u8]> {
+        self.state.lock().connections.iter().map(|written| {
+            written.lock().as_slice().to_owned()
+        }).collect()
+    }
+
+    /// How many connections have been made
+    pub fn connection_count(&self) -> uint {
+        self.state.lock().connections.len()
+    }
+}
+
+/// A connection to a `MockServer`.
+pub struct MockStream {
+    priv events: Vec<Event>,
+    /// How many bytes of the first event, if `Data`, have been read
+    priv offset: uint,
+    priv written: Arc<Mutex<Vec<u8>>>,
+    priv read_timeout_ms: Option<u64>,
+}
+
+fn connection_refused_err(addr: SocketAddr) -> IoError {
+    IoError {
+        kind: ConnectionRefused,
+        desc: "No mock server or response for this address",
+        detail: Some(addr.to_str()),
+    }
+}
+
+impl Connecter for MockStream {
+    fn connect(addr: SocketAddr) -> IoResult<MockStream> {
+        let server = local_data::get(servers, |existing| {
+            existing.and_then(|existing| {
+                existing.iter().find(|server| server.addr == addr).map(|server| server.clone())
+            })
+        });
+        let server = match server {
+            Some(server) => server,
+            None => return Err(connection_refused_err(addr)),
+        };
+        let mut state = server.state.lock();
+        if state.responses.len() == 0 {
+            return Err(connection_refused_err(addr));
+        }
+        let response = state.responses.remove(0).unwrap();
+        let written = Arc::new(Mutex::new(Vec::new()));
+        state.connections.push(written.clone());
+        Ok(MockStream {
+            events: response.events,
+            offset: 0,
+            written: written,
+            read_timeout_ms: None,
+        })
+    }
+
+    fn set_read_timeout(&mut self, timeout_ms: Option<u64>) {
+        self.read_timeout_ms = timeout_ms;
+    }
+}
+
+impl Reader for MockStream {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        loop {
+            if self.events.len() == 0 {
+                return Err(io::standard_error(io::EndOfFile));
+            }
+            match *self.events.get_mut(0) {
+                Data(ref data) if self.offset < data.len() => {
+                    let len = min(buf.len(), data.len() - self.offset);
+                    buf.mut_slice_to(len).copy_from(data.slice(self.offset, self.offset + len));
+                    self.offset += len;
+                    return Ok(len);
+                },
+                Data(_) => (),
+                Pause(ref mut ms) => match self.read_timeout_ms {
+                    Some(timeout_ms) if timeout_ms < *ms => {
+                        timer::sleep(timeout_ms);
+                        *ms -= timeout_ms;
+                        return Err(io::standard_error(TimedOut));
+                    },
+                    _ => timer::sleep(*ms),
+                },
+                Fail(kind) => return Err(io::standard_error(kind)),
+            }
+            self.events.shift();
+            self.offset = 0;
+        }
+    }
+}
+
+impl Writer for MockStream {
+    fn write(&mut self, buf: &[u8]) -> IoResult<()> {
+        self.written.lock().push_all(buf);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{IoResult, ConnectionReset, ConnectionRefused, EndOfFile, TimedOut};
+    use connecter::Connecter;
+    use super::{MockServer, MockResponse, MockStream};
+
+    #[test]
+    fn test_mock_stream() {
+        let server = MockServer::new();
+        let mut response = MockResponse::new("abc");
+        response.pause(20);
+        response.send_chunked(["hello"]);
+        response.fail(ConnectionReset);
+        server.respond(response);
+        server.respond(MockResponse::empty());
+
+        let mut stream: MockStream = Connecter::connect(server.addr()).unwrap();
+        stream.write(bytes!("request")).unwrap();
+        let mut buf = [0u8, ..2];
+        assert_eq!(stream.read(buf), Ok(2));
+        assert_eq!(stream.read(buf), Ok(1));
+        assert_eq!(buf.slice_to(1), bytes!("c"));
+        stream.set_read_timeout(Some(10));
+        assert_eq!(stream.read(buf).unwrap_err().kind, TimedOut);
+        stream.set_read_timeout(None);
+        assert_eq!(stream.read_exact(15).unwrap().as_slice(), bytes!("5\r\nhello\r\n0\r\n\r\n"));
+        assert_eq!(stream.read(buf).unwrap_err().kind, ConnectionReset);
+        assert_eq!(stream.read(buf).unwrap_err().kind, ConnectionReset);
+
+        let mut stream: MockStream = Connecter::connect(server.addr()).unwrap();
+        assert_eq!(stream.read(buf).unwrap_err().kind, EndOfFile);
+        let refused: IoResult<MockStream> = Connecter::connect(server.addr());
+        assert_eq!(refused.unwrap_err().kind, ConnectionRefused);
+
+        assert_eq!(server.connection_count(), 2);
+        assert_eq!(server.requests(), vec!(bytes!("request").to_owned(), ~[]));
+    }
+
+    #[test]
+    fn test_servers_are_separate() {
+        let one = MockServer::new();
+        let two = MockServer::new();
+        assert!(one.addr() != two.addr());
+        two.respond(MockResponse::empty());
+        let refused: IoResult<MockStream> = Connecter::connect(one.addr());
+        assert!(refused.is_err());
+        let connected: IoResult<MockStream> = Connecter::connect(two.addr());
+        assert!(connected.is_ok());
+    }
+}
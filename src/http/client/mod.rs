@@ -2,21 +2,34 @@
 
 Modules for making HTTP requests.
 
-At present, requests must be constructed with `RequestWriter`, which does not expose a particularly
-nice-looking API.
+For most purposes, a `Session` is the thing to use: it makes a request in a single call, with
+default headers, a base URL, credentials, cookies and redirects taken care of, and returns a
+`Response` with the body read in full. It can also keep responses in a `cache::Cache`. The Python
+[Requests](http://python-requests.org/) library has been a heavy influence on it.
 
-In the future there will be a more friendly API for making requests; the Python
-[Requests](http://python-requests.org/) library should be a heavy influence in this.
+Beneath that are `RequestWriter` and `ResponseReader`, which give control of the details of a
+request and stream the bodies.
 
-In the mean time, what there is is not *so* bad.
-
-Oh yeah: don't expect to conveniently make any requests which need to send a request body yet. It's
-possible, but it's not elegant convenient yet. (Most notably, no transfer-encodings are supported.)
+For testing code which makes requests, `mock::MockServer` stands in for a real server.
 
 */
 
 pub use self::request::RequestWriter;
 pub use self::response::ResponseReader;
-
+pub use self::session::Session;
+
+pub mod cache;
+pub mod cookie;
+pub mod decoding;
+pub mod mock;
+pub mod pool;
+pub mod proxy;
+pub mod redirect;
 pub mod request;
 pub mod response;
+pub mod retry;
+pub mod session;
+pub mod text;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,371 @@
+/*!
+
+Reuse of persistent connections between requests.
+
+A `ConnectionPool` holds idle connections, keyed by the `Origin` (scheme, host and port) that they
+are connected to. A `RequestWriter` made with `RequestWriter::new_with_pool` will take an idle
+connection from the pool in preference to opening a new one, and the `ResponseReader` returns the
+connection to the pool once the response body has been read in its entirety (provided that neither
+side has asked for the connection to be closed).
+
+Clones of a pool share the same connections, so a pool can be handed to several tasks.
+
+A pool can also cap the connections in use to each origin (see `set_max_active_per_host`): once a
+request has connected, it holds its place until the response body has been read and the connection
+put back, or the response dropped; a request which would go over the cap waits in `connect` until
+another lets go of its place. By default there is no cap.
+
+```rust
+use http::client::RequestWriter;
+use http::client::pool::ConnectionPool;
+use http::method::Get;
+use std::io::net::tcp::TcpStream;
+
+let pool: ConnectionPool<TcpStream> = ConnectionPool::new();
+for _ in range(0, 100) {
+    let request = RequestWriter::new_with_pool(Get, from_str("http://example.com/").unwrap(),
+                                               pool.clone()).unwrap();
+    let mut response = match request.read_response() {
+        Ok(response) => response,
+        Err((_request, error)) => fail!(":-( {}", error),
+    };
+    // Read the body to the end so that the connection can go back into the pool
+    let _body = response.read_to_end();
+}
+```
+
+*/
+
+use collections::hashmap::HashMap;
+use sync::{Arc, Mutex};
+use time::precise_time_ns;
+use url::Url;
+
+use buffer::BufferedStream;
+
+/// By default, connections which have been idle for 90 seconds are closed.
+pub static DEFAULT_IDLE_TIMEOUT_MS: u64 = 90_000;
+
+/// By default, at most 8 idle connections are kept for each origin.
+pub static DEFAULT_MAX_IDLE_PER_HOST: uint = 8;
+
+/// The scheme, host and port of a URL; connections can be shared between URLs of the same origin.
+#[deriving(Clone, Eq, TotalEq, Hash)]
+pub struct Origin {
+    /// The URL scheme, e.g. "http"
+    scheme: ~str,
+
+    /// The host name, as it was given in the URL
+    host: ~str,
+
+    /// The port, explicit or implied by the scheme
+    port: u16,
+
+    /// The URL of the proxy which connections to the origin go through, if any. Connections
+    /// through one proxy are no use for requests through another, or for those without one, so
+    /// they are pooled apart. `from_url` leaves this `None`.
+    proxy: Option<~str>,
+}
+
+impl Origin {
+    /// Determine the origin of a URL. If the URL does not specify a port, the default port for the
+    /// scheme is used (443 for HTTPS, 80 otherwise).
+    ///
+    /// `None` is returned if the port is not a valid port number.
+    pub fn from_url(url: &Url) -> Option<Origin> {
+        let port = match url.port {
+            Some(ref port) => match from_str::<u16>(*port) {
+                Some(port) => port,
+                None => return None,
+            },
+            None if url.scheme.as_slice() == "https" => 443,
+            None => 80,
+        };
+        Some(Origin {
+            scheme: url.scheme.clone(),
+            host: url.host.clone(),
+            port: port,
+            proxy: None,
+        })
+    }
+}
+
+struct IdleConnection<S> {
+    stream: BufferedStream<S>,
+    /// When the connection was put back into the pool, in nanoseconds (see `precise_time_ns`)
+    since: u64,
+}
+
+struct PoolState<S> {
+    /// Idle connections for each origin, the most recently used last.
+    idle: HashMap<Origin, Vec<IdleConnection<S>>>,
+    idle_timeout_ns: u64,
+    max_idle_per_host: uint,
+}
+
+/// The connections in use (not idle), which are kept apart from the pool's generic state so that
+/// an `ActiveConnection` needn't know the stream type.
+struct ActiveState {
+    /// The number of connections in use to each origin which has any
+    counts: HashMap<Origin, uint>,
+    max_per_host: Option<uint>,
+}
+
+impl<S> PoolState<S> {
+    /// Drop (and thus close) any connections which have been idle for too long.
+    fn evict_expired(&mut self, now: u64) {
+        let idle_timeout_ns = self.idle_timeout_ns;
+        for (_, connections) in self.idle.mut_iter() {
+            // They are in order of last use, so all the expired ones are at the front.
+            let expired = match connections.iter().position(|c| now - c.since < idle_timeout_ns) {
+                Some(n) => n,
+                None => connections.len(),
+            };
+            for _ in range(0, expired) {
+                connections.remove(0);
+            }
+        }
+    }
+}
+
+/// A collection of idle persistent connections, shared between its clones.
+pub struct ConnectionPool<S> {
+    priv state: Arc<Mutex<PoolState<S>>>,
+    priv active: Arc<Mutex<ActiveState>>,
+}
+
+impl<S: Send> ConnectionPool<S> {
+    /// Create a pool with the default idle timeout and per-host limit.
+    pub fn new() -> ConnectionPool<S> {
+        ConnectionPool::with_limits(DEFAULT_IDLE_TIMEOUT_MS, DEFAULT_MAX_IDLE_PER_HOST)
+    }
+
+    /// Create a pool which closes connections after they have been idle for `idle_timeout_ms`
+    /// milliseconds, and which keeps at most `max_idle_per_host` idle connections for each origin.
+    /// (Connections returned to the pool beyond that number cause the least recently used to be
+    /// closed.)
+    pub fn with_limits(idle_timeout_ms: u64, max_idle_per_host: uint) -> ConnectionPool<S> {
+        ConnectionPool {
+            state: Arc::new(Mutex::new(PoolState {
+                idle: HashMap::new(),
+                idle_timeout_ns: idle_timeout_ms * 1_000_000,
+                max_idle_per_host: max_idle_per_host,
+            })),
+            active: Arc::new(Mutex::new(ActiveState {
+                counts: HashMap::new(),
+                max_per_host: None,
+            })),
+        }
+    }
+
+    /// Cap the connections in use to each origin at `max`, or with `None`, remove the cap. This
+    /// applies to all clones of the pool.
+    ///
+    /// A task which goes over the cap itself, holding on to that many responses whose bodies it
+    /// has not finished with and then making another request, will wait forever.
+    pub fn set_max_active_per_host(&self, max: Option<uint>) {
+        let mut active = self.active.lock();
+        active.max_per_host = max;
+        active.cond.broadcast();
+    }
+
+    /// Take a place among the connections in use to the given origin, waiting for one if the cap
+    /// has been reached, along with an idle connection from the pool if there is one. The place is
+    /// held until the `ActiveConnection` is dropped.
+    pub fn acquire(&self, origin: &Origin) -> (ActiveConnection, Option<BufferedStream<S>>) {
+        {
+            let mut active = self.active.lock();
+            loop {
+                let count = active.counts.find(origin).map_or(0, |n| *n);
+                match active.max_per_host {
+                    Some(max) if count >= max => active.cond.wait(),
+                    _ => break,
+                }
+            }
+            *active.counts.find_or_insert(origin.clone(), 0) += 1;
+        }
+        let mut state = self.state.lock();
+        state.evict_expired(precise_time_ns());
+        let stream = match state.idle.find_mut(origin) {
+            Some(connections) => connections.pop().map(|c| c.stream),
+            None => None,
+        };
+        let active = ActiveConnection {
+            active: self.active.clone(),
+            origin: origin.clone(),
+        };
+        (active, stream)
+    }
+
+    /// Take an idle connection to the given origin out of the pool, if there is one.
+    pub fn take(&self, origin: &Origin) -> Option<BufferedStream<S>> {
+        let mut state = self.state.lock();
+        state.evict_expired(precise_time_ns());
+        match state.idle.find_mut(origin) {
+            Some(connections) => connections.pop().map(|c| c.stream),
+            None => None,
+        }
+    }
+
+    /// Put a connection to the given origin back into the pool.
+    ///
+    /// The connection must be ready for a new request to be written to it; that is, the response
+    /// to the previous request must have been read entirely.
+    pub fn put(&self, origin: Origin, stream: BufferedStream<S>) {
+        let now = precise_time_ns();
+        let mut state = self.state.lock();
+        state.evict_expired(now);
+        let max_idle_per_host = state.max_idle_per_host;
+        if max_idle_per_host == 0 {
+            return;
+        }
+        let connections = state.idle.find_or_insert_with(origin, |_| Vec::new());
+        if connections.len() >= max_idle_per_host {
+            connections.remove(0);
+        }
+        connections.push(IdleConnection {
+            stream: stream,
+            since: now,
+        });
+    }
+
+    /// The number of connections in use to the given origin.
+    pub fn active_count(&self, origin: &Origin) -> uint {
+        self.active.lock().counts.find(origin).map_or(0, |n| *n)
+    }
+
+    /// The number of idle connections to the given origin currently in the pool.
+    pub fn idle_count(&self, origin: &Origin) -> uint {
+        let mut state = self.state.lock();
+        state.evict_expired(precise_time_ns());
+        match state.idle.find(origin) {
+            Some(connections) => connections.len(),
+            None => 0,
+        }
+    }
+
+    /// Close all the idle connections in the pool.
+    pub fn clear(&self) {
+        self.state.lock().idle.clear();
+    }
+}
+
+/// A place among the connections in use to an origin, given up when this is dropped.
+pub struct ActiveConnection {
+    priv active: Arc<Mutex<ActiveState>>,
+    priv origin: Origin,
+}
+
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        let mut active = self.active.lock();
+        let none_left = match active.counts.find_mut(&self.origin) {
+            Some(n) => {
+                *n -= 1;
+                *n == 0
+            },
+            None => false,
+        };
+        if none_left {
+            active.counts.remove(&self.origin);
+        }
+        active.cond.broadcast();
+    }
+}
+
+impl<S: Send> Clone for ConnectionPool<S> {
+    fn clone(&self) -> ConnectionPool<S> {
+        ConnectionPool {
+            state: self.state.clone(),
+            active: self.active.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::timer;
+    use buffer::BufferedStream;
+    use memstream::MemReaderFakeStream;
+    use super::{ConnectionPool, Origin};
+
+    fn origin(url: &str) -> Origin {
+        Origin::from_url(&from_str(url).unwrap()).unwrap()
+    }
+
+    fn stream() -> BufferedStream<MemReaderFakeStream> {
+        BufferedStream::new(MemReaderFakeStream::new(~[]))
+    }
+
+    #[test]
+    fn test_origin() {
+        assert_eq!(origin("http://example.com/foo"), origin("http://example.com:80/bar?baz"));
+        assert_eq!(origin("https://example.com/"), origin("https://example.com:443/"));
+        assert!(origin("http://example.com/") != origin("https://example.com/"));
+        assert!(origin("http://example.com/") != origin("http://example.com:8080/"));
+        assert!(origin("http://example.com/") != origin("http://example.org/"));
+        assert_eq!(Origin::from_url(&from_str("http://example.com:http/").unwrap()), None);
+    }
+
+    #[test]
+    fn test_take_and_put() {
+        let pool = ConnectionPool::new();
+        let a = origin("http://a.example/");
+        let b = origin("http://b.example/");
+        assert!(pool.take(&a).is_none());
+        pool.put(a.clone(), stream());
+        assert_eq!(pool.idle_count(&a), 1);
+        assert!(pool.clone().take(&b).is_none());
+        assert!(pool.clone().take(&a).is_some());
+        assert!(pool.take(&a).is_none());
+    }
+
+    #[test]
+    fn test_max_idle_per_host() {
+        let pool = ConnectionPool::with_limits(60_000, 2);
+        let a = origin("http://a.example/");
+        pool.put(a.clone(), stream());
+        pool.put(a.clone(), stream());
+        pool.put(a.clone(), stream());
+        assert_eq!(pool.idle_count(&a), 2);
+
+        let pool = ConnectionPool::with_limits(60_000, 0);
+        pool.put(a.clone(), stream());
+        assert_eq!(pool.idle_count(&a), 0);
+    }
+
+    #[test]
+    fn test_max_active_per_host() {
+        let pool = ConnectionPool::new();
+        pool.set_max_active_per_host(Some(1));
+        let a = origin("http://a.example/");
+        let (first, idle) = pool.acquire(&a);
+        assert!(idle.is_none());
+        assert_eq!(pool.active_count(&a), 1);
+        // Another origin has a place of its own
+        let _other = pool.acquire(&origin("http://b.example/"));
+
+        let (sender, receiver) = channel();
+        let waiting_pool = pool.clone();
+        let waiting_a = a.clone();
+        spawn(proc() {
+            let (_second, idle) = waiting_pool.acquire(&waiting_a);
+            sender.send(idle.is_some());
+        });
+        timer::sleep(50);
+        assert_eq!(pool.active_count(&a), 1);
+
+        // Once the first connection is back in the pool, the waiting task gets it
+        pool.put(a.clone(), stream());
+        drop(first);
+        assert!(receiver.recv());
+    }
+
+    #[test]
+    fn test_idle_timeout() {
+        let pool = ConnectionPool::with_limits(0, 2);
+        let a = origin("http://a.example/");
+        pool.put(a.clone(), stream());
+        assert!(pool.take(&a).is_none());
+    }
+}
@@ -19,8 +19,8 @@ fn main() {
 }
 ```
 
-If you wish to send a request body (e.g. POST requests), I'm sorry to have to tell you that there is
-not *good* support for this yet. However, it can be done; here is an example:
+If you wish to send a request body (e.g. POST requests), set the Content-Length header and write
+the body before reading the response:
 
 ```rust
 let data: ~[u8];
@@ -34,19 +34,49 @@ let response = match request.read_response() {
 };
 ```
 
+A server listening on a Unix domain socket is reached with an `http+unix` URL, whose host is the
+percent-encoded path of the socket (`http+unix://%2Fvar%2Frun%2Fapp.sock/status`), and a
+`Connecter` which can connect to one, such as `UnixStream`; or see `RequestWriter::new_unix`.
+
+If you don't know the length of the body in advance, use the chunked transfer-coding instead; the
+body is then sent in chunks as it is written, followed by any trailers when the response is read:
+
+```rust
+let mut request: RequestWriter;
+
+request.headers.transfer_encoding = Some(vec!(Chunked));
+for line in lines {
+    request.write(line.as_bytes());
+}
+request.trailers.content_md5 = Some(md5);
+let response = request.read_response();
+```
+
 */
 use url;
 use url::Url;
 use method::Method;
-use std::io::{IoError, IoResult};
-use std::io::net::get_host_addresses;
-use std::io::net::ip::{SocketAddr, Ipv4Addr};
+use std::io::{IoError, IoResult, InvalidInput, OtherIoError, TimedOut};
+use std::io::net::ip::{SocketAddr, IpAddr, Ipv4Addr, Ipv6Addr};
+use time::precise_time_ns;
+use sync::{Arc, Mutex};
 use buffer::BufferedStream;
+use common::strip_brackets;
 use headers::request::HeaderCollection;
+use headers::transfer_encoding;
 use headers::host::Host;
-use connecter::Connecter;
+use headers::connection::{Connection, Close, Token};
+use connecter::{Connecter, Resolver, SharedResolver, SystemResolver};
+use error::{HttpError, Io, Timeout, ConnectTimeout, ReadTimeout, TotalTimeout};
 
+use client::response;
 use client::response::ResponseReader;
+use status;
+use client::pool::{ConnectionPool, ActiveConnection, Origin};
+use client::cookie::CookieJar;
+use client::decoding;
+use client::proxy;
+use client::proxy::Proxy;
 
 /*impl ResponseReader {
     {
@@ -70,9 +100,14 @@ pub struct RequestWriter<S> {
     priv stream: Option<BufferedStream<S>>,
     priv headers_written: bool,
 
-    /// The originating IP address of the request.
+    /// The address that the request is sent to: once connected, the address connected to, and
+    /// before that, the first of `remote_addrs`.
     remote_addr: Option<SocketAddr>,
 
+    /// The addresses that the host name resolved to, in the order in which connecting to them is
+    /// tried.
+    remote_addrs: Vec<SocketAddr>,
+
     /// The host name and IP address that the request was sent to; this must always be specified for
     /// HTTP/1.1 requests (or the request will be rejected), but for HTTP/1.0 requests the Host
     /// header was not defined, and so this field will probably be None in such cases.
@@ -81,11 +116,100 @@ pub struct RequestWriter<S> {
     /// The headers sent with the request.
     headers: ~HeaderCollection,
 
+    /// The trailer headers to send after the body, when the chunked transfer-coding is in use (see
+    /// the Transfer-Encoding header). They may be set at any time before the response is read.
+    trailers: ~HeaderCollection,
+
     /// The HTTP method for the request.
     method: Method,
 
     /// The URL being requested.
     url: Url,
+
+    /// The HTTP version to speak; `(1, 1)` unless you set it to `(1, 0)` to force HTTP/1.0. No
+    /// other versions are supported.
+    version: (uint, uint),
+
+    /// Whether the connection is to be closed once the response has been received.
+    ///
+    /// This determines whether `Connection: close` (HTTP/1.1) or `Connection: Keep-Alive`
+    /// (HTTP/1.0) is sent; it is only ever sent where it differs from the version's default.
+    ///
+    /// This is `false` for requests made with a connection pool and `true` otherwise.
+    close_connection: bool,
+
+    /// The pool from which to take an idle connection, and to which the connection will be
+    /// returned when the response has been read.
+    pool: Option<ConnectionPool<S>>,
+
+    /// The connection's place among those in use to its origin, if it was made with a pool (see
+    /// `ConnectionPool::set_max_active_per_host`)
+    priv active: Option<ActiveConnection>,
+
+    /// The cookie jar from which the Cookie header is filled in when the headers are written, and
+    /// in which any cookies set by the response will be stored.
+    cookie_jar: Option<CookieJar>,
+
+    /// Whether to ask for the response body to be compressed, and decompress it when reading. If
+    /// this is set, `Accept-Encoding: gzip, deflate` is sent (unless the header has been set
+    /// explicitly), and a response body with either content-coding is decoded by the
+    /// `ResponseReader`; see `client::decoding`.
+    decode_content: bool,
+
+    /// The most bytes to which a response body may decode, when it is decoded; any more is an
+    /// error. This is `Some(decoding::DEFAULT_MAX_DECODED_LEN)` by default.
+    max_decoded_len: Option<uint>,
+
+    /// Whether a `RetryPolicy` may repeat the request although its method is not idempotent, as
+    /// for a POST which the server is known to handle idempotently.
+    retryable: bool,
+
+    /// How long to wait for a connection to be established, in milliseconds; if `None`, as long as
+    /// the operating system is willing to.
+    connect_timeout_ms: Option<u64>,
+
+    /// How long to wait for each read from the connection, in milliseconds; if `None`,
+    /// indefinitely. (The status line and headers of the response are one read in this respect.)
+    read_timeout_ms: Option<u64>,
+
+    /// How long the whole exchange may take, from connecting to reading the end of the response
+    /// body, in milliseconds; if `None`, there is no limit.
+    total_timeout_ms: Option<u64>,
+
+    /// When connecting began, for `total_timeout_ms` (in nanoseconds; see `precise_time_ns`)
+    priv started_at: Option<u64>,
+
+    /// The proxy through which the request is sent, if any.
+    proxy: Option<Proxy>,
+
+    /// The resolver with which host names are looked up; if `None`, the system's.
+    priv resolver: Option<SharedResolver>,
+
+    /// The Unix domain socket to connect to instead of the host of the URL, if any.
+    unix_socket: Option<Path>,
+
+    /// Whether anything has been received in response to the request; shared with the copies
+    /// made by `clone_unsent` and the requests made by `follow_up`, where it is about whichever of
+    /// them connected last (see `response_began`)
+    priv response_began: Arc<Mutex<bool>>,
+
+    /// What went wrong, if reading the response (or its body) failed. This says more than the
+    /// `IoError` which was returned: which part of the response was malformed, for instance.
+    error: Option<HttpError>,
+}
+
+/// The URL scheme for HTTP over a Unix domain socket, whose host is the percent-encoded path of
+/// the socket.
+pub static UNIX_SCHEME: &'static str = "http+unix";
+
+/// What came of waiting for a 100 (Continue) response; see `RequestWriter.wait_for_continue`.
+pub enum Continuation<S> {
+    /// The server wants the body (or didn't say otherwise in time); write it, then read the
+    /// response as usual.
+    SendBody(RequestWriter<S>),
+
+    /// The server has responded without waiting for the body, which it does not want.
+    Responded(ResponseReader<S>),
 }
 
 /// Low-level HTTP request writing support
@@ -93,63 +217,229 @@ pub struct RequestWriter<S> {
 /// Moderately hacky, and due to current limitations in the TcpStream arrangement reading cannot
 /// take place until writing is completed.
 ///
-/// At present, this only supports making one request per connection.
+/// Each request is made on a new connection unless a `ConnectionPool` is used; see
+/// `RequestWriter::new_with_pool`.
 impl<S: Reader + Writer> RequestWriter<S> {
     /// Create a `RequestWriter` writing to the specified location
     pub fn new(method: Method, url: Url) -> IoResult<RequestWriter<S>> {
-        let host = match url.port {
-            None => Host {
-                name: url.host.to_owned(),
-                port: None,
-            },
-            Some(ref p) => Host {
-                name: url.host.to_owned(),
-                port: Some(from_str(*p).expect("You didn’t aught to give a bad port!")),
-                // TODO: fix extra::url to use u16 rather than ~str
-            },
-        };
-
-        let remote_addr = try!(url_to_socket_addr(&url));
-        info!("using ip address {} for {}", remote_addr.to_str(), url.host);
+        RequestWriter::new_with_proxy(method, url, None)
+    }
 
-        fn url_to_socket_addr(url: &Url) -> IoResult<SocketAddr> {
-            // Just grab the first IPv4 address
-            let addrs = try!(get_host_addresses(url.host));
-            let addr = addrs.move_iter().find(|&a| {
-                match a {
-                    Ipv4Addr(..) => true,
-                    _ => false
-                }
-            });
+    /// Create a `RequestWriter` which sends the request through the given proxy, if there is one.
+    /// See `client::proxy`.
+    pub fn new_with_proxy(method: Method, url: Url, proxy: Option<Proxy>)
+            -> IoResult<RequestWriter<S>> {
+        RequestWriter::new_resolving(method, url, proxy, None)
+    }
 
-            // TODO: Error handling
-            let addr = addr.unwrap();
+    /// Create a `RequestWriter` which looks up host names with the given resolver rather than the
+    /// system's (see `connecter::Resolver`), sending the request through the given proxy if there
+    /// is one. Requests following up on this one (e.g. for redirects) use the same resolver.
+    pub fn new_with_resolver(method: Method, url: Url, proxy: Option<Proxy>,
+                             resolver: SharedResolver) -> IoResult<RequestWriter<S>> {
+        RequestWriter::new_resolving(method, url, proxy, Some(resolver))
+    }
 
-            let port = url.port.clone().unwrap_or(~"80");
-            let port = from_str(port);
-            // TODO: Error handling
-            let port = port.unwrap();
+    /// Create a `RequestWriter` which connects to the Unix domain socket at `socket` rather than to
+    /// the host of the URL, which is sent only in the Host header. Connections are pooled by
+    /// socket rather than by the origin of the URL.
+    pub fn new_unix(method: Method, url: Url, socket: Path) -> IoResult<RequestWriter<S>> {
+        let host = Host {
+            name: strip_brackets(url.host).to_owned(),
+            port: try!(url_port(&url)),
+        };
+        let mut request = RequestWriter::unconnected(method, url, host, Vec::new(), None, None);
+        request.unix_socket = Some(socket);
+        Ok(request)
+    }
 
-            Ok(SocketAddr {
-                ip: addr,
-                port: port
-            })
+    fn new_resolving(method: Method, url: Url, proxy: Option<Proxy>,
+                     resolver: Option<SharedResolver>) -> IoResult<RequestWriter<S>> {
+        if url.scheme.as_slice() == UNIX_SCHEME {
+            let socket = Path::new(url::decode_component(url.host));
+            let mut request = try!(RequestWriter::new_unix(method, url, socket));
+            // The host is the socket's path, which means nothing to the server
+            request.headers.host = Some(Host { name: ~"localhost", port: None });
+            return Ok(request);
         }
 
+        let host = Host {
+            name: strip_brackets(url.host).to_owned(),
+            port: try!(url_port(&url)),
+        };
+
+        // If there is a proxy, that is what we connect to
+        let remote_addrs = {
+            let connect_to = match proxy {
+                Some(ref proxy) => &proxy.url,
+                None => &url,
+            };
+            let port = try!(url_port(connect_to)).unwrap_or(default_port(connect_to));
+            try!(url_to_socket_addrs(connect_to, port, &resolver))
+        };
+        info!("using ip addresses {:?} for {}", remote_addrs.as_slice(), url.host);
+        Ok(RequestWriter::unconnected(method, url, host, remote_addrs, proxy, resolver))
+    }
+
+    fn unconnected(method: Method, url: Url, host: Host, remote_addrs: Vec<SocketAddr>,
+                   proxy: Option<Proxy>, resolver: Option<SharedResolver>) -> RequestWriter<S> {
         let mut request = RequestWriter {
             stream: None,
             headers_written: false,
-            remote_addr: Some(remote_addr),
+            remote_addr: remote_addrs.as_slice().head().map(|addr| *addr),
+            remote_addrs: remote_addrs,
             headers: ~HeaderCollection::new(),
+            trailers: ~HeaderCollection::new(),
             method: method,
             url: url,
+            version: (1, 1),
+            // Without a pool, nothing could make use of the connection afterwards
+            close_connection: true,
+            pool: None,
+            active: None,
+            cookie_jar: None,
+            decode_content: false,
+            max_decoded_len: Some(decoding::DEFAULT_MAX_DECODED_LEN),
+            retryable: false,
+            connect_timeout_ms: None,
+            read_timeout_ms: None,
+            total_timeout_ms: None,
+            started_at: None,
+            proxy: proxy,
+            resolver: resolver,
+            unix_socket: None,
+            response_began: Arc::new(Mutex::new(false)),
+            error: None,
         };
         request.headers.host = Some(host);
+        request
+    }
+
+    /// The origin whose pooled connections the request can use: that of the URL (with the proxy,
+    /// if the request goes through one), or for a request made with `new_unix`, one standing for
+    /// the socket.
+    pub fn origin(&self) -> Option<Origin> {
+        match self.unix_socket {
+            Some(ref socket) if self.url.scheme.as_slice() != UNIX_SCHEME => Some(Origin {
+                scheme: UNIX_SCHEME.to_owned(),
+                host: socket.display().to_str(),
+                port: 0,
+                proxy: None,
+            }),
+            _ => Origin::from_url(&self.url).map(|mut origin| {
+                origin.proxy = self.proxy.as_ref().map(|proxy| proxy.url.to_str());
+                origin
+            }),
+        }
+    }
+
+    /// Create a `RequestWriter` which will reuse an idle connection from the pool if there is one,
+    /// and which will keep the connection open and return it to the pool afterwards.
+    pub fn new_with_pool(method: Method, url: Url, pool: ConnectionPool<S>)
+            -> IoResult<RequestWriter<S>> {
+        let mut request = try!(RequestWriter::new(method, url));
+        request.pool = Some(pool);
+        request.close_connection = false;
         Ok(request)
     }
 }
 
-impl<S: Connecter + Reader + Writer> RequestWriter<S> {
+impl<S: Connecter + Reader + Writer + Send> RequestWriter<S> {
+
+    /// Create a new request to the given URL with the same headers (other than Host, which is set
+    /// for the new URL) and connection options as this one. This is for such things as following
+    /// redirects; the body, of course, is not copied. If there is a cookie jar, the Cookie header
+    /// is not copied either, but filled in afresh from the jar for the new URL.
+    pub fn follow_up(&self, method: Method, url: Url) -> IoResult<RequestWriter<S>> {
+        // A request made with `new_unix` keeps to its socket for URLs of the same origin
+        let same_socket = match self.unix_socket {
+            Some(ref socket) if self.url.scheme.as_slice() != UNIX_SCHEME &&
+                                Origin::from_url(&url) == Origin::from_url(&self.url) => {
+                Some(socket.clone())
+            },
+            _ => None,
+        };
+        let mut request = match same_socket {
+            Some(socket) => try!(RequestWriter::new_unix(method, url, socket)),
+            None => try!(RequestWriter::new_resolving(method, url, self.proxy.clone(),
+                                                      self.resolver.clone())),
+        };
+        let host = request.headers.host.take();
+        request.headers = self.headers.clone();
+        request.headers.host = host;
+        request.version = self.version;
+        request.close_connection = self.close_connection;
+        request.pool = self.pool.clone();
+        request.cookie_jar = self.cookie_jar.clone();
+        request.decode_content = self.decode_content;
+        request.max_decoded_len = self.max_decoded_len;
+        request.retryable = self.retryable;
+        request.connect_timeout_ms = self.connect_timeout_ms;
+        request.read_timeout_ms = self.read_timeout_ms;
+        request.total_timeout_ms = self.total_timeout_ms;
+        // One exchange, as far as the total timeout is concerned
+        request.started_at = self.started_at;
+        request.response_began = self.response_began.clone();
+        if request.cookie_jar.is_some() {
+            // That was for the old URL; the jar knows what to send to the new one.
+            request.headers.cookie = None;
+        }
+        Ok(request)
+    }
+
+    /// A copy of the request, which must not yet have connected, to send once this has been sent
+    /// (as a `RetryPolicy` does). Unlike `follow_up`, this does not look the host name up again.
+    pub fn clone_unsent(&self) -> RequestWriter<S> {
+        if self.stream.is_some() {
+            fail!("RequestWriter.clone_unsent() called after connecting");
+        }
+        RequestWriter {
+            stream: None,
+            headers_written: false,
+            remote_addr: self.remote_addr,
+            remote_addrs: self.remote_addrs.clone(),
+            headers: self.headers.clone(),
+            trailers: self.trailers.clone(),
+            method: self.method.clone(),
+            url: self.url.clone(),
+            version: self.version,
+            close_connection: self.close_connection,
+            pool: self.pool.clone(),
+            active: None,
+            cookie_jar: self.cookie_jar.clone(),
+            decode_content: self.decode_content,
+            max_decoded_len: self.max_decoded_len,
+            retryable: self.retryable,
+            connect_timeout_ms: self.connect_timeout_ms,
+            read_timeout_ms: self.read_timeout_ms,
+            total_timeout_ms: self.total_timeout_ms,
+            started_at: None,
+            proxy: self.proxy.clone(),
+            resolver: self.resolver.clone(),
+            unix_socket: self.unix_socket.clone(),
+            response_began: self.response_began.clone(),
+            error: None,
+        }
+    }
+
+    /// Whether anything was received in response to the request, or to whichever of its copies
+    /// (see `clone_unsent`) and follow-ups connected last. A request which failed before that can
+    /// be sent again without fear that the server acted on it and then failed to respond.
+    pub fn response_began(&self) -> bool {
+        *self.response_began.lock()
+    }
+
+    /// Note that the response has begun to be received; `ResponseReader::construct` does this.
+    pub fn set_response_began(&self) {
+        *self.response_began.lock() = true;
+    }
+
+    /// Stop counting the connection as in use to its origin (see
+    /// `ConnectionPool::set_max_active_per_host`). The `ResponseReader` does this when it returns
+    /// the connection to the pool.
+    pub fn release_active(&mut self) {
+        self.active = None;
+    }
 
     /// Connect to the remote host if not already connected.
     pub fn try_connect(&mut self) -> IoResult<()> {
@@ -166,17 +456,155 @@ impl<S: Connecter + Reader + Writer> RequestWriter<S> {
         if !self.stream.is_none() {
             fail!("I don't think you meant to call connect() twice, you know.");
         }
+        if self.started_at.is_none() {
+            self.started_at = Some(precise_time_ns());
+        }
+        *self.response_began.lock() = false;
 
-        self.stream = match self.remote_addr {
-            Some(addr) => {
-                let stream = try!(Connecter::connect(addr));
-                Some(BufferedStream::new(stream))
+        let (active, pooled) = match (&self.pool, self.origin()) {
+            (&Some(ref pool), Some(ref origin)) => {
+                let (active, pooled) = pool.acquire(origin);
+                (Some(active), pooled)
             },
-            None => fail!("connect() called before remote_addr was set"),
+            _ => (None, None),
         };
+        self.active = active;
+        if pooled.is_some() {
+            debug!("reusing pooled connection for {}", self.url.to_str());
+            self.stream = pooled;
+            return Ok(());
+        }
+
+        match self.unix_socket.clone() {
+            Some(socket) => {
+                try!(self.time_remaining_ms());
+                let stream = try!(Connecter::connect_unix(&socket));
+                self.stream = Some(BufferedStream::new(stream));
+                return Ok(());
+            },
+            None => (),
+        }
+
+        // Try each address in turn, so that one which is unreachable doesn't sink the request
+        let mut last_err = None;
+        for &addr in self.remote_addrs.clone().iter() {
+            let timeout_ms = lesser_timeout(self.connect_timeout_ms,
+                                            try!(self.time_remaining_ms()));
+            let connected = match timeout_ms {
+                Some(timeout_ms) => match Connecter::connect_timeout(addr, timeout_ms) {
+                    Err(ref err) if err.kind == TimedOut => {
+                        // If it was the total timeout which ran out, say so
+                        try!(self.time_remaining_ms());
+                        Err(Timeout(ConnectTimeout).to_io_error())
+                    },
+                    result => result,
+                },
+                None => Connecter::connect(addr),
+            };
+            match connected {
+                Ok(stream) => {
+                    self.remote_addr = Some(addr);
+                    self.stream = Some(BufferedStream::new(stream));
+                    return self.establish_tunnel();
+                },
+                Err(err) => {
+                    debug!("failed to connect to {} for {}: {}", addr, self.url.to_str(), err);
+                    last_err = Some(err);
+                },
+            }
+        }
+        match last_err {
+            Some(err) => Err(err),
+            None => fail!("connect() called with no addresses to connect to"),
+        }
+    }
+
+    /// If the request is to go through a tunnel via the proxy, establish it on the new connection.
+    fn establish_tunnel(&mut self) -> IoResult<()> {
+        let authorization = match self.proxy {
+            Some(ref proxy) if Proxy::tunnels(&self.url) => {
+                // Credentials for the proxy are for the proxy, not for sending through the tunnel
+                match self.headers.proxy_authorization.take() {
+                    Some(authorization) => Some(authorization),
+                    None => proxy.authorization.clone(),
+                }
+            },
+            _ => return Ok(()),
+        };
+        let authority = Host {
+            name: strip_brackets(self.url.host).to_owned(),
+            port: Some(try!(url_port(&self.url)).unwrap_or(default_port(&self.url))),
+        }.to_str();
+        let timeout_ms = try!(self.read_timeout_ms_now());
+        let result = {
+            let stream = self.stream.get_mut_ref();
+            stream.wrapped.set_read_timeout(timeout_ms);
+            proxy::connect_tunnel(stream, authority,
+                                  authorization.as_ref().map(|a| a.as_slice()))
+        };
+        result.map_err(|err| self.read_error(err))
+    }
+
+    /// The time left before `total_timeout_ms` expires, in milliseconds, or `None` if there is no
+    /// total timeout (or the request has not yet begun). If it has expired, that is an error.
+    pub fn time_remaining_ms(&self) -> IoResult<Option<u64>> {
+        match (self.total_timeout_ms, self.started_at) {
+            (Some(total_ms), Some(started_at)) => {
+                let elapsed_ms = (precise_time_ns() - started_at) / 1_000_000;
+                if elapsed_ms >= total_ms {
+                    Err(Timeout(TotalTimeout).to_io_error())
+                } else {
+                    Ok(Some(total_ms - elapsed_ms))
+                }
+            },
+            _ => Ok(None),
+        }
+    }
+
+    /// Set the read timeout of the connection for the next read from it: the lesser of
+    /// `read_timeout_ms` and the time remaining before `total_timeout_ms` expires.
+    pub fn set_read_timeout_for(&self, stream: &mut BufferedStream<S>) -> IoResult<()> {
+        stream.wrapped.set_read_timeout(try!(self.read_timeout_ms_now()));
         Ok(())
     }
 
+    /// The timeout for a read starting now, as for `set_read_timeout_for`.
+    fn read_timeout_ms_now(&self) -> IoResult<Option<u64>> {
+        Ok(lesser_timeout(self.read_timeout_ms, try!(self.time_remaining_ms())))
+    }
+
+    /// Turn a `TimedOut` error from reading the response into one saying which timeout expired.
+    /// Other errors are returned unchanged.
+    pub fn read_error(&self, err: IoError) -> IoError {
+        self.read_http_error(err).to_io_error()
+    }
+
+    /// As `read_error`, but as an `HttpError`: `Timeout` for a `TimedOut` error, and `Io` for any
+    /// other.
+    pub fn read_http_error(&self, err: IoError) -> HttpError {
+        if err.kind != TimedOut {
+            return Io(err);
+        }
+        match self.time_remaining_ms() {
+            Err(_) => Timeout(TotalTimeout),
+            Ok(_) => Timeout(ReadTimeout),
+        }
+    }
+
+    /// Keep `err` as the `error` with which reading the response failed, returning it as an
+    /// `IoError`.
+    pub fn response_error(&mut self, err: HttpError) -> IoError {
+        let io_err = err.to_io_error();
+        self.error = Some(err);
+        io_err
+    }
+
+    /// Whether the Request-Line and headers have been written, after which the request (with any
+    /// body written so far) cannot be sent again.
+    pub fn headers_written(&self) -> bool {
+        self.headers_written
+    }
+
     /// Write the Request-Line and headers of the response, if we have not already done so.
     pub fn try_write_headers(&mut self) -> IoResult<()> {
         if !self.headers_written {
@@ -194,24 +622,184 @@ impl<S: Connecter + Reader + Writer> RequestWriter<S> {
         if self.headers_written {
             fail!("RequestWriter.write_headers() called, but headers already written");
         }
+        let (major, minor) = self.version;
+        let persistent_by_default = match (major, minor) {
+            (1, 0) => false,
+            (1, 1) => true,
+            _ => return Err(unsupported_version_err(major, minor)),
+        };
         if self.stream.is_none() {
             try!(self.connect());
         }
 
+        // Persistent connections are the default in HTTP/1.1 but not in HTTP/1.0 (RFC2616 §8.1),
+        // so the Connection header need only be sent to say otherwise.
+        if self.close_connection == persistent_by_default {
+            let option = if self.close_connection { Close } else { Token(~"Keep-Alive") };
+            add_connection_option(&mut *self.headers, option);
+        }
+
+        // The chunked transfer-coding is only for HTTP/1.1, and takes the place of Content-Length
+        let chunked = self.is_chunked();
+        if chunked {
+            if !persistent_by_default {
+                return Err(chunked_needs_http_1_1_err());
+            }
+            self.headers.content_length = None;
+        }
+
+        // Cookies from the jar go after any which were set explicitly
+        let jar_cookies = match self.cookie_jar {
+            Some(ref jar) => jar.cookie_header(&self.url),
+            None => None,
+        };
+        match (self.headers.cookie.take(), jar_cookies) {
+            (Some(cookies), Some(jar_cookies)) =>
+                self.headers.cookie = Some(format!("{}; {}", cookies, jar_cookies)),
+            (cookies, None) => self.headers.cookie = cookies,
+            (None, jar_cookies) => self.headers.cookie = jar_cookies,
+        }
+
+        if self.decode_content && self.headers.accept_encoding.is_none() {
+            self.headers.accept_encoding = Some(decoding::ACCEPT_ENCODING.to_owned());
+        }
+
+        // When a proxy is to forward the request, the Request-URI is absolute (RFC2616 §5.1.2)
+        let absolute_prefix = match self.proxy {
+            Some(ref proxy) if !Proxy::tunnels(&self.url) => {
+                if self.headers.proxy_authorization.is_none() {
+                    self.headers.proxy_authorization = proxy.authorization.clone();
+                }
+                let authority = Host {
+                    name: strip_brackets(self.url.host).to_owned(),
+                    port: try!(url_port(&self.url)),
+                };
+                format!("{}://{}", self.url.scheme, authority)
+            },
+            _ => ~"",
+        };
+
         // Write the Request-Line (RFC2616 §5.1)
-        // TODO: get to the point where we can say HTTP/1.1 with good conscience
         try!(write!(self.stream.get_mut_ref() as &mut Writer,
-            "{} {}{}{} HTTP/1.0\r\n",
+            "{} {}{}{}{} HTTP/{}.{}\r\n",
             self.method.to_str(),
+            absolute_prefix,
             if self.url.path.len()  > 0 { self.url.path.as_slice() } else { "/" },
             if self.url.query.len() > 0 { "?" } else { "" },
-            url::query_to_str(&self.url.query)));
+            url::query_to_str(&self.url.query),
+            major,
+            minor));
 
         try!(self.headers.write_all(self.stream.get_mut_ref()));
         self.headers_written = true;
+        if chunked {
+            // The headers must be flushed out before the stream starts writing chunks
+            let stream = self.stream.get_mut_ref();
+            try!(stream.flush());
+            stream.writing_chunked_body = true;
+        }
         Ok(())
     }
 
+    /// Whether the body is to be sent with the chunked transfer-coding, i.e. whether it is the last
+    /// transfer-coding in the Transfer-Encoding header.
+    pub fn is_chunked(&self) -> bool {
+        match self.headers.transfer_encoding {
+            Some(ref codings) => codings.last() == Some(&transfer_encoding::Chunked),
+            None => false,
+        }
+    }
+
+    /// Finish writing the body: when it is chunked, write the last chunk and the trailers.
+    fn finish_body(&mut self) -> IoResult<()> {
+        if !self.is_chunked() {
+            return Ok(());
+        }
+        let stream = self.stream.get_mut_ref();
+        // Out with the last of the data, and then back to writing plainly
+        try!(stream.flush());
+        stream.writing_chunked_body = false;
+        try!(stream.write(bytes!("0\r\n")));
+        self.trailers.write_all(stream)
+    }
+
+    /// Send the headers with `Expect: 100-continue` and wait up to `timeout_ms` milliseconds for
+    /// the server to say whether it wants the body (RFC 7231, §5.1.1), so that a large body need
+    /// not be sent only to be refused. This must be called before anything has been written.
+    ///
+    /// If the server responds with 100 (Continue), or says nothing in time (as servers which don't
+    /// implement expectations may not), the request is returned in `SendBody` for its body to be
+    /// written. If the server sends a final response instead (e.g. 401, 413, or 417 if it does not
+    /// meet the expectation), the body is not wanted, and the response is returned in
+    /// `Responded`; the connection is then not reused, as the server may yet read it for a body.
+    pub fn wait_for_continue(mut self, timeout_ms: u64)
+            -> Result<Continuation<S>, (RequestWriter<S>, IoError)> {
+        if self.headers_written {
+            fail!("RequestWriter.wait_for_continue() called, but headers already written");
+        }
+        self.headers.expect = Some(~"100-continue");
+        match self.write_headers() {
+            Ok(()) => (),
+            Err(err) => return Err((self, err)),
+        };
+        match self.flush() {
+            Ok(()) => (),
+            Err(err) => return Err((self, err)),
+        };
+
+        loop {
+            let timeout_ms = match self.time_remaining_ms() {
+                Ok(remaining_ms) => lesser_timeout(Some(timeout_ms), remaining_ms),
+                Err(err) => return Err((self, err)),
+            };
+            let first_byte = {
+                let stream = self.stream.get_mut_ref();
+                stream.wrapped.set_read_timeout(timeout_ms);
+                stream.read_byte()
+            };
+            match first_byte {
+                Ok(b) => self.stream.get_mut_ref().poke_byte(b),
+                Err(ref err) if err.kind == TimedOut => {
+                    // Unless that was the total timeout running out, it's time to send the body
+                    return match self.time_remaining_ms() {
+                        Ok(_) => Ok(SendBody(self)),
+                        Err(err) => Err((self, err)),
+                    };
+                },
+                Err(err) => return Err((self, err)),
+            }
+
+            // The server has something to say, then; it is read with the usual read timeout.
+            let read_timeout_ms = match self.read_timeout_ms_now() {
+                Ok(read_timeout_ms) => read_timeout_ms,
+                Err(err) => return Err((self, err)),
+            };
+            let result = {
+                let stream = self.stream.get_mut_ref();
+                stream.wrapped.set_read_timeout(read_timeout_ms);
+                response::read_response_head(stream)
+            };
+            let (version, status, headers, set_cookies) = match result {
+                Ok(parts) => parts,
+                Err(err) => {
+                    let err = response::head_err(&self, err);
+                    let err = self.response_error(err);
+                    return Err((self, err));
+                },
+            };
+
+            if status == status::Continue {
+                return Ok(SendBody(self));
+            } else if response::is_interim(&status) {
+                continue;
+            }
+            self.close_connection = true;
+            let stream = self.stream.take().unwrap();
+            return Ok(Responded(ResponseReader::from_parts(stream, self, version, status, headers,
+                                                           set_cookies)));
+        }
+    }
+
     /**
      * Send the request and construct a `ResponseReader` out of it.
      *
@@ -223,19 +811,149 @@ impl<S: Connecter + Reader + Writer> RequestWriter<S> {
             Ok(()) => (),
             Err(err) => return Err((self, err)),
         };
+        match self.finish_body() {
+            Ok(()) => (),
+            Err(err) => return Err((self, err)),
+        };
         match self.flush() {
             Ok(()) => (),
             Err(err) => return Err((self, err)),
         };
         match self.stream.take() {
-            Some(stream) => ResponseReader::construct(stream, self),
+            Some(mut stream) => {
+                match self.set_read_timeout_for(&mut stream) {
+                    Ok(()) => (),
+                    Err(err) => return Err((self, err)),
+                }
+                ResponseReader::construct(stream, self)
+            },
             None => unreachable!(), // TODO: is it genuinely unreachable?
         }
     }
 }
 
+fn chunked_needs_http_1_1_err() -> IoError {
+    IoError {
+        kind: InvalidInput,
+        desc: "The chunked transfer-coding cannot be used with HTTP/1.0",
+        detail: None,
+    }
+}
+
+fn unsupported_version_err(major: uint, minor: uint) -> IoError {
+    IoError {
+        kind: InvalidInput,
+        desc: "RequestWriter can only speak HTTP/1.0 or HTTP/1.1",
+        detail: Some(format!("HTTP/{}.{}", major, minor)),
+    }
+}
+
+/// The lesser of two optional timeouts, `None` being the greatest.
+fn lesser_timeout(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if a < b { a } else { b }),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+fn bad_port_err(port: &str) -> IoError {
+    IoError {
+        kind: InvalidInput,
+        desc: "invalid port in URL",
+        detail: Some(port.to_owned()),
+    }
+}
+
+fn no_addresses_err(host: &str) -> IoError {
+    IoError {
+        kind: OtherIoError,
+        desc: "host name resolved to no addresses",
+        detail: Some(host.to_owned()),
+    }
+}
+
+/// The port given in the URL, if any.
+fn url_port(url: &Url) -> IoResult<Option<u16>> {
+    match url.port {
+        Some(ref port) => match from_str::<u16>(*port) {
+            Some(port) => Ok(Some(port)),
+            None => Err(bad_port_err(*port)),
+        },
+        None => Ok(None),
+    }
+}
+
+/// The port implied by the scheme of the URL: 443 for HTTPS, 80 otherwise.
+fn default_port(url: &Url) -> u16 {
+    if url.scheme.as_slice() == "https" { 443 } else { 80 }
+}
+
+/// Determine the socket addresses to try connecting to for the URL, resolving its host name if it
+/// is not an IP address.
+fn url_to_socket_addrs(url: &Url, port: u16, resolver: &Option<SharedResolver>)
+        -> IoResult<Vec<SocketAddr>> {
+    let host = strip_brackets(url.host);
+    let ips = match from_str::<IpAddr>(host) {
+        Some(ip) => vec!(ip),
+        None => {
+            let (addrs, _ttl_ms) = match *resolver {
+                Some(ref resolver) => try!(resolver.resolve(host)),
+                None => try!(SystemResolver.resolve(host)),
+            };
+            if addrs.len() == 0 {
+                return Err(no_addresses_err(host));
+            }
+            interleave_address_families(addrs)
+        },
+    };
+    Ok(ips.move_iter().map(|ip| SocketAddr { ip: ip, port: port }).collect())
+}
+
+/// Put resolved addresses in the order to try them in: alternately IPv6 and IPv4, starting with
+/// the family of the first address and otherwise keeping the resolver's order (as RFC 6555, "Happy
+/// Eyeballs", recommends). That way, if one family is unreachable, the other is not tried last.
+fn interleave_address_families(addrs: Vec<IpAddr>) -> Vec<IpAddr> {
+    let v4_first = match addrs.as_slice().head() {
+        Some(&Ipv4Addr(..)) => true,
+        _ => false,
+    };
+    let (v4, v6): (Vec<IpAddr>, Vec<IpAddr>) = addrs.move_iter().partition(|a| match *a {
+        Ipv4Addr(..) => true,
+        Ipv6Addr(..) => false,
+    });
+    let (first, second) = if v4_first { (v4, v6) } else { (v6, v4) };
+    let mut ordered = Vec::with_capacity(first.len() + second.len());
+    let mut first = first.move_iter();
+    let mut second = second.move_iter();
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => break,
+            (a, b) => {
+                a.map(|a| ordered.push(a));
+                b.map(|b| ordered.push(b));
+            },
+        }
+    }
+    ordered
+}
+
+/// Add a connection-option to the Connection header, unless it is already there.
+fn add_connection_option(headers: &mut HeaderCollection, option: Connection) {
+    match headers.connection {
+        Some(ref mut options) => {
+            if !options.contains(&option) {
+                options.push(option);
+            }
+            return;
+        },
+        None => (),
+    }
+    headers.connection = Some(vec!(option));
+}
+
 /// Write the request body. Note that any calls to `write()` will cause the headers to be sent.
-impl<S: Reader + Writer + Connecter> Writer for RequestWriter<S> {
+impl<S: Reader + Writer + Connecter + Send> Writer for RequestWriter<S> {
     fn write(&mut self, buf: &[u8]) -> IoResult<()> {
         if !self.headers_written {
             try!(self.write_headers());
@@ -250,3 +968,322 @@ impl<S: Reader + Writer + Connecter> Writer for RequestWriter<S> {
         self.stream.get_mut_ref().flush()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::io::{IoResult, InvalidInput, TimedOut, Listener, Acceptor};
+    use std::io::net::ip::{SocketAddr, Ipv4Addr, Ipv6Addr};
+    use std::io::net::tcp::{TcpListener, TcpStream};
+    use std::io::net::unix::{UnixListener, UnixStream};
+    use std::io::TempDir;
+    use sync::Arc;
+    use url;
+    use connecter::{Resolver, StaticResolver};
+    use std::str;
+    use buffer::BufferedStream;
+    use headers::transfer_encoding::Chunked;
+    use memstream::{MemReaderFakeStream, MemWriterFakeStream};
+    use method::{Get, Post};
+    use status;
+    use super::{SendBody, Responded};
+    use super::{RequestWriter, interleave_address_families, lesser_timeout};
+    use client::proxy::Proxy;
+
+    fn request(url: &str) -> RequestWriter<MemReaderFakeStream> {
+        RequestWriter::new(Get, from_str(url).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_ip_literals() {
+        let r = request("http://127.0.0.1:8080/");
+        assert_eq!(r.remote_addrs.len(), 1);
+        assert_eq!(r.remote_addr.unwrap().ip, Ipv4Addr(127, 0, 0, 1));
+        assert_eq!(r.remote_addr.unwrap().port, 8080);
+        assert_eq!(r.headers.host.get_ref().to_str(), ~"127.0.0.1:8080");
+
+        let r = request("https://[::1]/");
+        assert_eq!(r.remote_addr.unwrap().ip, Ipv6Addr(0, 0, 0, 0, 0, 0, 0, 1));
+        assert_eq!(r.remote_addr.unwrap().port, 443);
+        assert_eq!(r.headers.host.get_ref().to_str(), ~"[::1]");
+    }
+
+    #[test]
+    fn test_resolver() {
+        let mut resolver = StaticResolver::new();
+        resolver.insert("example.test", vec!(Ipv4Addr(127, 0, 0, 2)));
+        let resolver = Arc::new(~resolver as ~Resolver:Send+Share);
+        let r: RequestWriter<MemReaderFakeStream> = RequestWriter::new_with_resolver(
+            Get, from_str("http://example.test:8080/").unwrap(), None, resolver.clone()).unwrap();
+        assert_eq!(r.remote_addr.unwrap().ip, Ipv4Addr(127, 0, 0, 2));
+        assert_eq!(r.remote_addr.unwrap().port, 8080);
+        assert_eq!(r.headers.host.get_ref().to_str(), ~"example.test:8080");
+
+        let r: IoResult<RequestWriter<MemReaderFakeStream>> = RequestWriter::new_with_resolver(
+            Get, from_str("http://example.invalid/").unwrap(), None, resolver);
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn test_bad_port() {
+        let r: Result<RequestWriter<MemReaderFakeStream>, _> =
+            RequestWriter::new(Get, from_str("http://127.0.0.1:http/").unwrap());
+        match r {
+            Err(err) => assert_eq!(err.kind, InvalidInput),
+            Ok(_) => fail!("a bad port should be an error"),
+        }
+    }
+
+    #[test]
+    fn test_unix_socket_url() {
+        let r = request("http+unix://%2Ftmp%2Fapp.sock/status");
+        assert_eq!(r.unix_socket, Some(Path::new("/tmp/app.sock")));
+        assert_eq!(r.remote_addr, None);
+        assert_eq!(r.remote_addrs.len(), 0);
+        assert_eq!(r.headers.host.get_ref().to_str(), ~"localhost");
+        assert_eq!(r.origin().unwrap().host, ~"%2Ftmp%2Fapp.sock");
+
+        // An explicit socket, which follow-ups to the same origin keep to
+        let r: RequestWriter<UnixStream> = RequestWriter::new_unix(
+            Get, from_str("http://app.test/a").unwrap(), Path::new("/tmp/app.sock")).unwrap();
+        assert_eq!(r.headers.host.get_ref().to_str(), ~"app.test");
+        assert_eq!(r.origin().unwrap().host, ~"/tmp/app.sock");
+        let next = r.follow_up(Get, from_str("http://app.test/b").unwrap()).unwrap();
+        assert_eq!(next.unix_socket, Some(Path::new("/tmp/app.sock")));
+        let mut resolver = StaticResolver::new();
+        resolver.insert("other.test", vec!(Ipv4Addr(127, 0, 0, 2)));
+        let mut r = r;
+        r.resolver = Some(Arc::new(~resolver as ~Resolver:Send+Share));
+        let next = r.follow_up(Get, from_str("http://other.test/").unwrap()).unwrap();
+        assert_eq!(next.unix_socket, None);
+    }
+
+    #[test]
+    fn test_proxied_origin() {
+        let url = "http://127.0.0.1/";
+        let proxy = |proxy_url: &str| Some(Proxy::new(from_str(proxy_url).unwrap()));
+        let direct = request(url);
+        let proxied: RequestWriter<MemReaderFakeStream> = RequestWriter::new_with_proxy(
+            Get, from_str(url).unwrap(), proxy("http://127.0.0.1:3128/")).unwrap();
+        let other: RequestWriter<MemReaderFakeStream> = RequestWriter::new_with_proxy(
+            Get, from_str(url).unwrap(), proxy("http://127.0.0.1:8080/")).unwrap();
+        assert_eq!(direct.origin().unwrap().proxy, None);
+        assert_eq!(proxied.origin().unwrap().proxy, Some(~"http://127.0.0.1:3128/"));
+        // Their connections are not to be mixed up in a pool
+        assert!(direct.origin() != proxied.origin());
+        assert!(proxied.origin() != other.origin());
+    }
+
+    #[test]
+    fn test_unix_socket_request() {
+        let dir = TempDir::new("rust-http-test").unwrap();
+        let socket = dir.path().join("server.sock");
+        let acceptor = UnixListener::bind(&socket).listen().unwrap();
+        spawn(proc() {
+            let mut acceptor = acceptor;
+            let mut stream = acceptor.accept().unwrap();
+            let mut head = Vec::new();
+            while !head.as_slice().ends_with(bytes!("\r\n\r\n")) {
+                head.push(stream.read_byte().unwrap());
+            }
+            assert!(head.as_slice().starts_with(bytes!("GET /status HTTP/1.1\r\n")));
+            stream.write(bytes!("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nOK")).unwrap();
+        });
+
+        let url = format!("http+unix://{}/status",
+                          url::encode_component(socket.as_str().unwrap()));
+        let request: RequestWriter<UnixStream> =
+            RequestWriter::new(Get, from_str(url).unwrap()).unwrap();
+        let mut response = match request.read_response() {
+            Ok(response) => response,
+            Err((_, err)) => fail!("request failed: {}", err),
+        };
+        assert_eq!(response.status, status::Ok);
+        assert_eq!(response.read_to_end().unwrap(), bytes!("OK").to_owned());
+    }
+
+    #[test]
+    fn test_interleave_address_families() {
+        let a = Ipv4Addr(192, 0, 2, 1);
+        let b = Ipv4Addr(192, 0, 2, 2);
+        let x = Ipv6Addr(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        let y = Ipv6Addr(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2);
+        assert_eq!(interleave_address_families(vec!(x, y, a, b)), vec!(x, a, y, b));
+        assert_eq!(interleave_address_families(vec!(a, b, x)), vec!(a, x, b));
+        assert_eq!(interleave_address_families(vec!(a, b)), vec!(a, b));
+    }
+
+    #[test]
+    fn test_lesser_timeout() {
+        assert_eq!(lesser_timeout(None, None), None);
+        assert_eq!(lesser_timeout(Some(5), None), Some(5));
+        assert_eq!(lesser_timeout(None, Some(5)), Some(5));
+        assert_eq!(lesser_timeout(Some(5), Some(3)), Some(3));
+    }
+
+    /// A request to a server which accepts the connection but never says anything.
+    fn silent_server_request() -> (RequestWriter<TcpStream>, TcpListener) {
+        let listener = TcpListener::bind(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 0 })
+                                  .unwrap();
+        let addr = listener.socket_name().unwrap();
+        let url = from_str(format!("http://127.0.0.1:{}/", addr.port)).unwrap();
+        (RequestWriter::new(Get, url).unwrap(), listener)
+    }
+
+    #[test]
+    fn test_read_timeout() {
+        let (mut request, listener) = silent_server_request();
+        let _acceptor = listener.listen().unwrap();
+        request.read_timeout_ms = Some(50);
+        match request.read_response() {
+            Err((_, err)) => {
+                assert_eq!(err.kind, TimedOut);
+                assert_eq!(err.desc, "Timed out waiting for the server to respond");
+            },
+            Ok(_) => fail!("the server never responded, so the request should time out"),
+        }
+    }
+
+    #[test]
+    fn test_total_timeout() {
+        let (mut request, listener) = silent_server_request();
+        let _acceptor = listener.listen().unwrap();
+        request.read_timeout_ms = Some(10_000);
+        request.total_timeout_ms = Some(50);
+        match request.read_response() {
+            Err((_, err)) => {
+                assert_eq!(err.kind, TimedOut);
+                assert_eq!(err.desc, "The request did not complete within its total timeout");
+            },
+            Ok(_) => fail!("the server never responded, so the request should time out"),
+        }
+    }
+
+    /// A request writing to memory, as though already connected
+    fn writing_request() -> RequestWriter<MemWriterFakeStream> {
+        let mut request = RequestWriter::new(Post, from_str("http://127.0.0.1/").unwrap())
+                                        .unwrap();
+        request.stream = Some(BufferedStream::new(MemWriterFakeStream::new()));
+        request
+    }
+
+    fn written(request: &mut RequestWriter<MemWriterFakeStream>) -> ~str {
+        request.stream.get_mut_ref().flush().unwrap();
+        str::from_utf8_owned(request.stream.get_ref().wrapped.get_ref().to_owned()).unwrap()
+    }
+
+    #[test]
+    fn test_chunked_body() {
+        let mut request = writing_request();
+        request.headers.transfer_encoding = Some(vec!(Chunked));
+        request.headers.content_length = Some(10);
+        request.write(bytes!("Hello, ")).unwrap();
+        request.flush().unwrap();
+        request.write(bytes!("world!")).unwrap();
+        request.trailers.content_md5 = Some(~"abc");
+        request.finish_body().unwrap();
+        let written = written(&mut request);
+        assert!(!written.contains("Content-Length"));
+        assert!(written.contains("\r\nTransfer-Encoding: chunked\r\n"));
+        assert!(written.ends_with("\r\n\r\n\
+                                   7\r\nHello, \r\n\
+                                   6\r\nworld!\r\n\
+                                   0\r\n\
+                                   Content-MD5: abc\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_chunked_body_empty() {
+        let mut request = writing_request();
+        request.headers.transfer_encoding = Some(vec!(Chunked));
+        request.try_write_headers().unwrap();
+        request.finish_body().unwrap();
+        assert!(written(&mut request).ends_with("\r\n\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_chunked_body_http_1_0() {
+        let mut request = writing_request();
+        request.version = (1, 0);
+        request.headers.transfer_encoding = Some(vec!(Chunked));
+        assert_eq!(request.write_headers().unwrap_err().kind, InvalidInput);
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let mut request = writing_request();
+        request.version = (2, 0);
+        assert_eq!(request.write_headers().unwrap_err().kind, InvalidInput);
+        assert!(!request.headers_written());
+    }
+
+    /// A server for one request with a five-byte body: it reads the request's head, sends `first`
+    /// and then, if `then` is not empty, reads the body and sends `then`.
+    fn expecting_server(first: &'static str, then: &'static str) -> ~str {
+        let listener = TcpListener::bind(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 0 })
+                                  .unwrap();
+        let port = listener.socket_name().unwrap().port;
+        let acceptor = listener.listen().unwrap();
+        spawn(proc() {
+            let mut acceptor = acceptor;
+            let mut stream = acceptor.accept().unwrap();
+            let mut head = Vec::new();
+            while !head.as_slice().ends_with(bytes!("\r\n\r\n")) {
+                head.push(stream.read_byte().unwrap());
+            }
+            stream.write(first.as_bytes()).unwrap();
+            if then.len() > 0 {
+                assert_eq!(stream.read_exact(5).unwrap().as_slice(), bytes!("Hello"));
+                stream.write(then.as_bytes()).unwrap();
+            }
+        });
+        format!("http://127.0.0.1:{}/", port)
+    }
+
+    #[test]
+    fn test_wait_for_continue() {
+        let url = expecting_server("HTTP/1.1 100 Continue\r\n\r\n",
+                                   "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+        let mut request: RequestWriter<TcpStream> =
+            RequestWriter::new(Post, from_str(url).unwrap()).unwrap();
+        request.headers.content_length = Some(5);
+        let mut request = match request.wait_for_continue(10_000) {
+            Ok(SendBody(request)) => request,
+            Ok(Responded(_)) => fail!("the server asked for the body"),
+            Err((_, err)) => fail!("{}", err),
+        };
+        request.write(bytes!("Hello")).unwrap();
+        match request.read_response() {
+            Ok(response) => assert_eq!(response.status, status::Ok),
+            Err((_, err)) => fail!("{}", err),
+        }
+    }
+
+    #[test]
+    fn test_wait_for_continue_refused() {
+        let url = expecting_server("HTTP/1.1 413 Request Entity Too Large\r\n\
+                                    Content-Length: 0\r\n\r\n", "");
+        let mut request: RequestWriter<TcpStream> =
+            RequestWriter::new(Post, from_str(url).unwrap()).unwrap();
+        request.headers.content_length = Some(5);
+        match request.wait_for_continue(10_000) {
+            Ok(Responded(response)) => {
+                assert_eq!(response.status, status::RequestEntityTooLarge);
+                assert!(response.close_connection);
+            },
+            Ok(SendBody(_)) => fail!("the server refused the body"),
+            Err((_, err)) => fail!("{}", err),
+        }
+    }
+
+    #[test]
+    fn test_wait_for_continue_timeout() {
+        let (mut request, listener) = silent_server_request();
+        let _acceptor = listener.listen().unwrap();
+        request.headers.content_length = Some(5);
+        match request.wait_for_continue(50) {
+            Ok(SendBody(_)) => (),
+            Ok(Responded(_)) => fail!("the server never responded"),
+            Err((_, err)) => fail!("{}", err),
+        }
+    }
+}
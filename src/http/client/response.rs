@@ -1,8 +1,20 @@
-use std::io::{Stream, IoResult, OtherIoError, IoError};
+use std::io;
+use std::io::{Stream, IoResult, IoError, ConnectionAborted};
+use std::cmp::min;
+use url::Url;
+use connecter::Connecter;
 use client::request::RequestWriter;
+use client::decoding;
+use client::decoding::Decoder;
+use client::text::TextReader;
 use rfc2616::{CR, LF, SP};
-use common::read_http_version;
+use rfc2616::content_coding::ValueToken;
+use common::{read_http_version, read_chunk_size, read_chunk_end};
+use error::{HttpError, HttpResult, Io, MalformedStatusLine, MalformedHeader, Truncated};
 use headers;
+use headers::transfer_encoding;
+use method::{Head, Connect};
+use status;
 use status::Status;
 
 use buffer::BufferedStream;
@@ -10,7 +22,8 @@ use server::request::{RequestBuffer};
 use headers::{EndOfFile, EndOfHeaders, MalformedHeaderSyntax, MalformedHeaderValue};
 
 pub struct ResponseReader<S> {
-    priv stream: BufferedStream<S>,
+    /// The connection; `None` once the body has been read and the connection returned to a pool.
+    priv stream: Option<BufferedStream<S>>,
 
     /// The request which this is a response to
     request: RequestWriter<S>,
@@ -23,113 +36,732 @@ pub struct ResponseReader<S> {
 
     /// The headers received in the response.
     headers: ~headers::response::HeaderCollection,
+
+    /// The trailer headers received after the body, when the chunked transfer-coding is in use.
+    /// These are only available after the body has been read in its entirety.
+    trailers: ~headers::response::HeaderCollection,
+
+    /// The values of the Set-Cookie headers received in the response. These are kept apart from
+    /// `headers` because, unlike other headers, several Set-Cookie headers cannot be combined into
+    /// one. If the request had a cookie jar, they will also have been stored in it.
+    set_cookies: Vec<~str>,
+
+    /// If the response was obtained by following redirects (see `client::redirect`), the URLs which
+    /// were redirected from, in the order they were requested. The final URL is `request.url`.
+    redirect_chain: Vec<Url>,
+
+    /// Whether the connection will be closed after the response, as determined from the HTTP
+    /// version and the Connection header of the request and response, and the manner in which the
+    /// end of the body is marked.
+    close_connection: bool,
+
+    /// How the body is being read.
+    priv body_state: BodyState,
+
+    /// The decoder of the body, once reading with `read` has begun decoding it; see
+    /// `content_coding`.
+    priv decoder: Option<Decoder>,
 }
 
-fn bad_response_err() -> IoError {
-    // TODO: IoError isn't right
-    IoError {
-        kind: OtherIoError,
-        desc: "Server returned malformed HTTP response",
-        detail: None,
-    }
+/// The state of reading the response body, determined by the rules of RFC 7230, §3.3.3.
+#[deriving(Eq)]
+enum BodyState {
+    /// The body is delimited by the closing of the connection.
+    UntilClose,
+
+    /// The body has a known length (from the Content-Length header); the value is the number of
+    /// bytes remaining.
+    Length(uint),
+
+    /// The chunked transfer-coding (RFC 2616, §3.6.1). The value is the number of bytes of
+    /// chunk-data remaining in the current chunk; zero means that the next chunk-size line has not
+    /// yet been read.
+    Chunked(uint),
+
+    /// The body has been read entirely; all further reads will produce EOF.
+    Finished,
 }
 
-impl<S: Stream> ResponseReader<S> {
-    pub fn construct(mut stream: BufferedStream<S>, request: RequestWriter<S>)
+impl<S: Connecter + Stream + Send> ResponseReader<S> {
+    /// Read the response to the request from the stream, up to the end of the headers. Interim
+    /// (1xx) responses other than 101 (Switching Protocols) are skipped over.
+    ///
+    /// If the connection fails or is closed before anything at all has been received, the error
+    /// is that of the connection (or `ConnectionAborted` for the closing of it), rather than that
+    /// of a malformed response: a server may close an idle persistent connection just as a request
+    /// is sent on it, and the request can then be retried (see `client::retry`). After that, a
+    /// response which is malformed or cut short is an error whose particulars are kept in the
+    /// request's `error`.
+    pub fn construct(mut stream: BufferedStream<S>, mut request: RequestWriter<S>)
             -> Result<ResponseReader<S>, (RequestWriter<S>, IoError)> {
-        // TODO: raise condition at the points where Err is returned
-        //let mut b = [0u8, ..4096];
-        //let len = stream.read(b);
-        //println!("{}", ::std::str::from_bytes(b.slice_to(len.unwrap())));
-        let http_version = match read_http_version(&mut stream, |b| b == SP) {
-            Ok(nums) => nums,
-            Err(_) => return Err((request, bad_response_err())),
-        };
-
-        // Read the status code
-        let mut digits = 0u8;
-        let mut status_code = 0u16;
+        match stream.read_byte() {
+            Ok(b) => {
+                request.set_response_began();
+                stream.poke_byte(b);
+            },
+            Err(err) => {
+                let err = no_response_err(&request, err);
+                let err = request.response_error(err);
+                return Err((request, err));
+            },
+        }
         loop {
-            if digits == 4u8 {
-                // Status code must be three digits long
-                return Err((request, bad_response_err()));
+            let head = match read_response_head(&mut stream) {
+                Ok(head) => head,
+                Err(err) => {
+                    let err = head_err(&request, err);
+                    let err = request.response_error(err);
+                    return Err((request, err));
+                },
+            };
+            let (http_version, status, headers, set_cookies) = head;
+            if is_interim(&status) {
+                debug!("skipping interim response {}", status);
+                continue;
             }
-            match stream.read_byte() {
-                Ok(b) if b >= '0' as u8 && b <= '9' as u8 => {
-                    status_code = status_code * 10 + b as u16 - '0' as u16;
+            return Ok(ResponseReader::from_parts(stream, request, http_version, status, headers,
+                                                 set_cookies));
+        }
+    }
+
+    /// Make the response out of its status line and headers, which have already been read from
+    /// the stream.
+    pub fn from_parts(stream: BufferedStream<S>, request: RequestWriter<S>,
+                      http_version: (uint, uint), status: Status,
+                      headers: ~headers::response::HeaderCollection, set_cookies: Vec<~str>)
+            -> ResponseReader<S> {
+        match request.cookie_jar {
+            Some(ref jar) => jar.store(&request.url, set_cookies.as_slice()),
+            None => (),
+        }
+
+        let status_code = status.code();
+        let tunnel = request.method == Connect && status_code / 100 == 2;
+
+        // Determine the length of the body as specified in RFC 7230, §3.3.3.
+        let body_state = if request.method == Head ||
+                status_code / 100 == 1 ||
+                status == status::NoContent ||
+                status == status::NotModified {
+            // Never a body, whatever the headers may say
+            Finished
+        } else if tunnel {
+            // The connection is now a tunnel; what follows is not ours to read as a body.
+            Finished
+        } else {
+            match (&headers.transfer_encoding, headers.content_length) {
+                // The chunked transfer-coding, if applied, must be the last one (RFC 2616, §3.6).
+                (&Some(ref codings), _) if codings.last() == Some(&transfer_encoding::Chunked)
+                    => Chunked(0),
+                // Other transfer-codings leave only the closing of the connection to go by. Note
+                // also that Transfer-Encoding overrides Content-Length.
+                (&Some(_), _) => UntilClose,
+                (&None, Some(0)) => Finished,
+                (&None, Some(length)) => Length(length),
+                (&None, None) => UntilClose,
+            }
+        };
+
+        let close_connection = request.close_connection ||
+                               closes_connection(http_version, &headers) ||
+                               body_state == UntilClose ||
+                               tunnel;
+
+        let mut response = ResponseReader {
+            stream: Some(stream),
+            request: request,
+            version: http_version,
+            status: status,
+            headers: headers,
+            trailers: ~headers::response::HeaderCollection::new(),
+            set_cookies: set_cookies,
+            redirect_chain: Vec::new(),
+            close_connection: close_connection,
+            body_state: body_state,
+            decoder: None,
+        };
+        if response.body_state == Finished {
+            response.finish_body();
+        }
+        response
+    }
+
+    /// Mark the body as having been read in its entirety, returning the connection to the pool if
+    /// the request was made with one and the connection is to be kept open.
+    fn finish_body(&mut self) {
+        self.body_state = Finished;
+        if self.close_connection {
+            return;
+        }
+        let returned = match (&self.request.pool, self.request.origin()) {
+            (&Some(ref pool), Some(origin)) => match self.stream.take() {
+                Some(stream) => {
+                    debug!("returning connection for {} to the pool", self.request.url.to_str());
+                    pool.put(origin, stream);
+                    true
+                },
+                None => false,
+            },
+            _ => false,
+        };
+        if returned {
+            // Now idle, it no longer counts as in use
+            self.request.release_active();
+        }
+    }
+}
+
+/// Determine whether the server will close the connection after the response, from the response's
+/// HTTP version and Connection header (RFC 2616, §8.1).
+fn closes_connection(version: (uint, uint), headers: &headers::response::HeaderCollection)
+        -> bool {
+    let mut close = match version {
+        (1, 0) => true,
+        (1, 1) => false,
+        // HTTP/0.9 or something we don't know; best not to trust it with another request
+        _ => true,
+    };
+    match headers.connection {
+        Some(ref options) => for option in options.iter() {
+            match *option {
+                headers::connection::Close => return true,
+                headers::connection::Token(ref s) if s.as_slice() == "Keep-Alive" => {
+                    close = false;
                 },
-                Ok(b) if b == SP => break,
-                _ => return Err((request, bad_response_err())),
+                headers::connection::Token(_) => (),
             }
-            digits += 1;
+        },
+        None => (),
+    }
+    close
+}
+
+/// Whether a response with this status is an interim one, to be followed by another (RFC 7231,
+/// §6.2). 101 (Switching Protocols) is not: what follows it is no longer HTTP.
+pub fn is_interim(status: &Status) -> bool {
+    status.code() / 100 == 1 && *status != status::SwitchingProtocols
+}
+
+/// Read the Status-Line and headers of a response: the HTTP version, status, headers and values
+/// of the Set-Cookie headers.
+pub fn read_response_head<S: Stream>(stream: &mut BufferedStream<S>)
+        -> HttpResult<((uint, uint), Status, ~headers::response::HeaderCollection, Vec<~str>)> {
+    let (http_version, status) = try!(read_status_line(stream));
+    let (headers, set_cookies) = try!(read_headers(stream));
+    Ok((http_version, status, headers, set_cookies))
+}
+
+/// Read a Status-Line (RFC 2616, §6.1), returning the HTTP version and status.
+fn read_status_line<S: Stream>(stream: &mut BufferedStream<S>)
+        -> HttpResult<((uint, uint), Status)> {
+    let http_version = match read_http_version(stream, |b| b == SP) {
+        Ok(nums) => nums,
+        Err(Io(err)) => return Err(Io(err)),
+        Err(_) => return Err(MalformedStatusLine),
+    };
+
+    // Read the status code
+    let mut digits = 0u8;
+    let mut status_code = 0u16;
+    loop {
+        if digits == 4u8 {
+            // Status code must be three digits long
+            return Err(MalformedStatusLine);
+        }
+        match try!(stream.read_byte().map_err(Io)) {
+            b if b >= '0' as u8 && b <= '9' as u8 => {
+                status_code = status_code * 10 + b as u16 - '0' as u16;
+            },
+            b if b == SP => break,
+            _ => return Err(MalformedStatusLine),
         }
+        digits += 1;
+    }
 
-        // Read the status reason
-        let mut reason = ~"";
-        loop {
-            match stream.read_byte() {
-                Ok(b) if b == CR => {
-                    if stream.read_byte() == Ok(LF) {
-                        break;
-                    } else {
-                        // Response-Line has CR without LF. Not yet resilient; TODO.
-                        return Err((request, bad_response_err()));
-                    }
-                }
-                Ok(b) => {
-                    reason.push_char(b as char);
+    // Read the status reason
+    let mut reason = ~"";
+    loop {
+        match try!(stream.read_byte().map_err(Io)) {
+            b if b == CR => {
+                if stream.read_byte() == Ok(LF) {
+                    break;
+                } else {
+                    // Response-Line has CR without LF. Not yet resilient; TODO.
+                    return Err(MalformedStatusLine);
                 }
-                Err(_) => return Err((request, bad_response_err())),
+            }
+            b => {
+                reason.push_char(b as char);
             }
         }
+    }
 
-        // Now we sneakily slip back to server::RequestBuffer to avoid code duplication. This is
-        // temporary, honest!
-        //
-        // You see, read_header and read_header_line will be replaced, as will this. The code will
-        // not be shared between them as they will have ultra-smart parsers (probably using Ragel)
-        // to provide fast loading of standard headers, and the set of defined headers is distinct
-        // between a request and response.
-        let headers = {
-            let mut buffer = RequestBuffer::new(&mut stream);
-            let mut headers = ~headers::response::HeaderCollection::new();
+    Ok((http_version, Status::from_code_and_reason(status_code, reason)))
+}
+
+fn closed_without_response_err() -> IoError {
+    IoError {
+        kind: ConnectionAborted,
+        desc: "Connection closed before any response was received",
+        detail: None,
+    }
+}
+
+/// The error for a failure to receive the first byte of the response.
+fn no_response_err<S: Connecter + Stream + Send>(request: &RequestWriter<S>, err: IoError)
+        -> HttpError {
+    match err.kind {
+        io::EndOfFile => Io(closed_without_response_err()),
+        _ => request.read_http_error(err),
+    }
+}
+
+/// An error in reading the status line or headers: a `TimedOut` error is reported as such (see
+/// `RequestWriter.read_error`), and the end of the stream as `Truncated`.
+pub fn head_err<S: Connecter + Stream + Send>(request: &RequestWriter<S>, err: HttpError)
+        -> HttpError {
+    match err.eof_is_truncation() {
+        Io(err) => request.read_http_error(err),
+        err => err,
+    }
+}
+
+/// Read header lines up to and including the empty line which marks their end, returning with the
+/// headers the values of any Set-Cookie headers.
+///
+/// This is used both for the headers of the response and for the trailer of a chunked body.
+fn read_headers<S: Stream>(stream: &mut BufferedStream<S>)
+        -> HttpResult<(~headers::response::HeaderCollection, Vec<~str>)> {
+    // Now we sneakily slip back to server::RequestBuffer to avoid code duplication. This is
+    // temporary, honest!
+    //
+    // You see, read_header and read_header_line will be replaced, as will this. The code will
+    // not be shared between them as they will have ultra-smart parsers (probably using Ragel)
+    // to provide fast loading of standard headers, and the set of defined headers is distinct
+    // between a request and response.
+    let mut buffer = RequestBuffer::new(stream);
+    let mut headers = ~headers::response::HeaderCollection::new();
+    let mut set_cookies = Vec::new();
+    loop {
+        let xxx = buffer.read_header::<headers::response::Header>();
+        info!("header = {:?}", xxx);
+        match xxx {
+        //match buffer.read_header::<headers::response::Header>() {
+            Err(EndOfFile) => return Err(Truncated),
+            Err(EndOfHeaders) => break,
+            Err(MalformedHeaderSyntax(line)) => return Err(MalformedHeader(line, ~"")),
+            Err(MalformedHeaderValue(name, value)) => {
+                if name.as_slice() == "Location" {
+                    // Not an absolute URL, but it may be a relative reference; see `redirect`
+                    headers.extensions.insert(name, value);
+                } else {
+                    // Just ignore the header
+                    debug!("ignoring header: {}", MalformedHeader(name, value));
+                }
+            },
+            Ok(headers::response::ExtensionHeader(ref name, ref value))
+                    if name.as_slice() == "Set-Cookie" => {
+                set_cookies.push(value.clone());
+            },
+            Ok(header) => {
+                headers.insert(header);
+            },
+        }
+    }
+    Ok((headers, set_cookies))
+}
+
+impl<S: Connecter + Stream + Send> Reader for ResponseReader<S> {
+    /// Read from the body, decoding it if `content_coding` says so.
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        let mut decoder = match self.decoder.take() {
+            Some(decoder) => decoder,
+            None => match self.content_coding() {
+                Some(coding) => try!(Decoder::new(coding, self.request.max_decoded_len)),
+                None => return self.read_raw(buf),
+            },
+        };
+        let result = decoder.read(&mut RawBody { response: self }, buf);
+        self.decoder = Some(decoder);
+        let decoded_all = match result {
+            Err(ref err) => err.kind == io::EndOfFile,
+            Ok(_) => false,
+        };
+        if decoded_all {
+            // The body is read to its end (ignoring anything after the encoded data), so that the
+            // trailer is read and the connection can be reused
+            let mut rest = [0u8, ..512];
             loop {
-                let xxx = buffer.read_header::<headers::response::Header>();
-                info!("header = {:?}", xxx);
-                match xxx {
-                //match buffer.read_header::<headers::response::Header>() {
-                    Err(EndOfFile) => {
-                        //fail!("server disconnected, no more response to receive :-(");
-                        return Err((request, bad_response_err()));
-                    },
-                    Err(EndOfHeaders) => break,
-                    Err(MalformedHeaderSyntax) => {
-                        return Err((request, bad_response_err()));
-                    },
-                    Err(MalformedHeaderValue) => {
-                        println!("Bad header encountered. TODO: handle this better.");
-                        // Now just ignore the header
-                    },
-                    Ok(header) => {
-                        headers.insert(header);
-                    },
+                match self.read_undecoded(rest) {
+                    Ok(_) => (),
+                    Err(ref err) if err.kind == io::EndOfFile => break,
+                    Err(err) => return Err(err),
                 }
             }
-            headers
-        };
+        }
+        result
+    }
+}
 
-        Ok(ResponseReader {
-            stream: stream,
-            request: request,
-            version: http_version,
-            status: Status::from_code_and_reason(status_code, reason),
-            headers: headers,
+/// The body of a response as it was sent, for a `Decoder` to read.
+struct RawBody<'a, S> {
+    response: &'a mut ResponseReader<S>,
+}
+
+impl<'a, S: Connecter + Stream + Send> Reader for RawBody<'a, S> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        self.response.read_undecoded(buf)
+    }
+}
+
+impl<S: Connecter + Stream + Send> ResponseReader<S> {
+    /// The content-coding which reading the body will decode: gzip or deflate, if the request had
+    /// `decode_content` set and the response has either in its Content-Encoding header.
+    pub fn content_coding(&self) -> Option<ValueToken> {
+        if !self.request.decode_content {
+            return None;
+        }
+        self.headers.content_encoding.as_ref().and_then(|encoding| {
+            decoding::decodable_coding(*encoding)
         })
     }
+
+    /// Turn the response into a reader of its body as UTF-8 text, decoded from the character set
+    /// which the Content-Type header gives (see `client::text`).
+    pub fn into_text(self) -> IoResult<TextReader<ResponseReader<S>>> {
+        let media_type = self.headers.content_type.clone();
+        TextReader::for_media_type(self, media_type.as_ref())
+    }
+
+    /// Read from the body as it was sent, without decoding it.
+    ///
+    /// Once reading with `read` has begun decoding the body, what is left of it is not to be read
+    /// raw; this is then an `EndOfFile` error.
+    pub fn read_raw(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        if self.decoder.is_some() {
+            return Err(io::standard_error(io::EndOfFile));
+        }
+        self.read_undecoded(buf)
+    }
 }
 
-impl<S: Stream> Reader for ResponseReader<S> {
-    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
-        self.stream.read(buf)
+impl<S: Connecter + Stream + Send> ResponseReader<S> {
+    /// Read from the body as it was sent, whether or not it is being decoded.
+    fn read_undecoded(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        let result = if self.body_state == Finished {
+            self.read_body(buf)
+        } else {
+            match self.request.set_read_timeout_for(self.stream.get_mut_ref()) {
+                Ok(()) => self.read_body(buf),
+                Err(err) => Err(Io(err)),
+            }
+        };
+        match result {
+            Ok(read) => Ok(read),
+            // The end of the body, rather than anything having gone wrong
+            Err(Io(ref err)) if err.kind == io::EndOfFile => Err(err.clone()),
+            Err(Io(err)) => {
+                let err = self.request.read_http_error(err);
+                Err(self.request.response_error(err))
+            },
+            Err(err) => Err(self.request.response_error(err)),
+        }
+    }
+}
+
+impl<S: Connecter + Stream + Send> ResponseReader<S> {
+    /// Read from the body, according to the manner in which its end is marked.
+    fn read_body(&mut self, buf: &mut [u8]) -> HttpResult<uint> {
+        match self.body_state {
+            UntilClose => match self.stream.get_mut_ref().read(buf) {
+                Err(ref err) if err.kind == io::EndOfFile => {
+                    self.body_state = Finished;
+                    Err(Io(io::standard_error(io::EndOfFile)))
+                },
+                result => result.map_err(Io),
+            },
+            Length(remaining) => {
+                let len = min(remaining, buf.len());
+                let read = try!(self.stream.get_mut_ref().read(buf.mut_slice_to(len))
+                                    .map_err(|err| Io(err).eof_is_truncation()));
+                if read == remaining {
+                    self.finish_body();
+                } else {
+                    self.body_state = Length(remaining - read);
+                }
+                Ok(read)
+            },
+            Finished => Err(Io(io::standard_error(io::EndOfFile))),
+            Chunked(0) => {
+                let size = read_chunk_size(self.stream.get_mut_ref());
+                match try!(size.map_err(|err| err.eof_is_truncation())) {
+                    0 => {
+                        // That was the last-chunk; all that remains is the trailer.
+                        let trailers = read_headers(self.stream.get_mut_ref());
+                        let (trailers, _) = try!(trailers.map_err(|err| err.eof_is_truncation()));
+                        self.trailers = trailers;
+                        self.finish_body();
+                        Err(Io(io::standard_error(io::EndOfFile)))
+                    },
+                    size => {
+                        self.body_state = Chunked(size);
+                        self.read_body(buf)
+                    },
+                }
+            },
+            Chunked(remaining) => {
+                let len = min(remaining, buf.len());
+                let read = try!(self.stream.get_mut_ref().read(buf.mut_slice_to(len))
+                                    .map_err(|err| Io(err).eof_is_truncation()));
+                if read == remaining {
+                    let end = read_chunk_end(self.stream.get_mut_ref());
+                    try!(end.map_err(|err| err.eof_is_truncation()));
+                    self.body_state = Chunked(0);
+                } else {
+                    self.body_state = Chunked(remaining - read);
+                }
+                Ok(read)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str;
+    use buffer::BufferedStream;
+    use memstream::MemReaderFakeStream;
+    use method::{Method, Get, Head};
+    use client::request::RequestWriter;
+    use client::pool::{ConnectionPool, Origin};
+    use client::cookie::CookieJar;
+    use status;
+    use super::ResponseReader;
+
+    fn read_response(raw: &str) -> ResponseReader<MemReaderFakeStream> {
+        read_response_to(Get, raw)
+    }
+
+    fn read_response_to(method: Method, raw: &str) -> ResponseReader<MemReaderFakeStream> {
+        let mut request = RequestWriter::new(method, from_str("http://127.0.0.1/").unwrap())
+                                        .unwrap();
+        request.close_connection = false;
+        let stream = BufferedStream::new(MemReaderFakeStream::new(raw.as_bytes().to_owned()));
+        match ResponseReader::construct(stream, request) {
+            Ok(response) => response,
+            Err((_, err)) => fail!("failed to read response: {}", err),
+        }
+    }
+
+    #[test]
+    fn test_identity_body() {
+        let mut response = read_response("HTTP/1.1 200 OK\r\n\r\nHello, world!");
+        let body = response.read_to_end().unwrap();
+        assert_eq!(str::from_utf8(body), Some("Hello, world!"));
+    }
+
+    #[test]
+    fn test_content_length_body() {
+        let mut response = read_response("HTTP/1.1 200 OK\r\n\
+                                          Content-Length: 5\r\n\
+                                          \r\n\
+                                          HelloHTTP/1.1 200 OK\r\n");
+        let body = response.read_to_end().unwrap();
+        assert_eq!(str::from_utf8(body), Some("Hello"));
+        assert!(response.read_byte().is_err());
+    }
+
+    #[test]
+    fn test_content_length_truncated() {
+        let mut response = read_response("HTTP/1.1 200 OK\r\n\
+                                          Content-Length: 10\r\n\
+                                          \r\n\
+                                          Hello");
+        assert!(response.read_to_end().is_err());
+    }
+
+    #[test]
+    fn test_no_body() {
+        let raw = "Content-Length: 5\r\n\r\nHello";
+        let mut response = read_response_to(Head, "HTTP/1.1 200 OK\r\n" + raw);
+        assert_eq!(response.read_to_end().unwrap().len(), 0);
+        let mut response = read_response("HTTP/1.1 204 No Content\r\n" + raw);
+        assert_eq!(response.read_to_end().unwrap().len(), 0);
+        let mut response = read_response("HTTP/1.1 304 Not Modified\r\n" + raw);
+        assert_eq!(response.read_to_end().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_transfer_encoding_overrides_content_length() {
+        let mut response = read_response("HTTP/1.1 200 OK\r\n\
+                                          Content-Length: 2\r\n\
+                                          Transfer-Encoding: chunked\r\n\
+                                          \r\n\
+                                          5\r\n\
+                                          Hello\r\n\
+                                          0\r\n\
+                                          \r\n");
+        let body = response.read_to_end().unwrap();
+        assert_eq!(str::from_utf8(body), Some("Hello"));
+    }
+
+    #[test]
+    fn test_chunked_body() {
+        let mut response = read_response("HTTP/1.1 200 OK\r\n\
+                                          Transfer-Encoding: chunked\r\n\
+                                          \r\n\
+                                          7\r\n\
+                                          Hello, \r\n\
+                                          6;ext=\"value\"\r\n\
+                                          world!\r\n\
+                                          0\r\n\
+                                          \r\n");
+        let body = response.read_to_end().unwrap();
+        assert_eq!(str::from_utf8(body), Some("Hello, world!"));
+        assert!(response.read_byte().is_err());
+    }
+
+    #[test]
+    fn test_chunked_trailers() {
+        let mut response = read_response("HTTP/1.1 200 OK\r\n\
+                                          Transfer-Encoding: chunked\r\n\
+                                          Trailer: Server\r\n\
+                                          \r\n\
+                                          2\r\n\
+                                          OK\r\n\
+                                          0\r\n\
+                                          Server: Example\r\n\
+                                          X-Checksum: abc\r\n\
+                                          \r\n");
+        assert_eq!(response.trailers.server, None);
+        let body = response.read_to_end().unwrap();
+        assert_eq!(str::from_utf8(body), Some("OK"));
+        assert_eq!(response.headers.server, None);
+        assert_eq!(response.trailers.server, Some(~"Example"));
+        assert_eq!(response.trailers.extensions.find(&~"X-Checksum"), Some(&~"abc"));
+    }
+
+    #[test]
+    fn test_chunked_bad_chunk_size() {
+        let mut response = read_response("HTTP/1.1 200 OK\r\n\
+                                          Transfer-Encoding: chunked\r\n\
+                                          \r\n\
+                                          xyz\r\n\
+                                          Hello\r\n");
+        assert!(response.read_to_end().is_err());
+    }
+
+    #[test]
+    fn test_close_connection() {
+        let response = read_response("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+        assert!(!response.close_connection);
+        let response = read_response("HTTP/1.1 200 OK\r\n\
+                                      Connection: close\r\n\
+                                      Content-Length: 0\r\n\r\n");
+        assert!(response.close_connection);
+        let response = read_response("HTTP/1.0 200 OK\r\nContent-Length: 0\r\n\r\n");
+        assert!(response.close_connection);
+        let response = read_response("HTTP/1.0 200 OK\r\n\
+                                      Connection: keep-alive\r\n\
+                                      Content-Length: 0\r\n\r\n");
+        assert!(!response.close_connection);
+        // Without Content-Length, only the closing of the connection ends the body
+        let response = read_response("HTTP/1.1 200 OK\r\n\r\n");
+        assert!(response.close_connection);
+    }
+
+    #[test]
+    fn test_connection_returned_to_pool() {
+        let pool = ConnectionPool::new();
+        let url = from_str("http://127.0.0.1/").unwrap();
+        let origin = Origin::from_url(&url).unwrap();
+        let request = RequestWriter::new_with_pool(Get, url, pool.clone()).unwrap();
+        let raw = "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nHello";
+        let stream = BufferedStream::new(MemReaderFakeStream::new(raw.as_bytes().to_owned()));
+        let mut response = match ResponseReader::construct(stream, request) {
+            Ok(response) => response,
+            Err((_, err)) => fail!("failed to read response: {}", err),
+        };
+        assert_eq!(pool.idle_count(&origin), 0);
+        let body = response.read_to_end().unwrap();
+        assert_eq!(str::from_utf8(body), Some("Hello"));
+        assert_eq!(pool.idle_count(&origin), 1);
+    }
+
+    #[test]
+    fn test_chunked_truncated() {
+        let mut response = read_response("HTTP/1.1 200 OK\r\n\
+                                          Transfer-Encoding: chunked\r\n\
+                                          \r\n\
+                                          a\r\n\
+                                          Hello");
+        assert!(response.read_to_end().is_err());
+    }
+
+    #[test]
+    fn test_set_cookies() {
+        let jar = CookieJar::new();
+        let url = from_str("http://127.0.0.1/").unwrap();
+        let mut request = RequestWriter::new(Get, url).unwrap();
+        request.cookie_jar = Some(jar.clone());
+        let raw = "HTTP/1.1 200 OK\r\n\
+                   Set-Cookie: a=1\r\n\
+                   Content-Length: 0\r\n\
+                   Set-Cookie: b=2; Path=/\r\n\
+                   \r\n";
+        let stream = BufferedStream::new(MemReaderFakeStream::new(raw.as_bytes().to_owned()));
+        let response = match ResponseReader::construct(stream, request) {
+            Ok(response) => response,
+            Err((_, err)) => fail!("failed to read response: {}", err),
+        };
+        assert_eq!(response.set_cookies, vec!(~"a=1", ~"b=2; Path=/"));
+        assert_eq!(response.headers.extensions.find(&~"Set-Cookie"), None);
+        assert_eq!(jar.cookie_header(&response.request.url), Some(~"a=1; b=2"));
+    }
+
+    #[test]
+    fn test_interim_responses_skipped() {
+        let mut response = read_response("HTTP/1.1 100 Continue\r\n\r\n\
+                                          HTTP/1.1 102 Processing\r\nX-Progress: 1\r\n\r\n\
+                                          HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nOK");
+        assert_eq!(response.status, status::Ok);
+        assert_eq!(response.headers.extensions.find(&~"X-Progress"), None);
+        assert_eq!(str::from_utf8(response.read_to_end().unwrap()), Some("OK"));
+
+        // But 101 is final
+        let response = read_response("HTTP/1.1 101 Switching Protocols\r\nUpgrade: foo\r\n\r\n");
+        assert_eq!(response.status, status::SwitchingProtocols);
+    }
+
+    #[test]
+    fn test_content_decoding() {
+        let gzip = [0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xf3, 0x48, 0xcd,
+                    0xc9, 0xc9, 0xd7, 0x51, 0x28, 0xcf, 0x2f, 0xca, 0x49, 0x51, 0x04, 0x00, 0xe6,
+                    0xc6, 0xe6, 0xeb, 0x0d, 0x00, 0x00, 0x00];
+        let response = |decode_content: bool| {
+            let mut request = RequestWriter::new(Get, from_str("http://127.0.0.1/").unwrap())
+                                            .unwrap();
+            request.decode_content = decode_content;
+            let mut raw = bytes!("HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\n\
+                                  Content-Length: 33\r\n\r\n").to_owned();
+            raw.push_all(gzip);
+            let stream = BufferedStream::new(MemReaderFakeStream::new(raw));
+            match ResponseReader::construct(stream, request) {
+                Ok(response) => response,
+                Err((_, err)) => fail!("failed to read response: {}", err),
+            }
+        };
+
+        let mut decoded = response(true);
+        assert_eq!(str::from_utf8(decoded.read_to_end().unwrap()), Some("Hello, world!"));
+        assert!(decoded.read_raw([0u8, ..16]).is_err());
+
+        let mut limited = response(true);
+        limited.request.max_decoded_len = Some(5);
+        assert!(limited.read_to_end().is_err());
+
+        let mut raw = response(false);
+        assert_eq!(raw.content_coding(), None);
+        assert_eq!(raw.read_to_end().unwrap().as_slice(), gzip.as_slice());
     }
 }
@@ -0,0 +1,317 @@
+/*!
+
+Retrying requests which failed for want of a response.
+
+A request can fail through no fault of its own: most commonly, a server closes an idle persistent
+connection just as a request is sent on it. A `RetryPolicy` sends the request again when that
+happens, or when the server responds with 503 (Service Unavailable) or 429 (Too Many Requests), with
+an exponentially increasing delay between attempts (or, where the response has a Retry-After
+header, the delay which that asks for).
+
+Only requests which can safely be repeated are retried: those with an idempotent method (see
+`Method::is_idempotent`), or which have been marked `retryable`. A request whose body is being
+streamed cannot be sent again, so it is never retried; the body must be given to `send` in full.
+
+```rust
+use http::client::RequestWriter;
+use http::client::retry::RetryPolicy;
+use http::method::Get;
+
+let request = RequestWriter::new(Get, from_str("http://example.com/").unwrap()).unwrap();
+let response = RetryPolicy::new().send(request, []).unwrap();
+```
+
+*/
+
+use std::io::{IoError, IoResult, Stream};
+use std::io::{ConnectionRefused, ConnectionReset, ConnectionAborted, BrokenPipe};
+use std::io::timer;
+use std::u64;
+use time::get_time;
+
+use common::parse_http_date;
+use connecter::Connecter;
+use status;
+use client::request::RequestWriter;
+use client::response::ResponseReader;
+
+/// By default, a request is retried up to three times.
+pub static DEFAULT_MAX_RETRIES: uint = 3;
+
+/// By default, the first retry is after a tenth of a second.
+pub static DEFAULT_INITIAL_BACKOFF_MS: u64 = 100;
+
+/// By default, there are never more than ten seconds between retries.
+pub static DEFAULT_MAX_BACKOFF_MS: u64 = 10_000;
+
+/// By default, a Retry-After of up to a minute is waited for.
+pub static DEFAULT_MAX_RETRY_AFTER_MS: u64 = 60_000;
+
+/// The rules for retrying requests.
+#[deriving(Clone)]
+pub struct RetryPolicy {
+    /// The most times to retry a request; zero means that requests are sent just once.
+    max_retries: uint,
+
+    /// The delay before the first retry, in milliseconds; it doubles with each retry after that.
+    initial_backoff_ms: u64,
+
+    /// The longest delay between retries, in milliseconds, when the server doesn't say.
+    max_backoff_ms: u64,
+
+    /// The longest delay that a Retry-After header may ask for, in milliseconds. If it asks for
+    /// longer, the response is returned rather than waiting.
+    max_retry_after_ms: u64,
+}
+
+impl RetryPolicy {
+    /// A policy with the defaults above.
+    pub fn new() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: DEFAULT_MAX_RETRIES,
+            initial_backoff_ms: DEFAULT_INITIAL_BACKOFF_MS,
+            max_backoff_ms: DEFAULT_MAX_BACKOFF_MS,
+            max_retry_after_ms: DEFAULT_MAX_RETRY_AFTER_MS,
+        }
+    }
+
+    /// The delay before the given retry (counting from zero), in milliseconds.
+    pub fn backoff_ms(&self, retry: uint) -> u64 {
+        let mut delay_ms = self.initial_backoff_ms;
+        for _ in range(0, retry) {
+            if delay_ms >= self.max_backoff_ms {
+                break;
+            }
+            delay_ms *= 2;
+        }
+        if delay_ms > self.max_backoff_ms { self.max_backoff_ms } else { delay_ms }
+    }
+
+    /// Send the request with the given body (which may be empty) and read the response, retrying
+    /// according to this policy.
+    pub fn send<S: Connecter + Stream + Send>(&self, request: RequestWriter<S>, body: &[u8])
+            -> IoResult<ResponseReader<S>> {
+        self.send_with(request, body, |request, body| {
+            let mut request = request;
+            if body.len() > 0 {
+                request.headers.transfer_encoding = None;
+                request.headers.content_length = Some(body.len());
+                try!(request.write(body));
+            }
+            match request.read_response() {
+                Ok(response) => Ok(response),
+                Err((_request, err)) => Err(err),
+            }
+        })
+    }
+
+    /// As `send`, but with each attempt made by `send_once`, which must write the body and read
+    /// the response; `RedirectPolicy.send`, for example.
+    pub fn send_with<S: Connecter + Stream + Send>(
+            &self, request: RequestWriter<S>, body: &[u8],
+            send_once: |RequestWriter<S>, &[u8]| -> IoResult<ResponseReader<S>>)
+            -> IoResult<ResponseReader<S>> {
+        let retryable = !request.headers_written() &&
+                        (request.method.is_idempotent() || request.retryable);
+        let mut request = request;
+        let mut retries = 0u;
+        loop {
+            // Sending consumes the request, so it is copied beforehand in case it is to be retried
+            let unsent = if retryable && retries < self.max_retries {
+                Some(request.clone_unsent())
+            } else {
+                None
+            };
+            let result = send_once(request, body);
+            let next = match unsent {
+                Some(next) => next,
+                None => return result,
+            };
+            let delay_ms = match result {
+                Err(ref err) if is_retryable_err(err, next.response_began()) => {
+                    Some(self.backoff_ms(retries))
+                },
+                Err(_) => None,
+                Ok(ref response) => self.status_delay_ms(response, retries),
+            };
+            match delay_ms {
+                Some(delay_ms) => {
+                    info!("retrying {} {} in {}ms", next.method.to_str(), next.url.to_str(),
+                          delay_ms);
+                    drop(result);
+                    timer::sleep(delay_ms);
+                },
+                None => return result,
+            }
+            request = next;
+            retries += 1;
+        }
+    }
+
+    /// The delay before retrying, if the response is one to retry.
+    fn status_delay_ms<S: Connecter + Stream + Send>(&self, response: &ResponseReader<S>,
+                                                     retry: uint) -> Option<u64> {
+        if response.status != status::ServiceUnavailable &&
+                response.status != status::TooManyRequests {
+            return None;
+        }
+        let retry_after = response.headers.retry_after.as_ref().and_then(|value| {
+            retry_after_ms(*value, get_time().sec)
+        });
+        match retry_after {
+            Some(delay_ms) if delay_ms > self.max_retry_after_ms => None,
+            Some(delay_ms) => Some(delay_ms),
+            None => Some(self.backoff_ms(retry)),
+        }
+    }
+}
+
+/// Whether the error is one of the connection failing or being closed before a response was
+/// received (see `ResponseReader::construct`), so that sending the request again may succeed. Once
+/// some of the response has been received, the server has acted on the request, and it isn't.
+fn is_retryable_err(err: &IoError, response_began: bool) -> bool {
+    match err.kind {
+        ConnectionRefused => true,
+        ConnectionReset | ConnectionAborted | BrokenPipe => !response_began,
+        _ => false,
+    }
+}
+
+/// The delay asked for by the value of a Retry-After header (RFC 2616, §14.37): a number of
+/// seconds, or an HTTP-date (compared with `now`, in seconds since the epoch). A delay too long to
+/// count in milliseconds is taken as the longest there is.
+fn retry_after_ms(value: &str, now: i64) -> Option<u64> {
+    let value = value.trim();
+    match from_str::<u64>(value) {
+        Some(seconds) => return Some(seconds_to_ms(seconds)),
+        None => (),
+    }
+    parse_http_date(value).map(|tm| {
+        let seconds = tm.to_timespec().sec - now;
+        if seconds > 0 { seconds_to_ms(seconds as u64) } else { 0 }
+    })
+}
+
+/// Seconds as milliseconds, or `u64::MAX` if there are too many.
+fn seconds_to_ms(seconds: u64) -> u64 {
+    seconds.checked_mul(&1000).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{IoError, ConnectionReset, ConnectionAborted, TimedOut, OtherIoError};
+    use std::io::{Listener, Acceptor};
+    use std::io::net::ip::{SocketAddr, Ipv4Addr};
+    use std::io::net::tcp::{TcpListener, TcpStream};
+    use buffer::BufferedStream;
+    use memstream::MemReaderFakeStream;
+    use method::{Method, Get, Post};
+    use status;
+    use client::request::RequestWriter;
+    use client::response::ResponseReader;
+    use std::u64;
+    use super::{RetryPolicy, is_retryable_err, retry_after_ms};
+
+    /// A server for a connection per response: it reads the request's head and sends the
+    /// response, or if the response is empty, closes the connection without one.
+    fn scripted_server(responses: &'static [&'static str]) -> ~str {
+        let listener = TcpListener::bind(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 0 })
+                                  .unwrap();
+        let port = listener.socket_name().unwrap().port;
+        let acceptor = listener.listen().unwrap();
+        spawn(proc() {
+            let mut acceptor = acceptor;
+            for response in responses.iter() {
+                let mut stream = acceptor.accept().unwrap();
+                let mut head = Vec::new();
+                while !head.as_slice().ends_with(bytes!("\r\n\r\n")) {
+                    head.push(stream.read_byte().unwrap());
+                }
+                stream.write(response.as_bytes()).unwrap();
+            }
+        });
+        format!("http://127.0.0.1:{}/", port)
+    }
+
+    fn send(method: Method, url: ~str) -> Result<status::Status, IoError> {
+        let request: RequestWriter<TcpStream> =
+            RequestWriter::new(method, from_str(url).unwrap()).unwrap();
+        let mut policy = RetryPolicy::new();
+        policy.initial_backoff_ms = 1;
+        policy.send(request, []).map(|response| response.status)
+    }
+
+    #[test]
+    fn test_retry_after_closed_connection() {
+        let url = scripted_server(&["", "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"]);
+        assert_eq!(send(Get, url), Ok(status::Ok));
+    }
+
+    #[test]
+    fn test_no_retry_of_post() {
+        let url = scripted_server(&[""]);
+        assert_eq!(send(Post, url).unwrap_err().kind, ConnectionAborted);
+    }
+
+    #[test]
+    fn test_retry_of_unavailable() {
+        let url = scripted_server(&[
+            "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 0\r\nContent-Length: 0\r\n\r\n",
+            "HTTP/1.1 429 Too Many Requests\r\nContent-Length: 0\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"]);
+        assert_eq!(send(Get, url), Ok(status::Ok));
+
+        // Unless the server wants longer than we'll wait
+        let url = scripted_server(&[
+            "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 3600\r\nContent-Length: 0\r\n\r\n"]);
+        assert_eq!(send(Get, url), Ok(status::ServiceUnavailable));
+    }
+
+    #[test]
+    fn test_backoff_ms() {
+        let policy = RetryPolicy::new();
+        assert_eq!(policy.backoff_ms(0), 100);
+        assert_eq!(policy.backoff_ms(1), 200);
+        assert_eq!(policy.backoff_ms(3), 800);
+        assert_eq!(policy.backoff_ms(7), 10_000);
+        assert_eq!(policy.backoff_ms(100), 10_000);
+    }
+
+    #[test]
+    fn test_is_retryable_err() {
+        let err = |kind| IoError { kind: kind, desc: "", detail: None };
+        assert!(is_retryable_err(&err(ConnectionReset), false));
+        assert!(is_retryable_err(&err(ConnectionAborted), false));
+        assert!(!is_retryable_err(&err(TimedOut), false));
+        assert!(!is_retryable_err(&err(OtherIoError), false));
+
+        // Not once the server has begun to respond
+        assert!(!is_retryable_err(&err(ConnectionReset), true));
+    }
+
+    #[test]
+    fn test_response_began() {
+        let request: RequestWriter<MemReaderFakeStream> =
+            RequestWriter::new(Get, from_str("http://127.0.0.1/").unwrap()).unwrap();
+        let unsent = request.clone_unsent();
+        assert!(!unsent.response_began());
+        let raw = bytes!("HTTP/1.1 200").to_owned();
+        let stream = BufferedStream::new(MemReaderFakeStream::new(raw));
+        assert!(ResponseReader::construct(stream, request).is_err());
+        assert!(unsent.response_began());
+    }
+
+    #[test]
+    fn test_retry_after_ms() {
+        // Sun, 06 Nov 1994 08:49:37 GMT
+        let now = 784111777;
+        assert_eq!(retry_after_ms("120", now), Some(120_000));
+        assert_eq!(retry_after_ms("Sun, 06 Nov 1994 08:50:07 GMT", now), Some(30_000));
+        assert_eq!(retry_after_ms("Sunday, 06-Nov-94 08:49:47 GMT", now), Some(10_000));
+        assert_eq!(retry_after_ms("Sun, 06 Nov 1994 08:00:00 GMT", now), Some(0));
+        assert_eq!(retry_after_ms("soon", now), None);
+
+        // Too long to count in milliseconds, which must not wrap around to a short delay
+        assert_eq!(retry_after_ms("18446744073709552", now), Some(u64::MAX));
+    }
+}
@@ -0,0 +1,404 @@
+/*!
+
+A convenient API for making requests, after the fashion of Python's
+[Requests](http://python-requests.org/).
+
+A `Session` holds the things which are common to a series of requests: a connection pool, a cookie
+jar, default headers, a base URL, credentials, the policy for following redirects and optionally a
+cache (see `client::cache`). Each request is then a single call, which returns a `Response` with the
+body read in full:
+
+```rust
+use http::client::session::Session;
+use std::io::net::tcp::TcpStream;
+
+let mut session: Session<TcpStream> = Session::new();
+session.base_url = Some(from_str("http://example.com/api/").unwrap());
+session.basic_auth = Some((~"Aladdin", ~"open sesame"));
+session.headers.user_agent = Some(~"my-program/1.0");
+
+let response = session.get("users/1").unwrap();
+if response.is_success() {
+    println!("{}", response.text().unwrap());
+}
+let response = session.post("users", bytes!("name=Jafar")).unwrap();
+```
+
+For streaming request or response bodies, or anything else out of the ordinary, use
+`Session.prepare` to make the `RequestWriter` and proceed from there.
+
+*/
+
+use std::io::{IoError, IoResult, InvalidInput, OtherIoError, MemReader, Stream};
+use url::Url;
+
+use common::basic_credentials;
+use connecter::Connecter;
+use headers;
+use headers::request::HeaderCollection;
+use method::{Method, Get, Head, Post, Put, Delete};
+use status::Status;
+use client::cache::Cache;
+use client::cookie::CookieJar;
+use client::pool::{ConnectionPool, Origin};
+use client::redirect::{RedirectPolicy, resolve_reference};
+use client::request::RequestWriter;
+use client::response::ResponseReader;
+use client::retry::RetryPolicy;
+use client::text::TextReader;
+
+/// The things common to a series of requests.
+pub struct Session<S> {
+    /// The URL against which relative URLs are resolved. Note that, as in HTML, a base URL of
+    /// `http://example.com/api` resolves `users` to `http://example.com/users`; it should end with
+    /// a slash.
+    base_url: Option<Url>,
+
+    /// Headers to send with every request (other than Host, which is always that of the URL).
+    headers: ~HeaderCollection,
+
+    /// The user name and password to send with the Basic authentication scheme, unless the
+    /// Authorization header is set in `headers`. They are sent only to the origin (scheme, host
+    /// and port) of `base_url`, so never if there is no base URL.
+    basic_auth: Option<(~str, ~str)>,
+
+    /// How redirects are followed; if `None`, they aren't, and are returned as the response.
+    redirect_policy: Option<RedirectPolicy>,
+
+    /// How failed requests are retried; if `None` (the default), they aren't.
+    retry_policy: Option<RetryPolicy>,
+
+    /// Whether to ask for compressed responses and decompress them (see `client::decoding`).
+    decode_content: bool,
+
+    /// How long to wait for each read from the server, in milliseconds (see
+    /// `RequestWriter.read_timeout_ms`).
+    read_timeout_ms: Option<u64>,
+
+    /// The cache in which responses are stored and looked up; if `None` (the default), there is
+    /// none.
+    cache: Option<Cache>,
+
+    /// The connections kept open between requests
+    pool: ConnectionPool<S>,
+
+    /// The cookies received in responses, to be sent with subsequent requests
+    cookie_jar: CookieJar,
+}
+
+fn bad_url_err(url: &str) -> IoError {
+    IoError {
+        kind: InvalidInput,
+        desc: "invalid URL",
+        detail: Some(url.to_owned()),
+    }
+}
+
+impl<S: Connecter + Stream + Send> Session<S> {
+    /// A session with no base URL, default headers or credentials, which follows redirects
+    /// according to `RedirectPolicy::new()` and decompresses responses.
+    pub fn new() -> Session<S> {
+        Session {
+            base_url: None,
+            headers: ~HeaderCollection::new(),
+            basic_auth: None,
+            redirect_policy: Some(RedirectPolicy::new()),
+            retry_policy: None,
+            decode_content: true,
+            read_timeout_ms: None,
+            cache: None,
+            pool: ConnectionPool::new(),
+            cookie_jar: CookieJar::new(),
+        }
+    }
+
+    /// Make a GET request.
+    pub fn get(&self, url: &str) -> IoResult<Response> {
+        self.request(Get, url, [])
+    }
+
+    /// Make a HEAD request.
+    pub fn head(&self, url: &str) -> IoResult<Response> {
+        self.request(Head, url, [])
+    }
+
+    /// Make a POST request with the given body.
+    pub fn post(&self, url: &str, body: &[u8]) -> IoResult<Response> {
+        self.request(Post, url, body)
+    }
+
+    /// Make a PUT request with the given body.
+    pub fn put(&self, url: &str, body: &[u8]) -> IoResult<Response> {
+        self.request(Put, url, body)
+    }
+
+    /// Make a DELETE request.
+    pub fn delete(&self, url: &str) -> IoResult<Response> {
+        self.request(Delete, url, [])
+    }
+
+    /// Make a request with the given method and body (which may be empty), following redirects
+    /// and retrying according to the session's policies, and read the response in full; or if
+    /// the cache has a fresh response to it, return that.
+    pub fn request(&self, method: Method, url: &str, body: &[u8]) -> IoResult<Response> {
+        let request = try!(self.prepare(method, url));
+        match self.cache {
+            Some(ref cache) => cache.send_with(request, body, |request, body| {
+                self.fetch(request, body)
+            }),
+            None => self.fetch(request, body),
+        }
+    }
+
+    /// Send the request and read the response in full, following redirects and retrying.
+    fn fetch(&self, request: RequestWriter<S>, body: &[u8]) -> IoResult<Response> {
+        let mut response = match self.retry_policy {
+            Some(ref retry_policy) => try!(retry_policy.send_with(request, body, |request, body| {
+                self.send_once(request, body)
+            })),
+            None => try!(self.send_once(request, body)),
+        };
+        Response::read(&mut response)
+    }
+
+    /// Send the request and read the response, following redirects but not retrying.
+    fn send_once(&self, request: RequestWriter<S>, body: &[u8]) -> IoResult<ResponseReader<S>> {
+        match self.redirect_policy {
+            Some(ref policy) => policy.send(request, body),
+            None => {
+                let mut request = request;
+                if body.len() > 0 {
+                    request.headers.content_length = Some(body.len());
+                    try!(request.write(body));
+                }
+                match request.read_response() {
+                    Ok(response) => Ok(response),
+                    Err((_request, err)) => Err(err),
+                }
+            },
+        }
+    }
+
+    /// Make a `RequestWriter` for the URL (which may be relative to `base_url`) with the session's
+    /// headers, credentials, connection pool and cookie jar, but send nothing yet.
+    pub fn prepare(&self, method: Method, url: &str) -> IoResult<RequestWriter<S>> {
+        let url = try!(self.resolve(url));
+        let mut request = try!(RequestWriter::new_with_pool(method, url, self.pool.clone()));
+        let host = request.headers.host.take();
+        request.headers = self.headers.clone();
+        request.headers.host = host;
+        if request.headers.authorization.is_none() && self.is_base_origin(&request.url) {
+            request.headers.authorization = self.basic_auth.as_ref().map(|&(ref user, ref pass)| {
+                basic_credentials(*user, Some(pass.as_slice()))
+            });
+        }
+        request.cookie_jar = Some(self.cookie_jar.clone());
+        request.decode_content = self.decode_content;
+        request.read_timeout_ms = self.read_timeout_ms;
+        Ok(request)
+    }
+
+    /// Whether the URL has the same origin as `base_url`.
+    fn is_base_origin(&self, url: &Url) -> bool {
+        match self.base_url {
+            Some(ref base) => match (Origin::from_url(base), Origin::from_url(url)) {
+                (Some(base), Some(origin)) => base == origin,
+                _ => false,
+            },
+            None => false,
+        }
+    }
+
+    /// Resolve a URL which may be relative to `base_url` (RFC 3986, §5.2; see
+    /// `redirect::resolve_reference`).
+    pub fn resolve(&self, url: &str) -> IoResult<Url> {
+        let resolved = match self.base_url {
+            Some(ref base) => resolve_reference(base, url),
+            None => None,
+        };
+        match resolved {
+            Some(resolved) => Ok(resolved),
+            // Not relative, or nothing to be relative to
+            None => from_str(url).ok_or(bad_url_err(url)),
+        }
+    }
+}
+
+/// A response, with its body read in full.
+pub struct Response {
+    /// The URL which the response came from (after any redirects)
+    url: Url,
+
+    /// The HTTP version number of the response
+    version: (uint, uint),
+
+    /// The status of the response
+    status: Status,
+
+    /// The headers of the response
+    headers: ~headers::response::HeaderCollection,
+
+    /// The URLs which were redirected from on the way to `url`, in order
+    redirect_chain: Vec<Url>,
+
+    priv body: ~[u8],
+}
+
+fn status_err(status: &Status) -> IoError {
+    IoError {
+        kind: OtherIoError,
+        desc: "the server responded with an error status",
+        detail: Some(status.to_str()),
+    }
+}
+
+impl Response {
+    /// A response made up of the given parts, not redirected from anywhere; as a `Cache` makes a
+    /// stored response into one.
+    pub fn new(url: Url, version: (uint, uint), status: Status,
+               headers: ~headers::response::HeaderCollection, body: ~[u8]) -> Response {
+        Response {
+            url: url,
+            version: version,
+            status: status,
+            headers: headers,
+            redirect_chain: Vec::new(),
+            body: body,
+        }
+    }
+
+    /// Read the rest of the response and make a `Response` out of it.
+    pub fn read<S: Connecter + Stream + Send>(response: &mut ResponseReader<S>)
+            -> IoResult<Response> {
+        let body = try!(response.read_to_end());
+        Ok(Response {
+            url: response.request.url.clone(),
+            version: response.version,
+            status: response.status.clone(),
+            headers: response.headers.clone(),
+            redirect_chain: response.redirect_chain.clone(),
+            body: body,
+        })
+    }
+
+    /// The body of the response
+    pub fn bytes<'a>(&'a self) -> &'a [u8] {
+        self.body.as_slice()
+    }
+
+    /// The body of the response as text, decoded from the character set which the Content-Type
+    /// header gives (see `client::text`).
+    pub fn text(&self) -> IoResult<~str> {
+        let body = MemReader::new(self.body.clone());
+        let mut reader = try!(TextReader::for_media_type(body, self.headers.content_type.as_ref()));
+        reader.read_to_str()
+    }
+
+    /// Whether the status is 1xx (Informational)
+    pub fn is_informational(&self) -> bool {
+        self.status.code() / 100 == 1
+    }
+
+    /// Whether the status is 2xx (Successful)
+    pub fn is_success(&self) -> bool {
+        self.status.code() / 100 == 2
+    }
+
+    /// Whether the status is 3xx (Redirection)
+    pub fn is_redirection(&self) -> bool {
+        self.status.code() / 100 == 3
+    }
+
+    /// Whether the status is 4xx (Client Error)
+    pub fn is_client_error(&self) -> bool {
+        self.status.code() / 100 == 4
+    }
+
+    /// Whether the status is 5xx (Server Error)
+    pub fn is_server_error(&self) -> bool {
+        self.status.code() / 100 == 5
+    }
+
+    /// Turn a client or server error status (4xx or 5xx) into an error.
+    pub fn error_for_status(self) -> IoResult<Response> {
+        if self.is_client_error() || self.is_server_error() {
+            Err(status_err(&self.status))
+        } else {
+            Ok(self)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use buffer::BufferedStream;
+    use memstream::MemReaderFakeStream;
+    use method::Get;
+    use status;
+    use client::request::RequestWriter;
+    use client::response::ResponseReader;
+    use super::{Session, Response};
+
+    #[test]
+    fn test_resolve() {
+        let mut session: Session<MemReaderFakeStream> = Session::new();
+        assert!(session.resolve("users").is_err());
+        assert_eq!(session.resolve("http://example.org/x").unwrap().to_str(),
+                   ~"http://example.org/x");
+
+        session.base_url = Some(from_str("http://example.com:8080/api/v1/").unwrap());
+        let resolve = |url: &str| session.resolve(url).unwrap().to_str();
+        assert_eq!(resolve("users"), ~"http://example.com:8080/api/v1/users");
+        assert_eq!(resolve("users?id=1"), ~"http://example.com:8080/api/v1/users?id=1");
+        assert_eq!(resolve("/users"), ~"http://example.com:8080/users");
+        assert_eq!(resolve("../v2/users"), ~"http://example.com:8080/api/v2/users");
+        assert_eq!(resolve("./users/../groups"), ~"http://example.com:8080/api/v1/groups");
+        assert_eq!(resolve("?q=1"), ~"http://example.com:8080/api/v1/?q=1");
+        assert_eq!(resolve("//example.org/x"), ~"http://example.org/x");
+        assert_eq!(resolve("https://example.org/"), ~"https://example.org/");
+    }
+
+    #[test]
+    fn test_prepare() {
+        let mut session: Session<MemReaderFakeStream> = Session::new();
+        session.base_url = Some(from_str("http://127.0.0.1/").unwrap());
+        session.basic_auth = Some((~"Aladdin", ~"open sesame"));
+        session.headers.user_agent = Some(~"test");
+        let request = session.prepare(Get, "x").unwrap();
+        assert_eq!(request.url.to_str(), ~"http://127.0.0.1/x");
+        assert_eq!(request.headers.user_agent, Some(~"test"));
+        assert_eq!(request.headers.authorization, Some(~"Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="));
+        assert_eq!(request.headers.host.get_ref().to_str(), ~"127.0.0.1");
+        assert!(request.cookie_jar.is_some());
+        assert!(request.decode_content);
+
+        // The credentials are for the base URL's origin only
+        let request = session.prepare(Get, "http://127.0.0.1:8080/x").unwrap();
+        assert_eq!(request.headers.authorization, None);
+        let request = session.prepare(Get, "http://127.0.0.1/y").unwrap();
+        assert_eq!(request.headers.authorization, Some(~"Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="));
+
+        // Explicit headers win
+        session.headers.authorization = Some(~"Bearer xyz");
+        let request = session.prepare(Get, "x").unwrap();
+        assert_eq!(request.headers.authorization, Some(~"Bearer xyz"));
+    }
+
+    #[test]
+    fn test_response() {
+        let request = RequestWriter::new(Get, from_str("http://127.0.0.1/").unwrap()).unwrap();
+        let raw = "HTTP/1.1 404 Not Found\r\nContent-Length: 9\r\n\r\nNot here!";
+        let stream = BufferedStream::new(MemReaderFakeStream::new(raw.as_bytes().to_owned()));
+        let mut reader = match ResponseReader::construct(stream, request) {
+            Ok(reader) => reader,
+            Err((_, err)) => fail!("{}", err),
+        };
+        let response = Response::read(&mut reader).unwrap();
+        assert_eq!(response.status, status::NotFound);
+        assert_eq!(response.bytes(), bytes!("Not here!"));
+        assert_eq!(response.text().unwrap(), ~"Not here!");
+        assert!(response.is_client_error());
+        assert!(!response.is_success());
+        assert!(response.error_for_status().is_err());
+    }
+}
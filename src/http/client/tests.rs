@@ -1,15 +1,207 @@
-#[cfg(test)];
+//! Tests of whole exchanges, against a `MockServer`.
 
-use memstream::MemReaderFakeStream;
+use std::io::{ConnectionReset, TimedOut};
+use std::str;
+use method::{Get, Post};
+use status;
+use error::{MalformedStatusLine, MalformedHeader, Truncated, Timeout, ReadTimeout};
+use client::cache::Cache;
+use client::mock::{MockServer, MockResponse, MockStream};
+use client::pool::ConnectionPool;
+use client::redirect::RedirectPolicy;
 use client::request::RequestWriter;
 use client::response::ResponseReader;
+use client::retry::RetryPolicy;
+use client::session::Session;
 
-fn test() {
-    let mut request = ~RequestWriter::new(Get, from_str("http://example.com/").unwrap());
-    ResponseReader::construct(MemReaderFakeStream::new(bytes!("\
+fn read_response(request: RequestWriter<MockStream>) -> ResponseReader<MockStream> {
+    match request.read_response() {
+        Ok(response) => response,
+        Err((_, err)) => fail!("failed to read response: {}", err),
+    }
+}
+
+#[test]
+fn test_request_and_response() {
+    let server = MockServer::new();
+    server.respond(MockResponse::new("\
 HTTP/1.1 200 OK\r\n\
 ETag: W/\"it's an entity-tag!\"\r\n\
 Content-Length: 28\r\n\
 \r\n\
-And here's the request body.")), request);
+And here's the request body."));
+
+    let mut request = RequestWriter::new(Post, server.url("/submit?x=1")).unwrap();
+    request.headers.content_length = Some(5);
+    request.write(bytes!("Hello")).unwrap();
+    let mut response = read_response(request);
+    assert_eq!(response.status, status::Ok);
+    assert_eq!(str::from_utf8(response.read_to_end().unwrap()),
+               Some("And here's the request body."));
+
+    let sent = server.requests();
+    let sent = str::from_utf8(sent.get(0).as_slice()).unwrap();
+    assert!(sent.starts_with("POST /submit?x=1 HTTP/1.1\r\n"));
+    assert!(sent.contains("\r\nHost: 192.0.2.1:"));
+    assert!(sent.contains("\r\nContent-Length: 5\r\n"));
+    assert!(sent.ends_with("\r\n\r\nHello"));
+}
+
+#[test]
+fn test_chunked_response() {
+    let server = MockServer::new();
+    let mut mock = MockResponse::new("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n");
+    mock.send_chunked(["Hello", ", ", "world!"]);
+    server.respond(mock);
+
+    let mut response = read_response(RequestWriter::new(Get, server.url("/")).unwrap());
+    assert_eq!(str::from_utf8(response.read_to_end().unwrap()), Some("Hello, world!"));
+}
+
+#[test]
+fn test_slow_response() {
+    let server = MockServer::new();
+    let mut mock = MockResponse::new("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n");
+    mock.pause(1_000);
+    mock.send(bytes!("Hello"));
+    server.respond(mock);
+
+    let mut request = RequestWriter::new(Get, server.url("/")).unwrap();
+    request.read_timeout_ms = Some(10);
+    let mut response = read_response(request);
+    assert_eq!(response.status, status::Ok);
+    assert_eq!(response.read_to_end().unwrap_err().kind, TimedOut);
+    assert_eq!(response.request.error, Some(Timeout(ReadTimeout)));
+}
+
+#[test]
+fn test_truncated_response() {
+    let server = MockServer::new();
+    server.respond(MockResponse::new("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nHello"));
+
+    let mut response = read_response(RequestWriter::new(Get, server.url("/")).unwrap());
+    assert!(response.read_to_end().is_err());
+    assert_eq!(response.request.error, Some(Truncated));
+}
+
+#[test]
+fn test_malformed_response() {
+    let server = MockServer::new();
+    server.respond(MockResponse::new("HTTP/1.1 2000 OK\r\n\r\n"));
+    server.respond(MockResponse::new("HTTP/1.1 200 OK\r\nContent/Length: 5\r\n\r\nHello"));
+    server.respond(MockResponse::new("HTTP/1.1 200 OK\r\nContent-Len"));
+
+    let response_err = || {
+        let request: RequestWriter<MockStream> = RequestWriter::new(Get, server.url("/")).unwrap();
+        match request.read_response() {
+            Ok(_) => fail!("expected an error"),
+            Err((request, _)) => request.error.unwrap(),
+        }
+    };
+    assert_eq!(response_err(), MalformedStatusLine);
+    assert_eq!(response_err(), MalformedHeader(~"Content/", ~""));
+    assert_eq!(response_err(), Truncated);
+}
+
+#[test]
+fn test_relative_redirect() {
+    let server = MockServer::new();
+    server.respond(MockResponse::new("HTTP/1.1 302 Found\r\nLocation: ../final?x=1\r\n\
+                                      Content-Length: 0\r\n\r\n"));
+    server.respond(MockResponse::new("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok"));
+
+    let request = RequestWriter::new(Get, server.url("/a/b/start")).unwrap();
+    let mut response = RedirectPolicy::new().send(request, []).unwrap();
+    assert_eq!(response.status, status::Ok);
+    assert_eq!(response.redirect_chain.len(), 1);
+    assert_eq!(response.request.url.path, ~"/a/final");
+    assert_eq!(str::from_utf8(response.read_to_end().unwrap()), Some("ok"));
+}
+
+#[test]
+fn test_persistent_connection() {
+    let server = MockServer::new();
+    server.respond(MockResponse::new("HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\none\
+                                      HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\ntwo"));
+    let pool = ConnectionPool::new();
+
+    for expected in ["one", "two"].iter() {
+        let request = RequestWriter::new_with_pool(Get, server.url("/"), pool.clone()).unwrap();
+        let mut response = read_response(request);
+        assert_eq!(str::from_utf8(response.read_to_end().unwrap()), Some(*expected));
+    }
+    assert_eq!(server.connection_count(), 1);
+}
+
+#[test]
+fn test_retry_after_reset() {
+    let server = MockServer::new();
+    let mut reset = MockResponse::empty();
+    reset.fail(ConnectionReset);
+    server.respond(reset);
+    server.respond(MockResponse::new("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"));
+
+    let mut policy = RetryPolicy::new();
+    policy.initial_backoff_ms = 1;
+    let request = RequestWriter::new(Get, server.url("/")).unwrap();
+    let response = policy.send(request, []).unwrap();
+    assert_eq!(response.status, status::Ok);
+    assert_eq!(server.connection_count(), 2);
+}
+
+#[test]
+fn test_session() {
+    let server = MockServer::new();
+    let mut mock = MockResponse::new(format!("HTTP/1.1 302 Found\r\nLocation: {}\r\n\
+                                              Set-Cookie: id=1\r\nContent-Length: 0\r\n\r\n",
+                                             server.url("/api/v2/")).as_slice());
+    mock.send(bytes!("HTTP/1.1 200 OK\r\n\
+                      Content-Type: text/plain; charset=ISO-8859-1\r\n\
+                      Content-Length: 4\r\n\r\ncaf", 0xe9));
+    server.respond(mock);
+
+    let mut session: Session<MockStream> = Session::new();
+    session.base_url = Some(server.url("/api/v1/"));
+    let response = session.get("things").unwrap();
+    assert!(response.is_success());
+    assert_eq!(response.redirect_chain.len(), 1);
+    assert_eq!(response.text().unwrap(), ~"café");
+
+    // Both requests went on the one connection
+    let sent = server.requests();
+    assert_eq!(sent.len(), 1);
+    let sent = str::from_utf8(sent.get(0).as_slice()).unwrap();
+    let second = sent.find_str("GET /api/v2/ HTTP/1.1\r\n").unwrap();
+    assert!(sent.starts_with("GET /api/v1/things HTTP/1.1\r\n"));
+    assert!(sent.slice_from(second).contains("\r\nCookie: id=1\r\n"));
+}
+
+#[test]
+fn test_cache() {
+    let server = MockServer::new();
+    server.respond(MockResponse::new("\
+HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nContent-Length: 3\r\n\r\none\
+HTTP/1.1 200 OK\r\nCache-Control: no-cache\r\nETag: \"2\"\r\nContent-Length: 3\r\n\r\ntwo\
+HTTP/1.1 304 Not Modified\r\nETag: \"2\"\r\n\r\n"));
+
+    let mut session: Session<MockStream> = Session::new();
+    session.cache = Some(Cache::in_memory());
+    let fresh = server.url("/fresh").to_str();
+    let revalidated = server.url("/revalidated").to_str();
+    assert_eq!(session.get(fresh).unwrap().bytes(), bytes!("one"));
+    assert_eq!(session.get(fresh).unwrap().bytes(), bytes!("one"));
+    assert_eq!(session.get(revalidated).unwrap().bytes(), bytes!("two"));
+    let response = session.get(revalidated).unwrap();
+    assert_eq!(response.status, status::Ok);
+    assert_eq!(response.bytes(), bytes!("two"));
+
+    // The fresh response was not asked for again; the other was, conditionally
+    let sent = server.requests();
+    let sent = str::from_utf8(sent.get(0).as_slice()).unwrap();
+    let requests: Vec<&str> = sent.split_str("\r\n\r\n").filter(|r| r.len() > 0).collect();
+    assert_eq!(requests.len(), 3);
+    assert!(requests.get(0).starts_with("GET /fresh HTTP/1.1\r\n"));
+    assert!(requests.get(1).starts_with("GET /revalidated HTTP/1.1\r\n"));
+    assert!(!requests.get(1).contains("If-None-Match"));
+    assert!(requests.get(2).contains("\r\nIf-None-Match: \"2\""));
 }
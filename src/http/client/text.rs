@@ -0,0 +1,365 @@
+/*!
+
+Reading text in whatever character set it comes in as UTF-8.
+
+A `TextReader` wraps a `Reader` of text in some character set, and is itself a `Reader` of that
+text in UTF-8, so that `read_to_str` and friends work as they should. The character set is taken,
+in order of preference, from the `charset` parameter of the media type, from a byte order mark at
+the start of the text, or from a default:
+
+```rust
+let response: ResponseReader<TcpStream>;
+
+// As the Content-Type header of the response says
+let text = response.into_text().unwrap().read_to_str().unwrap();
+```
+
+The character sets understood are UTF-8, UTF-16 (either way round) and ISO-8859-1; any other is an
+error, rather than something to be guessed at.
+
+*/
+
+use std::ascii::StrAsciiExt;
+use std::char;
+use std::cmp::min;
+use std::fmt;
+use std::io;
+use std::io::{IoError, IoResult, InvalidInput};
+use std::str;
+
+use headers::content_type::MediaType;
+
+/// A character set which a `TextReader` can decode.
+#[deriving(Eq, Clone)]
+pub enum Charset {
+    Utf8,
+    /// UTF-16, little-endian
+    Utf16Le,
+    /// UTF-16, big-endian
+    Utf16Be,
+    /// ISO-8859-1 (Latin-1), in which each byte is the code point of the same value. US-ASCII, a
+    /// subset of it, is read as this.
+    Iso88591,
+}
+
+impl Charset {
+    /// The character set with the given name (case insensitive), if it is one of those understood.
+    /// Plain "UTF-16" is taken as big-endian, as RFC 2781 says, unless a byte order mark says
+    /// otherwise.
+    pub fn from_label(label: &str) -> Option<Charset> {
+        match label.trim().to_ascii_lower().as_slice() {
+            "utf-8" | "utf8" | "unicode-1-1-utf-8" => Some(Utf8),
+            "utf-16le" => Some(Utf16Le),
+            "utf-16be" | "utf-16" => Some(Utf16Be),
+            "iso-8859-1" | "iso8859-1" | "iso_8859-1" | "latin1" | "l1" | "us-ascii" | "ascii" =>
+                Some(Iso88591),
+            _ => None,
+        }
+    }
+
+    /// The byte order mark of the character set, if it has one.
+    fn bom(&self) -> &'static [u8] {
+        match *self {
+            Utf8 => &[0xef, 0xbb, 0xbf],
+            Utf16Le => &[0xff, 0xfe],
+            Utf16Be => &[0xfe, 0xff],
+            Iso88591 => &[],
+        }
+    }
+}
+
+impl fmt::Show for Charset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.buf.write(match *self {
+            Utf8 => "UTF-8".as_bytes(),
+            Utf16Le => "UTF-16LE".as_bytes(),
+            Utf16Be => "UTF-16BE".as_bytes(),
+            Iso88591 => "ISO-8859-1".as_bytes(),
+        })
+    }
+}
+
+fn unsupported_charset_err(label: &str) -> IoError {
+    IoError {
+        kind: InvalidInput,
+        desc: "Unsupported character set",
+        detail: Some(label.to_owned()),
+    }
+}
+
+fn malformed_text_err(charset: Charset) -> IoError {
+    IoError {
+        kind: InvalidInput,
+        desc: "Malformed text",
+        detail: Some(format!("invalid {}", charset)),
+    }
+}
+
+/// The character set given by the `charset` parameter of the media type, if there is one; it is
+/// an error if it is not one of those understood.
+pub fn media_type_charset(media_type: &MediaType) -> IoResult<Option<Charset>> {
+    for &(ref name, ref value) in media_type.parameters.iter() {
+        if name.eq_ignore_ascii_case("charset") {
+            return match Charset::from_label(*value) {
+                Some(charset) => Ok(Some(charset)),
+                None => Err(unsupported_charset_err(*value)),
+            };
+        }
+    }
+    Ok(None)
+}
+
+/// The character set to assume for text of the media type when neither a `charset` parameter nor
+/// a byte order mark says: ISO-8859-1 for `text/*` (RFC 2616, §3.7.1), and UTF-8 for anything else
+/// (JSON, for example).
+pub fn default_charset(media_type: Option<&MediaType>) -> Charset {
+    match media_type {
+        Some(media_type) if media_type.type_.eq_ignore_ascii_case("text") => Iso88591,
+        _ => Utf8,
+    }
+}
+
+/// A `Reader` of UTF-8 text decoded from another `Reader` in some character set.
+pub struct TextReader<R> {
+    priv reader: R,
+
+    /// The character set, once it is known; without a `charset` parameter, that is once enough of
+    /// the text has been read to look for a byte order mark.
+    priv charset: Option<Charset>,
+
+    /// The character set to use if there is no byte order mark
+    priv default: Charset,
+
+    /// Bytes read but not yet decoded, being (as yet) an incomplete character
+    priv pending: Vec<u8>,
+
+    /// Text decoded but not yet read, as UTF-8
+    priv decoded: Vec<u8>,
+
+    /// How much of `decoded` has been read
+    priv pos: uint,
+
+    /// Whether the end of `reader` has been reached
+    priv at_end: bool,
+
+    /// Whether decoding has begun, the character set having been determined and any byte order
+    /// mark dropped
+    priv started: bool,
+}
+
+impl<R: Reader> TextReader<R> {
+    /// A reader of text in the given character set, or if that is `None`, whatever a byte order
+    /// mark says, or failing that, `default`.
+    pub fn new(reader: R, charset: Option<Charset>, default: Charset) -> TextReader<R> {
+        TextReader {
+            reader: reader,
+            charset: charset,
+            default: default,
+            pending: Vec::new(),
+            decoded: Vec::new(),
+            pos: 0,
+            at_end: false,
+            started: false,
+        }
+    }
+
+    /// A reader of text of the given media type (such as from a Content-Type header), in the
+    /// character set of its `charset` parameter, or failing that, as `new` with the default from
+    /// `default_charset`. An unsupported `charset` is an error.
+    pub fn for_media_type(reader: R, media_type: Option<&MediaType>) -> IoResult<TextReader<R>> {
+        let charset = match media_type {
+            Some(media_type) => try!(media_type_charset(media_type)),
+            None => None,
+        };
+        Ok(TextReader::new(reader, charset, default_charset(media_type)))
+    }
+
+    /// The character set being decoded, if it has yet been determined.
+    pub fn charset(&self) -> Option<Charset> {
+        self.charset
+    }
+
+    /// Unwrap the underlying reader.
+    pub fn unwrap(self) -> R {
+        self.reader
+    }
+
+    /// Read more of the underlying reader into `pending`, and decode what can be decoded.
+    fn fill(&mut self) -> IoResult<()> {
+        let mut buf = [0u8, ..4096];
+        match self.reader.read(buf) {
+            Ok(len) => self.pending.push_all(buf.slice_to(len)),
+            Err(ref err) if err.kind == io::EndOfFile => self.at_end = true,
+            Err(err) => return Err(err),
+        }
+
+        if !self.started {
+            // The longest byte order mark is three bytes
+            if self.pending.len() < 3 && !self.at_end {
+                return Ok(());
+            }
+            if self.charset.is_none() {
+                let sniffed = [Utf8, Utf16Le, Utf16Be].iter().map(|c| *c).find(|charset| {
+                    self.pending.as_slice().starts_with(charset.bom())
+                });
+                self.charset = Some(sniffed.unwrap_or(self.default));
+            }
+            // A byte order mark is not part of the text
+            let bom = self.charset.unwrap().bom();
+            if bom.len() > 0 && self.pending.as_slice().starts_with(bom) {
+                self.pending = Vec::from_slice(self.pending.as_slice().slice_from(bom.len()));
+            }
+            self.started = true;
+        }
+
+        let charset = self.charset.unwrap();
+        let used = try!(decode(charset, self.pending.as_slice(), &mut self.decoded));
+        self.pending = Vec::from_slice(self.pending.as_slice().slice_from(used));
+        if self.at_end && self.pending.len() > 0 {
+            // A character cut short
+            return Err(malformed_text_err(charset));
+        }
+        Ok(())
+    }
+}
+
+/// Decode as much of `input` as makes complete characters onto the end of `output`, returning how
+/// many bytes of `input` that was.
+fn decode(charset: Charset, input: &[u8], output: &mut Vec<u8>) -> IoResult<uint> {
+    match charset {
+        Utf8 => {
+            // Leave off any incomplete sequence at the end
+            let mut end = input.len();
+            for back in range(1u, 4).filter(|&back| back <= input.len()) {
+                let b = input[input.len() - back];
+                if b & 0xc0 == 0x80 {
+                    // A continuation byte; look further back for the start
+                    continue;
+                }
+                let len = match b {
+                    0x00..0x7f => 1,
+                    0xc0..0xdf => 2,
+                    0xe0..0xef => 3,
+                    _ => 4,
+                };
+                if len > back {
+                    end = input.len() - back;
+                }
+                break;
+            }
+            let complete = input.slice_to(end);
+            if !str::is_utf8(complete) {
+                return Err(malformed_text_err(Utf8));
+            }
+            output.push_all(complete);
+            Ok(end)
+        },
+        Utf16Le | Utf16Be => {
+            let mut units = Vec::with_capacity(input.len() / 2);
+            for pair in input.chunks(2).filter(|pair| pair.len() == 2) {
+                units.push(if charset == Utf16Le {
+                    pair[0] as u16 | pair[1] as u16 << 8
+                } else {
+                    pair[0] as u16 << 8 | pair[1] as u16
+                });
+            }
+            // A high surrogate at the end waits for its partner
+            match units.last() {
+                Some(&unit) if unit >= 0xd800 && unit < 0xdc00 => { units.pop(); },
+                _ => (),
+            }
+            match str::from_utf16(units.as_slice()) {
+                Some(text) => output.push_all(text.as_bytes()),
+                None => return Err(malformed_text_err(charset)),
+            }
+            Ok(units.len() * 2)
+        },
+        Iso88591 => {
+            let mut text = str::with_capacity(input.len());
+            for &b in input.iter() {
+                text.push_char(char::from_u32(b as u32).unwrap());
+            }
+            output.push_all(text.as_bytes());
+            Ok(input.len())
+        },
+    }
+}
+
+impl<R: Reader> Reader for TextReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        while self.pos == self.decoded.len() {
+            if self.at_end {
+                return Err(io::standard_error(io::EndOfFile));
+            }
+            self.decoded.clear();
+            self.pos = 0;
+            try!(self.fill());
+        }
+        let available = self.decoded.as_slice().slice_from(self.pos);
+        let len = min(buf.len(), available.len());
+        buf.mut_slice_to(len).copy_from(available.slice_to(len));
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::MemReader;
+    use headers::content_type::MediaType;
+    use super::{Charset, Utf8, Utf16Le, Utf16Be, Iso88591, TextReader, default_charset};
+
+    fn read(bytes: &[u8], charset: Option<Charset>, default: Charset) -> Option<~str> {
+        let mut reader = TextReader::new(MemReader::new(bytes.to_owned()), charset, default);
+        reader.read_to_str().ok()
+    }
+
+    #[test]
+    fn test_from_label() {
+        assert_eq!(Charset::from_label("UTF-8"), Some(Utf8));
+        assert_eq!(Charset::from_label("utf-16"), Some(Utf16Be));
+        assert_eq!(Charset::from_label("Latin1"), Some(Iso88591));
+        assert_eq!(Charset::from_label("Shift_JIS"), None);
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(read(bytes!("caf", 0xc3, 0xa9), Some(Utf8), Iso88591), Some(~"café"));
+        assert_eq!(read(bytes!("caf", 0xe9), Some(Iso88591), Utf8), Some(~"café"));
+        assert_eq!(read([0x63, 0, 0xe9, 0, 0x3d, 0xd8, 0x00, 0xde], Some(Utf16Le), Utf8),
+                   Some(~"cé😀"));
+        assert_eq!(read([0, 0x63, 0, 0xe9, 0xd8, 0x3d, 0xde, 0x00], Some(Utf16Be), Utf8),
+                   Some(~"cé😀"));
+        assert_eq!(read([], None, Utf8), Some(~""));
+
+        assert_eq!(read(bytes!("caf", 0xe9), Some(Utf8), Utf8), None);
+        assert_eq!(read(bytes!("caf", 0xc3), Some(Utf8), Utf8), None);
+        assert_eq!(read([0x63, 0, 0xe9], Some(Utf16Le), Utf8), None);
+        assert_eq!(read([0x3d, 0xd8], Some(Utf16Le), Utf8), None);
+    }
+
+    #[test]
+    fn test_bom() {
+        assert_eq!(read([0xef, 0xbb, 0xbf, 0x63], None, Iso88591), Some(~"c"));
+        assert_eq!(read([0xff, 0xfe, 0x63, 0], None, Iso88591), Some(~"c"));
+        assert_eq!(read([0xfe, 0xff, 0, 0x63], None, Iso88591), Some(~"c"));
+        assert_eq!(read([0xe9], None, Iso88591), Some(~"é"));
+        // A charset parameter beats the byte order mark, but one which agrees is still dropped
+        assert_eq!(read([0xef, 0xbb, 0xbf, 0x63], Some(Utf8), Utf8), Some(~"c"));
+        assert_eq!(read([0xff, 0xfe], Some(Iso88591), Utf8), Some(~"ÿþ"));
+    }
+
+    #[test]
+    fn test_for_media_type() {
+        let media_type = MediaType(~"text", ~"plain", vec!((~"Charset", ~"UTF-16LE")));
+        let reader = TextReader::for_media_type(MemReader::new(~[]), Some(&media_type)).unwrap();
+        assert_eq!(reader.charset(), Some(Utf16Le));
+
+        let media_type = MediaType(~"text", ~"plain", vec!((~"charset", ~"EBCDIC")));
+        assert!(TextReader::for_media_type(MemReader::new(~[]), Some(&media_type)).is_err());
+
+        assert_eq!(default_charset(Some(&MediaType(~"text", ~"html", Vec::new()))), Iso88591);
+        assert_eq!(default_charset(Some(&MediaType(~"application", ~"json", Vec::new()))), Utf8);
+        assert_eq!(default_charset(None), Utf8);
+    }
+}
@@ -6,18 +6,12 @@
  * TODO: refactor all this to store things in more usefully categorised places.
  */
 use std::num::{Zero, cast};
-use std::io::{IoError, IoResult, OtherIoError};
 #[cfg(test)]
 use std::io::MemReader;
-
-// XXX: IoError ain't a good representation of this.
-fn bad_input() -> IoError {
-    IoError {
-        kind: OtherIoError,
-        desc: "invalid number",
-        detail: None,
-    }
-}
+use serialize::base64::{ToBase64, STANDARD};
+use time::{Tm, strptime};
+use rfc2616::{CR, LF};
+use error::{HttpResult, Io, MalformedNumber, InvalidChunk};
 
 static ASCII_ZERO: u8 = '0' as u8;
 static ASCII_NINE: u8 = '9' as u8;
@@ -37,15 +31,15 @@ static ASCII_UPPER_F: u8 = 'F' as u8;
  *
  * # Return value
  *
- * - `None`, if the number overflows;
- * - `None`, if there is a leading zero;
- * - `None`, if all the digits are read and the `expected_end` function is false
+ * - `Err(MalformedNumber)`, if the number overflows;
+ * - `Err(MalformedNumber)`, if all the digits are read and the `expected_end` function is false;
+ * - `Err(Io(err))`, if reading fails.
  *
- * Should everything work as designed (i.e. none of these conditions occur) a `Some` is returned.
+ * Should everything work as designed (i.e. none of these conditions occur) an `Ok` is returned.
  */
 pub fn read_decimal<R: Reader, N: Unsigned + NumCast + Ord + CheckedMul + CheckedAdd>
                    (reader: &mut R, expected_end: |u8| -> bool)
-                   -> IoResult<N> {
+                   -> HttpResult<N> {
     // Here and in `read_hexadecimal` there is the possibility of infinite sequence of zeroes. The
     // spec allows this, but it may not be a good thing to allow. It's not a particularly good
     // attack surface, though, because of the low return.
@@ -61,12 +55,12 @@ pub fn read_decimal<R: Reader, N: Unsigned + NumCast + Ord + CheckedMul + Checke
                 match n.checked_mul(&ten).and_then(
                         |n| n.checked_add(&cast(b - ASCII_ZERO).unwrap())) {
                     Some(new_n) => new_n,
-                    None => return Err(bad_input()),  // overflow
+                    None => return Err(MalformedNumber),  // overflow
                 }
             },
             Ok(b) if got_content && expected_end(b) => return Ok(n),
-            Ok(_) => return Err(bad_input()),  // not a valid number
-            Err(err) => return Err(err),  // I/O error
+            Ok(_) => return Err(MalformedNumber),  // not a valid number
+            Err(err) => return Err(Io(err)),  // I/O error
         };
         got_content = true;
     }
@@ -83,15 +77,15 @@ pub fn read_decimal<R: Reader, N: Unsigned + NumCast + Ord + CheckedMul + Checke
  *
  * # Return value
  *
- * - `None`, if the number overflows;
- * - `None`, if there is a leading zero;
- * - `None`, if all the digits are read and the `expected_end` function is false
+ * - `Err(MalformedNumber)`, if the number overflows;
+ * - `Err(MalformedNumber)`, if all the digits are read and the `expected_end` function is false;
+ * - `Err(Io(err))`, if reading fails.
  *
- * Should everything work as designed (i.e. none of these conditions occur) a `Some` is returned.
+ * Should everything work as designed (i.e. none of these conditions occur) an `Ok` is returned.
  */
 pub fn read_hexadecimal<R: Reader, N: Unsigned + NumCast + Ord + CheckedMul + CheckedAdd>
                        (reader: &mut R, expected_end: |u8| -> bool)
-                       -> IoResult<N> {
+                       -> HttpResult<N> {
     let mut n: N = Zero::zero();
     let mut got_content = false;
     let sixteen: N = cast(16).unwrap();
@@ -101,26 +95,26 @@ pub fn read_hexadecimal<R: Reader, N: Unsigned + NumCast + Ord + CheckedMul + Ch
                 match n.checked_mul(&sixteen).and_then(
                         |n| n.checked_add(&cast(b - ASCII_ZERO).unwrap())) {
                     Some(new_n) => new_n,
-                    None => return Err(bad_input()),  // overflow
+                    None => return Err(MalformedNumber),  // overflow
                 }
             },
             Ok(b@ASCII_LOWER_A..ASCII_LOWER_F) => {
                 match n.checked_mul(&sixteen).and_then(
                         |n| n.checked_add(&cast(b - ASCII_LOWER_A + 10).unwrap())) {
                     Some(new_n) => new_n,
-                    None => return Err(bad_input()),  // overflow
+                    None => return Err(MalformedNumber),  // overflow
                 }
             },
             Ok(b@ASCII_UPPER_A..ASCII_UPPER_F) => {
                 match n.checked_mul(&sixteen).and_then(
                         |n| n.checked_add(&cast(b - ASCII_UPPER_A + 10).unwrap())) {
                     Some(new_n) => new_n,
-                    None => return Err(bad_input()),  // overflow
+                    None => return Err(MalformedNumber),  // overflow
                 }
             },
             Ok(b) if got_content && expected_end(b) => return Ok(n),
-            Ok(_) => return Err(bad_input()),  // not a valid number
-            Err(err) => return Err(err),  // I/O error
+            Ok(_) => return Err(MalformedNumber),  // not a valid number
+            Err(err) => return Err(Io(err)),  // I/O error
         };
         got_content = true;
     }
@@ -137,29 +131,30 @@ pub fn read_hexadecimal<R: Reader, N: Unsigned + NumCast + Ord + CheckedMul + Ch
  *
  * # Return value
  *
- * - `None`, if the HTTP-Version is malformed in any way;
- * - `None`, if the `expected_end` function returns false;
- * - A `Some`, if all goes well.
+ * - `Err(MalformedNumber)`, if the HTTP-Version is malformed in any way;
+ * - `Err(MalformedNumber)`, if the `expected_end` function returns false;
+ * - `Err(Io(err))`, if reading fails;
+ * - An `Ok`, if all goes well.
  */
 #[inline]
 pub fn read_http_version<R: Reader>
                         (reader: &mut R, expected_end: |u8| -> bool)
-                        -> IoResult<(uint, uint)> {
+                        -> HttpResult<(uint, uint)> {
     // I'd read into a [0u8, ..5], but that buffer is not guaranteed to be
     // filled, so I must read it byte by byte to guarantee correctness.
     // (Sure, no sane person/library would send the first packet with "HTT"
     // and leave the "P/1.1" to the next packet, but it's *possible*.)
-    let b0 = try!(reader.read_byte());
-    let b1 = try!(reader.read_byte());
-    let b2 = try!(reader.read_byte());
-    let b3 = try!(reader.read_byte());
-    let b4 = try!(reader.read_byte());
+    let b0 = try!(reader.read_byte().map_err(Io));
+    let b1 = try!(reader.read_byte().map_err(Io));
+    let b2 = try!(reader.read_byte().map_err(Io));
+    let b3 = try!(reader.read_byte().map_err(Io));
+    let b4 = try!(reader.read_byte().map_err(Io));
     if (b0 != 'h' as u8 && b0 != 'H' as u8) ||
        (b1 != 't' as u8 && b1 != 'T' as u8) ||
        (b2 != 't' as u8 && b2 != 'T' as u8) ||
        (b3 != 'p' as u8 && b3 != 'P' as u8) ||
        b4 != '/' as u8 {
-        return Err(bad_input());
+        return Err(MalformedNumber);
     }
 
     let major = try!(read_decimal(reader, |b| b == '.' as u8));
@@ -167,6 +162,65 @@ pub fn read_http_version<R: Reader>
     Ok((major, minor))
 }
 
+/**
+ * Read the chunk-size line of a chunk in the chunked transfer-coding (RFC 2616, §3.6.1), up to and
+ * including the CRLF which terminates it.
+ *
+ *     chunk           = chunk-size [ chunk-extension ] CRLF
+ *                       chunk-data CRLF
+ *     chunk-size      = 1*HEX
+ *     chunk-extension = *( ";" chunk-ext-name [ "=" chunk-ext-val ] )
+ *
+ * Chunk extensions are not understood by anything here, so they are skipped over.
+ *
+ * # Return value
+ *
+ * The size of the chunk-data which follows; zero indicates the last-chunk, after which come the
+ * trailer and a final CRLF.
+ */
+pub fn read_chunk_size<R: Reader>(reader: &mut R) -> HttpResult<uint> {
+    let mut next = 0u8;
+    let size = match read_hexadecimal(reader, |b| {
+        next = b;
+        b == CR || b == LF || b == ';' as u8
+    }) {
+        Ok(size) => size,
+        Err(MalformedNumber) => return Err(InvalidChunk),
+        Err(err) => return Err(err),
+    };
+    // Whatever lies between here and the LF is chunk-extension (or the CR of the CRLF).
+    while next != LF {
+        next = try!(reader.read_byte().map_err(Io));
+    }
+    Ok(size)
+}
+
+/**
+ * Read the CRLF which follows the chunk-data of a chunk (RFC 2616, §3.6.1).
+ *
+ * As with header lines, a lone LF is also accepted.
+ */
+pub fn read_chunk_end<R: Reader>(reader: &mut R) -> HttpResult<()> {
+    let mut b = try!(reader.read_byte().map_err(Io));
+    if b == CR {
+        b = try!(reader.read_byte().map_err(Io));
+    }
+    if b == LF {
+        Ok(())
+    } else {
+        Err(InvalidChunk)
+    }
+}
+
+/**
+ * The credentials for the Basic authentication scheme (RFC 2617, §2), as sent in the Authorization
+ * and Proxy-Authorization headers: "Basic " and the base64 encoding of `user:password`.
+ */
+pub fn basic_credentials(user: &str, password: Option<&str>) -> ~str {
+    let user_pass = format!("{}:{}", user, password.unwrap_or(""));
+    format!("Basic {}", user_pass.as_bytes().to_base64(STANDARD))
+}
+
 // I couldn't think what to call it. Ah well. It's just trivial syntax sugar, anyway.
 macro_rules! test_reads {
     ($func:ident $($value:expr => $expected:expr),*) => {{
@@ -179,6 +233,34 @@ macro_rules! test_reads {
     }}
 }
 
+/// Parse an HTTP-date (RFC 2616, §3.3.1), in any of the three formats which it may take, for a
+/// header which is not parsed as a `Tm` already (Retry-After, which may be a number instead, or
+/// Expires, where an invalid date is to be taken as being in the past).
+pub fn parse_http_date(value: &str) -> Option<Tm> {
+    static FORMATS: &'static [&'static str] = &[
+        "%a, %d %b %Y %T GMT",  // RFC 1123
+        "%A, %d-%b-%y %T GMT",  // RFC 850
+        "%c",                   // asctime()
+    ];
+    let value = value.trim();
+    for format in FORMATS.iter() {
+        match strptime(value, *format) {
+            Ok(tm) => return Some(tm),
+            Err(_) => (),
+        }
+    }
+    None
+}
+
+/// Remove the square brackets from around an IPv6 address in a URL, if it has them.
+pub fn strip_brackets<'a>(host: &'a str) -> &'a str {
+    if host.starts_with("[") && host.ends_with("]") {
+        host.slice(1, host.len() - 1)
+    } else {
+        host
+    }
+}
+
 #[test]
 fn test_read_http_version() {
     test_reads!(http_version
@@ -244,3 +326,49 @@ fn test_read_hexadecimal() {
                 "100\0" => Some(0x100u16)
     );
 }
+
+#[test]
+fn test_read_chunk_size() {
+    fn read(s: &str) -> Option<uint> {
+        read_chunk_size(&mut MemReader::new(s.as_bytes().into_owned())).ok()
+    }
+    assert_eq!(read("0\r\n"), Some(0));
+    assert_eq!(read("1a\r\n"), Some(0x1a));
+    assert_eq!(read("1A\n"), Some(0x1a));
+    assert_eq!(read("10;name=value\r\n"), Some(0x10));
+    assert_eq!(read("10;name=\"quoted value\";other\r\n"), Some(0x10));
+
+    // No chunk-size
+    assert_eq!(read("\r\n"), None);
+    // Not hexadecimal
+    assert_eq!(read("1g\r\n"), None);
+    // End of stream before the end of the line
+    assert_eq!(read("10;name"), None);
+}
+
+#[test]
+fn test_read_chunk_end() {
+    fn read(s: &str) -> bool {
+        read_chunk_end(&mut MemReader::new(s.as_bytes().into_owned())).is_ok()
+    }
+    assert!(read("\r\n"));
+    assert!(read("\n"));
+    assert!(!read("x\r\n"));
+    assert!(!read("\r"));
+    assert!(!read(""));
+}
+
+#[test]
+fn test_basic_credentials() {
+    assert_eq!(basic_credentials("Aladdin", Some("open sesame")),
+               ~"Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==");
+    assert_eq!(basic_credentials("user", None), ~"Basic dXNlcjo=");
+}
+
+#[test]
+fn test_parse_http_date() {
+    let sec = |value: &str| parse_http_date(value).map(|tm| tm.to_timespec().sec);
+    assert_eq!(sec("Sun, 06 Nov 1994 08:49:37 GMT"), Some(784111777));
+    assert_eq!(sec("Sunday, 06-Nov-94 08:49:37 GMT"), Some(784111777));
+    assert_eq!(sec("0"), None);
+}
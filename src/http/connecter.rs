@@ -1,8 +1,14 @@
 // The spelling "Connecter" is deliberate, by the way.
 
-use std::io::IoResult;
-use std::io::net::ip::SocketAddr;
+use std::ascii::StrAsciiExt;
+use std::io::{IoError, IoResult, OtherIoError, InvalidInput};
+use std::io::net::get_host_addresses;
+use std::io::net::ip::{SocketAddr, IpAddr};
 use std::io::net::tcp::TcpStream;
+use std::io::net::unix::UnixStream;
+use collections::hashmap::HashMap;
+use sync::{Arc, Mutex};
+use time::precise_time_ns;
 
 /// A trait for the concept of opening a stream connected to a IP socket address.
 ///
@@ -11,10 +17,276 @@ use std::io::net::tcp::TcpStream;
 /// than in terms of `TcpStream` only. This is handy for testing and for SSL.
 pub trait Connecter {
     fn connect(addr: SocketAddr) -> IoResult<Self>;
+
+    /// Connect, failing with a `TimedOut` error if the connection has not been established within
+    /// `timeout_ms` milliseconds. Connecters which cannot time out simply connect.
+    fn connect_timeout(addr: SocketAddr, _timeout_ms: u64) -> IoResult<Self> {
+        Connecter::connect(addr)
+    }
+
+    /// Make reads fail with a `TimedOut` error if they have not completed within `timeout_ms`
+    /// milliseconds from now, or with `None`, wait for them indefinitely. Connecters which cannot
+    /// time out ignore this.
+    fn set_read_timeout(&mut self, _timeout_ms: Option<u64>) {
+    }
+
+    /// Connect to the Unix domain socket at the given path. Connecters which only speak IP fail
+    /// with an `InvalidInput` error.
+    fn connect_unix(path: &Path) -> IoResult<Self> {
+        Err(unix_unsupported_err(path))
+    }
+}
+
+fn unix_unsupported_err(path: &Path) -> IoError {
+    IoError {
+        kind: InvalidInput,
+        desc: "this connecter cannot connect to a Unix domain socket",
+        detail: Some(path.display().to_str()),
+    }
 }
 
 impl Connecter for TcpStream {
     fn connect(addr: SocketAddr) -> IoResult<TcpStream> {
         TcpStream::connect(addr)
     }
+
+    fn connect_timeout(addr: SocketAddr, timeout_ms: u64) -> IoResult<TcpStream> {
+        TcpStream::connect_timeout(addr, timeout_ms)
+    }
+
+    fn set_read_timeout(&mut self, timeout_ms: Option<u64>) {
+        self.set_read_timeout(timeout_ms)
+    }
+}
+
+/// Connections to a Unix domain socket, as made for an `http+unix` URL; see
+/// `RequestWriter::new_unix`.
+impl Connecter for UnixStream {
+    fn connect(addr: SocketAddr) -> IoResult<UnixStream> {
+        Err(IoError {
+            kind: InvalidInput,
+            desc: "a Unix domain socket needs a path, not an IP address",
+            detail: Some(addr.to_str()),
+        })
+    }
+
+    fn set_read_timeout(&mut self, timeout_ms: Option<u64>) {
+        self.set_read_timeout(timeout_ms)
+    }
+
+    fn connect_unix(path: &Path) -> IoResult<UnixStream> {
+        UnixStream::connect(path)
+    }
+}
+
+/// A trait for the concept of looking up the IP addresses of a host name.
+///
+/// As with `Connecter`, this lets the client be used with something other than the system's
+/// resolver: to pin host names to particular addresses in tests, or to cache lookups.
+pub trait Resolver {
+    /// Look up the IP addresses of the host, in order of preference. Along with them comes the
+    /// length of time (in milliseconds) that they may be cached for, if the resolver knows it.
+    fn resolve(&self, host: &str) -> IoResult<(Vec<IpAddr>, Option<u64>)>;
+}
+
+/// A resolver which can be shared between requests; see `RequestWriter::new_with_resolver`.
+pub type SharedResolver = Arc<~Resolver:Send+Share>;
+
+/// The system's resolver (`getaddrinfo`, typically). It does not know how long the addresses may
+/// be cached for.
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve(&self, host: &str) -> IoResult<(Vec<IpAddr>, Option<u64>)> {
+        let addrs = try!(get_host_addresses(host));
+        Ok((addrs.move_iter().collect(), None))
+    }
+}
+
+fn unknown_host_err(host: &str) -> IoError {
+    IoError {
+        kind: OtherIoError,
+        desc: "unknown host",
+        detail: Some(host.to_owned()),
+    }
+}
+
+/// A resolver with a fixed set of host names, optionally falling back to another resolver for
+/// any others (without which they are an error).
+pub struct StaticResolver<R> {
+    priv hosts: HashMap<~str, Vec<IpAddr>>,
+    priv fallback: Option<R>,
+}
+
+impl StaticResolver<SystemResolver> {
+    /// A resolver which knows no hosts until they are inserted, and has nothing to fall back to.
+    pub fn new() -> StaticResolver<SystemResolver> {
+        StaticResolver {
+            hosts: HashMap::new(),
+            fallback: None,
+        }
+    }
+}
+
+impl<R: Resolver> StaticResolver<R> {
+    /// A resolver which knows no hosts until they are inserted, and passes the lookup of any
+    /// others on to `fallback`.
+    pub fn with_fallback(fallback: R) -> StaticResolver<R> {
+        StaticResolver {
+            hosts: HashMap::new(),
+            fallback: Some(fallback),
+        }
+    }
+
+    /// Resolve the host name (which is case insensitive) to the given addresses, replacing any
+    /// which it had.
+    pub fn insert(&mut self, host: &str, addrs: Vec<IpAddr>) {
+        self.hosts.insert(host.to_ascii_lower(), addrs);
+    }
+}
+
+impl<R: Resolver> Resolver for StaticResolver<R> {
+    fn resolve(&self, host: &str) -> IoResult<(Vec<IpAddr>, Option<u64>)> {
+        match self.hosts.find(&host.to_ascii_lower()) {
+            Some(addrs) => return Ok((addrs.clone(), None)),
+            None => (),
+        }
+        match self.fallback {
+            Some(ref fallback) => fallback.resolve(host),
+            None => Err(unknown_host_err(host)),
+        }
+    }
+}
+
+/// By default, addresses are cached for a minute if the resolver doesn't say how long.
+pub static DEFAULT_CACHE_TTL_MS: u64 = 60_000;
+
+struct CachedAddresses {
+    addrs: Vec<IpAddr>,
+    /// When they expire, in nanoseconds (see `precise_time_ns`)
+    expires: u64,
+}
+
+/// A resolver which remembers the addresses from another resolver for as long as it says they may
+/// be cached, or a default time if it doesn't know. Failed lookups are not cached.
+pub struct CachingResolver<R> {
+    priv resolver: R,
+    priv default_ttl_ms: u64,
+    priv cache: Mutex<HashMap<~str, CachedAddresses>>,
+}
+
+impl<R: Resolver> CachingResolver<R> {
+    /// Cache the lookups of `resolver`, for `DEFAULT_CACHE_TTL_MS` where it doesn't say how long.
+    pub fn new(resolver: R) -> CachingResolver<R> {
+        CachingResolver::with_default_ttl(resolver, DEFAULT_CACHE_TTL_MS)
+    }
+
+    /// Cache the lookups of `resolver`, for `default_ttl_ms` milliseconds where it doesn't say how
+    /// long.
+    pub fn with_default_ttl(resolver: R, default_ttl_ms: u64) -> CachingResolver<R> {
+        CachingResolver {
+            resolver: resolver,
+            default_ttl_ms: default_ttl_ms,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Forget all the cached addresses.
+    pub fn clear(&self) {
+        self.cache.lock().clear();
+    }
+}
+
+impl<R: Resolver> Resolver for CachingResolver<R> {
+    fn resolve(&self, host: &str) -> IoResult<(Vec<IpAddr>, Option<u64>)> {
+        let host = host.to_ascii_lower();
+        let now = precise_time_ns();
+        {
+            let mut cache = self.cache.lock();
+            let expired = match cache.find(&host) {
+                Some(cached) if cached.expires > now => {
+                    return Ok((cached.addrs.clone(), Some((cached.expires - now) / 1_000_000)));
+                },
+                Some(_) => true,
+                None => false,
+            };
+            if expired {
+                cache.remove(&host);
+            }
+        }
+
+        // The lock is not held while resolving, which may take a while
+        let (addrs, ttl_ms) = try!(self.resolver.resolve(host.as_slice()));
+        let ttl_ms = ttl_ms.unwrap_or(self.default_ttl_ms);
+        self.cache.lock().insert(host, CachedAddresses {
+            addrs: addrs.clone(),
+            expires: now + ttl_ms * 1_000_000,
+        });
+        Ok((addrs, Some(ttl_ms)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+    use std::io::IoResult;
+    use std::io::net::ip::{IpAddr, Ipv4Addr};
+    use super::{Resolver, StaticResolver, CachingResolver};
+
+    /// Resolves everything to 192.0.2.1, counting how many times it has done so.
+    struct CountingResolver {
+        lookups: Cell<uint>,
+        ttl_ms: Option<u64>,
+    }
+
+    impl Resolver for CountingResolver {
+        fn resolve(&self, _host: &str) -> IoResult<(Vec<IpAddr>, Option<u64>)> {
+            self.lookups.set(self.lookups.get() + 1);
+            Ok((vec!(Ipv4Addr(192, 0, 2, 1)), self.ttl_ms))
+        }
+    }
+
+    #[test]
+    fn test_static_resolver() {
+        let mut resolver = StaticResolver::new();
+        resolver.insert("Example.com", vec!(Ipv4Addr(127, 0, 0, 1)));
+        assert_eq!(resolver.resolve("example.COM").unwrap(),
+                   (vec!(Ipv4Addr(127, 0, 0, 1)), None));
+        assert!(resolver.resolve("example.org").is_err());
+
+        let mut resolver = StaticResolver::with_fallback(CountingResolver {
+            lookups: Cell::new(0),
+            ttl_ms: None,
+        });
+        resolver.insert("example.com", vec!(Ipv4Addr(127, 0, 0, 1)));
+        assert_eq!(resolver.resolve("example.org").unwrap(),
+                   (vec!(Ipv4Addr(192, 0, 2, 1)), None));
+    }
+
+    #[test]
+    fn test_caching_resolver() {
+        let resolver = CachingResolver::new(CountingResolver {
+            lookups: Cell::new(0),
+            ttl_ms: None,
+        });
+        let (addrs, ttl_ms) = resolver.resolve("example.com").unwrap();
+        assert_eq!(addrs, vec!(Ipv4Addr(192, 0, 2, 1)));
+        assert_eq!(ttl_ms, Some(60_000));
+        assert!(resolver.resolve("EXAMPLE.com").is_ok());
+        assert_eq!(resolver.resolver.lookups.get(), 1);
+        assert!(resolver.resolve("example.org").is_ok());
+        assert_eq!(resolver.resolver.lookups.get(), 2);
+        resolver.clear();
+        assert!(resolver.resolve("example.com").is_ok());
+        assert_eq!(resolver.resolver.lookups.get(), 3);
+
+        // An expired entry is looked up afresh
+        let resolver = CachingResolver::new(CountingResolver {
+            lookups: Cell::new(0),
+            ttl_ms: Some(0),
+        });
+        assert!(resolver.resolve("example.com").is_ok());
+        assert!(resolver.resolve("example.com").is_ok());
+        assert_eq!(resolver.resolver.lookups.get(), 2);
+    }
 }
@@ -0,0 +1,152 @@
+/*!
+
+What can go wrong in speaking HTTP.
+
+An `HttpError` says whether it was the connection that failed, or the other end that did not speak
+HTTP properly (and how), or a limit that was reached. The parsing of requests and responses
+produces `HttpResult`s; but the client and server are, for the most part, `Reader`s and `Writer`s,
+bound to `IoError`, so there an `HttpError` is turned into an `IoError` with `to_io_error`. As that
+says less than the `HttpError` did, the `HttpError` itself is kept where the caller can get at it:
+in `RequestWriter.error` for reading a response, `RequestBody.error` for reading a request body,
+and `Request.error` for a request which the server refused.
+
+```rust
+use http::client::RequestWriter;
+use http::error::MalformedHeader;
+use http::method::Get;
+
+let request = RequestWriter::new(Get, from_str("http://example.com/").unwrap()).unwrap();
+match request.read_response() {
+    Ok(_response) => (),
+    Err((request, err)) => match request.error {
+        Some(MalformedHeader(name, value)) => println!("bad {} header: {}", name, value),
+        _ => println!("{}", err),
+    },
+}
+```
+
+*/
+
+use std::fmt;
+use std::io;
+use std::io::IoError;
+
+pub type HttpResult<T> = Result<T, HttpError>;
+
+/// Something which went wrong in sending or receiving an HTTP message.
+#[deriving(Eq, Clone)]
+pub enum HttpError {
+    /// The connection failed (or was closed where the message could end)
+    Io(IoError),
+
+    /// The first line was not a valid Status-Line (or Request-Line)
+    MalformedStatusLine,
+
+    /// A header could not be parsed: its name (or as much of the line as was read, if that was
+    /// where the problem lay) and its value
+    MalformedHeader(~str, ~str),
+
+    /// A number (in the HTTP-Version, for example) was not a number, or was too big
+    MalformedNumber,
+
+    /// A chunk of a body in the chunked transfer-coding was malformed
+    InvalidChunk,
+
+    /// The connection was closed part way through a message
+    Truncated,
+
+    /// A body was longer than the limit, given here in bytes
+    BodyTooLarge(uint),
+
+    /// The header fields (or the trailer of a chunked body) went beyond the limits on them
+    HeadersTooLarge,
+
+    /// Something took longer than it was allowed
+    Timeout(TimeoutKind),
+
+    /// Redirects were followed up to the limit, given here, and there was yet another
+    TooManyRedirects(uint),
+}
+
+/// Which timeout ran out.
+#[deriving(Eq, Clone, Show)]
+pub enum TimeoutKind {
+    /// Making the connection
+    ConnectTimeout,
+    /// Waiting for the other end to send something
+    ReadTimeout,
+    /// The whole exchange, from connecting to reading the end of the response
+    TotalTimeout,
+}
+
+impl HttpError {
+    /// The error as an `IoError`, for a `Reader` or `Writer` to return.
+    ///
+    /// Malformed messages are `OtherIoError`, and timeouts `TimedOut`. A truncated message is
+    /// deliberately not `EndOfFile`, lest a `read_to_end()` take what it got as complete.
+    pub fn to_io_error(&self) -> IoError {
+        let (kind, desc, detail) = match *self {
+            Io(ref err) => return err.clone(),
+            MalformedStatusLine =>
+                (io::OtherIoError, "Malformed HTTP status line", None),
+            MalformedHeader(ref name, ref value) =>
+                (io::OtherIoError, "Malformed HTTP header", Some(format!("{}: {}", *name, *value))),
+            MalformedNumber =>
+                (io::OtherIoError, "invalid number", None),
+            InvalidChunk =>
+                (io::OtherIoError, "invalid chunk in chunked transfer-coding", None),
+            Truncated =>
+                (io::OtherIoError, "Connection closed part way through an HTTP message", None),
+            BodyTooLarge(limit) =>
+                (io::OtherIoError, "HTTP message body too large", Some(limit.to_str())),
+            HeadersTooLarge =>
+                (io::OtherIoError, "HTTP header fields too large", None),
+            Timeout(ConnectTimeout) =>
+                (io::TimedOut, "Timed out connecting to the server", None),
+            Timeout(ReadTimeout) =>
+                (io::TimedOut, "Timed out waiting for the server to respond", None),
+            Timeout(TotalTimeout) =>
+                (io::TimedOut, "The request did not complete within its total timeout", None),
+            TooManyRedirects(max) =>
+                (io::OtherIoError, "Too many redirects", Some(max.to_str())),
+        };
+        IoError {
+            kind: kind,
+            desc: desc,
+            detail: detail,
+        }
+    }
+
+    /// Turn the end of the stream into `Truncated`; for use where more of a message is expected.
+    pub fn eof_is_truncation(self) -> HttpError {
+        match self {
+            Io(ref err) if err.kind == io::EndOfFile => Truncated,
+            err => err,
+        }
+    }
+}
+
+impl fmt::Show for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let err = self.to_io_error();
+        match err.detail {
+            Some(ref detail) => write!(f.buf, "{} ({})", err.desc, *detail),
+            None => write!(f.buf, "{}", err.desc),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use super::{Io, MalformedHeader, Truncated, Timeout, ReadTimeout};
+
+    #[test]
+    fn test_io_error_kinds() {
+        assert_eq!(Timeout(ReadTimeout).to_io_error().kind, io::TimedOut);
+        assert_eq!(Truncated.to_io_error().kind, io::OtherIoError);
+        assert_eq!(Io(io::standard_error(io::EndOfFile)).eof_is_truncation(), Truncated);
+        assert_eq!(MalformedHeader(~"Content-Length", ~"ten").to_str(),
+                   ~"Malformed HTTP header (Content-Length: ten)");
+    }
+}
@@ -7,7 +7,8 @@ use std::fmt;
 #[deriving(Clone, Eq)]
 pub struct Host {
 
-    /// The name of the host that was requested
+    /// The name of the host that was requested. An IPv6 address is stored without the square
+    /// brackets which surround it in the header.
     name: ~str,
 
     /// If unspecified, assume the default port was used (80 for HTTP, 443 for HTTPS).
@@ -18,9 +19,15 @@ pub struct Host {
 
 impl fmt::Show for Host {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Only an IPv6 address can contain a colon, and it must be bracketed (RFC 3986, §3.2.2)
+        if self.name.contains_char(':') {
+            try!(write!(f.buf, "[{}]", self.name));
+        } else {
+            try!(f.buf.write(self.name.as_bytes()));
+        }
         match self.port {
-            Some(port) => write!(f.buf, "{}:{}", self.name, port.to_str()),
-            None => f.buf.write(self.name.as_bytes()),
+            Some(port) => write!(f.buf, ":{}", port.to_str()),
+            None => Ok(()),
         }
     }
 }
@@ -28,15 +35,31 @@ impl fmt::Show for Host {
 impl super::HeaderConvertible for Host {
     fn from_stream<R: Reader>(reader: &mut super::HeaderValueByteIterator<R>) -> Option<Host> {
         let s = reader.collect_to_str();
-        // TODO: this doesn't support IPv6 address access (e.g. "[::1]")
-        // Do this properly with correct authority parsing.
-        let mut hi = s.splitn(':', 1);
+        // host = IP-literal / IPv4address / reg-name; IP-literal = "[" IPv6address "]"
+        let (name, rest) = if s.starts_with("[") {
+            match s.find(']') {
+                Some(end) => (s.slice(1, end), s.slice_from(end + 1)),
+                None => return None,
+            }
+        } else {
+            match s.find(':') {
+                Some(colon) => (s.slice_to(colon), s.slice_from(colon)),
+                None => (s.as_slice(), ""),
+            }
+        };
+        let port = if rest.len() == 0 {
+            None
+        } else if rest.starts_with(":") {
+            match from_str::<u16>(rest.slice_from(1)) {
+                Some(port) => Some(port),
+                None => return None,
+            }
+        } else {
+            return None;
+        };
         Some(Host {
-            name: hi.next().unwrap().to_owned(),
-            port: match hi.next() {
-                Some(name) => from_str::<u16>(name),
-                None => None,
-            },
+            name: name.to_owned(),
+            port: port,
         })
     }
 
@@ -44,3 +67,18 @@ impl super::HeaderConvertible for Host {
         self.to_str()
     }
 }
+
+#[test]
+fn test_host() {
+    use headers::test_utils::{assert_conversion_correct, assert_invalid};
+    assert_conversion_correct("example.com", Host { name: ~"example.com", port: None });
+    assert_conversion_correct("example.com:8080", Host { name: ~"example.com", port: Some(8080) });
+    assert_conversion_correct("127.0.0.1:80", Host { name: ~"127.0.0.1", port: Some(80) });
+    assert_conversion_correct("[::1]", Host { name: ~"::1", port: None });
+    assert_conversion_correct("[fe80::1]:8080", Host { name: ~"fe80::1", port: Some(8080) });
+
+    assert_invalid::<Host>("example.com:http");
+    assert_invalid::<Host>("example.com:99999");
+    assert_invalid::<Host>("[::1");
+    assert_invalid::<Host>("[::1]8080");
+}
@@ -13,7 +13,14 @@ use method::Method;
 
 use self::serialization_utils::{normalise_header_name};
 
-pub enum HeaderLineErr { EndOfFile, EndOfHeaders, MalformedHeaderValue, MalformedHeaderSyntax }
+/// Why a header line could not be read: `MalformedHeaderValue` has the header's name and its value
+/// as it was sent, and `MalformedHeaderSyntax` as much of the line as was read.
+pub enum HeaderLineErr {
+    EndOfFile,
+    EndOfHeaders,
+    MalformedHeaderValue(~str, ~str),
+    MalformedHeaderSyntax(~str),
+}
 
 pub mod test_utils;
 pub mod serialization_utils;
@@ -29,7 +36,8 @@ From https://en.wikipedia.org/wiki/List_of_HTTP_headers:
   - Link
   - P3P
   - Refresh
-  - Set-Cookie
+  - Set-Cookie (for now the client keeps these apart, in `ResponseReader.set_cookies`, as several
+    of them cannot be combined into one header)
   - Status
   - Strict-Transport-Security
 
@@ -111,7 +119,10 @@ pub fn header_enum_from_stream<R: Reader, E: HeaderEnum>(reader: &mut R)
             },
             (_, Ok(b)) if b == SP => NameFinished,
             (_, Ok(b)) if b == COLON => break,
-            (_, Ok(_)) => return (Err(MalformedHeaderSyntax), None),
+            (_, Ok(b)) => {
+                header_name.push_char(b as char);
+                return (Err(MalformedHeaderSyntax(header_name)), None);
+            },
             (_, Err(_)) => return (Err(EndOfFile), None),
         }
     }
@@ -122,9 +133,9 @@ pub fn header_enum_from_stream<R: Reader, E: HeaderEnum>(reader: &mut R)
     match header {
         Some(h) => (Ok(h), iter.next_byte),
         None => {
-            debug!("malformed header value for {}", header_name);
-            // Alas, I can't tell you what the value actually was... TODO: improve that situation
-            (Err(MalformedHeaderValue), iter.next_byte)
+            let value = iter.raw_value();
+            debug!("malformed header value for {}: {}", header_name, value);
+            (Err(MalformedHeaderValue(header_name, value)), iter.next_byte)
         },
     }
 }
@@ -153,6 +164,9 @@ pub struct HeaderValueByteIterator<'a, R> {
 
     at_start: bool,
     priv state: HeaderValueByteIteratorState,
+
+    /// Every byte read from the reader, for `raw_value`
+    priv raw: Vec<u8>,
 }
 
 impl<'a, R: Reader> HeaderValueByteIterator<'a, R> {
@@ -163,9 +177,23 @@ impl<'a, R: Reader> HeaderValueByteIterator<'a, R> {
             next_byte: None,
             at_start: true,
             state: Normal,
+            raw: Vec::new(),
         }
     }
 
+    /// The header value as it was read (less leading and trailing white space), for reporting a
+    /// value which could not be parsed. This is only the whole value once iteration has finished.
+    pub fn raw_value(&self) -> ~str {
+        let raw = self.raw.as_slice();
+        // Having finished, the last byte read was the first of the next line
+        let raw = match self.next_byte {
+            Some(_) if self.state == Finished && raw.len() > 0 => raw.slice_to(raw.len() - 1),
+            _ => raw,
+        };
+        let value: ~str = raw.iter().map(|&b| b as char).collect();
+        value.trim().to_owned()
+    }
+
     /// Check that the entire header value has been consumed.
     ///
     /// Should there be any trailing linear white space, it is dropped.
@@ -457,7 +485,10 @@ impl<'a, R: Reader> Iterator<u8> for HeaderValueByteIterator<'a, R> {
                         self.state = Finished;
                         return None
                     },
-                    Ok(b) => b,
+                    Ok(b) => {
+                        self.raw.push(b);
+                        b
+                    },
                 }
             };
             match self.state {
@@ -733,9 +764,31 @@ impl HeaderConvertible for Tm {
 
 #[cfg(test)]
 mod test {
+    use std::io::MemReader;
     use time::Tm;
     use headers::test_utils::{from_stream_with_str, to_stream_into_str};
-    use super::HeaderConvertible;
+    use headers::response;
+    use super::{HeaderConvertible, header_enum_from_stream};
+    use super::{MalformedHeaderValue, MalformedHeaderSyntax};
+
+    #[test]
+    fn test_malformed_header() {
+        let mut reader = MemReader::new(bytes!("Content-Length:  ten \r\nDate").to_owned());
+        match header_enum_from_stream::<MemReader, response::Header>(&mut reader) {
+            (Err(MalformedHeaderValue(name, value)), next) => {
+                assert_eq!(name, ~"Content-Length");
+                assert_eq!(value, ~"ten");
+                assert_eq!(next, Some('D' as u8));
+            },
+            _ => fail!("expected a malformed header value"),
+        }
+
+        let mut reader = MemReader::new(bytes!("Content/Length: 10\r\n").to_owned());
+        match header_enum_from_stream::<MemReader, response::Header>(&mut reader) {
+            (Err(MalformedHeaderSyntax(line)), _) => assert_eq!(line, ~"Content/"),
+            _ => fail!("expected malformed header syntax"),
+        }
+    }
 
     #[test]
     fn test_from_stream_str() {
@@ -1010,7 +1063,7 @@ headers_mod! {
     #[doc = "Request whatnottery."]
     pub mod request;
 
-    num_headers: 38;
+    num_headers: 39;
 
     // RFC 2616, Section 4.5: General Header Fields
      0, "Cache-Control",     "Cache-Control",     CacheControl,     cache_control,     ~str;
@@ -1055,6 +1108,9 @@ headers_mod! {
     35, "Content-Type",     "Content-Type",     ContentType,     content_type,     headers::content_type::MediaType;
     36, "Expires",          "Expires",          Expires,         expires,          time::Tm;
     37, "Last-Modified",    "Last-Modified",    LastModified,    last_modified,    time::Tm;
+
+    // RFC 6265, Section 5.4: The Cookie Header
+    38, "Cookie", "Cookie", Cookie, cookie, ~str;
 }
 
 headers_mod! {
@@ -18,11 +18,14 @@
 extern crate url;
 extern crate time;
 extern crate collections;
+extern crate sync;
+extern crate serialize;
 
 pub mod buffer;
 pub mod client;
 pub mod common;
 pub mod connecter;
+pub mod error;
 pub mod server;
 pub mod method;
 pub mod headers;
@@ -2,12 +2,20 @@
 
 use std::io::{IoResult, Seek, SeekStyle};
 use std::io::{MemReader, MemWriter};
+use std::io::net::ip::SocketAddr;
+use connecter::Connecter;
 
 /// Writes to an owned, growable byte vector but also implements read with fail-on-call methods.
-struct MemWriterFakeStream(MemWriter);
+pub struct MemWriterFakeStream(MemWriter);
 
 impl MemWriterFakeStream {
     pub fn new() -> MemWriterFakeStream { MemWriterFakeStream(MemWriter::new()) }
+
+    /// The bytes written so far.
+    pub fn get_ref<'a>(&'a self) -> &'a [u8] {
+        let &MemWriterFakeStream(ref s) = self;
+        s.get_ref()
+    }
 }
 
 impl Writer for MemWriterFakeStream {
@@ -75,6 +83,18 @@ impl Writer for MemReaderFakeStream {
     }
 }
 
+impl Connecter for MemWriterFakeStream {
+    fn connect(_addr: SocketAddr) -> IoResult<MemWriterFakeStream> {
+        fail!("Uh oh, you didn't aught to call MemWriterFakeStream::connect()!")
+    }
+}
+
+impl Connecter for MemReaderFakeStream {
+    fn connect(_addr: SocketAddr) -> IoResult<MemReaderFakeStream> {
+        fail!("Uh oh, you didn't aught to call MemReaderFakeStream::connect()!")
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::{MemReaderFakeStream, MemWriterFakeStream};
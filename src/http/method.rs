@@ -83,4 +83,27 @@ impl Method {
             _         => ExtensionMethod(method.to_owned()),
         })
     }
+
+    /**
+     * Whether the method is idempotent (RFC 2616, §9.1.2): several identical requests have the
+     * same effect as one, so a request may be repeated if its response was lost.
+     *
+     * Nothing is known of extension methods, so they are taken not to be.
+     */
+    pub fn is_idempotent(&self) -> bool {
+        match *self {
+            Options | Get | Head | Put | Delete | Trace => true,
+            Post | Connect | Patch | ExtensionMethod(_) => false,
+        }
+    }
+}
+
+#[test]
+fn test_is_idempotent() {
+    assert!(Get.is_idempotent());
+    assert!(Put.is_idempotent());
+    assert!(Delete.is_idempotent());
+    assert!(!Post.is_idempotent());
+    assert!(!Patch.is_idempotent());
+    assert!(!ExtensionMethod(~"PROPFIND").is_idempotent());
 }
@@ -140,11 +140,12 @@ pub fn is_separator(o: u8) -> bool {
 // IANA is assigned as maintaining the registry for these things:
 // see https://www.iana.org/assignments/http-parameters/http-parameters.xml
 
-mod content_coding {
+pub mod content_coding {
     use std::fmt;
     use std::from_str::FromStr;
 
     /// Content-coding value tokens
+    #[deriving(Eq, Clone)]
     pub enum ValueToken {
         // An encoding format produced by the file compression program "gzip" (GNU zip) as described
         // in RFC 1952 [25]. This format is a Lempel-Ziv coding (LZ77) with a 32 bit CRC.
@@ -188,8 +189,8 @@ mod content_coding {
         fn from_str(s: &str) -> Option<ValueToken> {
             use std::ascii::StrAsciiExt;
             match s.to_ascii_lower().as_slice() {
-                "gzip" => Some(Gzip),
-                "compress" => Some(Compress),
+                "gzip" | "x-gzip" => Some(Gzip),
+                "compress" | "x-compress" => Some(Compress),
                 "deflate" => Some(Deflate),
                 "identity" => Some(Identity),
                 _ => None,
@@ -1,125 +1,480 @@
-use std::io::{Listener, Acceptor};
+use std::io::{Listener, Acceptor, IoResult, TimedOut, EndOfFile};
+use std::io::fs;
+use std::cmp::min;
 use std::io::net::ip::SocketAddr;
 use time::precise_time_ns;
 
-use std::io::net::tcp::TcpListener;
+use std::io::net::tcp::{TcpListener, TcpAcceptor, TcpStream};
+use std::io::net::unix::{UnixListener, UnixAcceptor, UnixStream};
 
 use buffer::BufferedStream;
+use error::{BodyTooLarge, HeadersTooLarge};
+use headers::connection::Close;
+use headers::response::HeaderCollection;
+use status;
 
-pub use self::request::{RequestBuffer, Request};
+pub use self::request::{RequestBuffer, Request, RequestBody};
 pub use self::response::ResponseWriter;
+pub use self::shutdown::ShutdownHandle;
+use self::shutdown::POLL_INTERVAL_MS;
 
 pub mod request;
 pub mod response;
+pub mod shutdown;
 
 pub trait Server: Send + Clone {
-	fn handle_request(&self, request: &Request, response: &mut ResponseWriter) -> ();
+	/**
+	 * Handle a request, reading its body (if it is wanted) from `body` and writing the response
+	 * to `response`. Whatever is not read of the body is discarded once this returns (or if
+	 * there is much of it, the connection is closed; see `Limits.max_discard_len`).
+	 *
+	 * A client which sent `Expect: 100-continue` is sent `100 Continue` when the body is first
+	 * read from; to refuse the body, respond without reading it (see `Request.expects_continue`).
+	 */
+	fn handle_request(&self, request: &Request, body: &mut RequestBody<ServerStream>,
+	                  response: &mut ResponseWriter) -> ();
 
 	// XXX: this could also be implemented on the serve methods
 	fn get_config(&self) -> Config;
 
 	/**
-	 * Attempt to bind to the address and port and start serving forever.
+	 * Attempt to bind to the address and port (or the Unix socket path) and start serving forever.
 	 *
 	 * This will only return if the initial connection fails or something else blows up.
 	 */
     fn serve_forever(self) {
+        self.serve_until_shutdown(ShutdownHandle::new())
+    }
+
+	/**
+	 * As `serve_forever`, but returning once `shutdown` has been told to shut the server down and
+	 * the requests being handled have finished (or had their time); see the `shutdown` module.
+	 */
+    fn serve_until_shutdown(self, shutdown: ShutdownHandle) {
         let config = self.get_config();
-        debug!("About to bind to {:?}", config.bind_address);
-        let mut acceptor = match TcpListener::bind(config.bind_address).listen() {
-            Err(err) => {
-                error!("bind or listen failed :-(: {}", err);
-                return;
+        match config.unix_socket_path {
+            Some(ref path) => {
+                debug!("About to bind to {}", path.display());
+                match UnixListener::bind(path).listen() {
+                    Err(err) => error!("bind or listen failed :-(: {}", err),
+                    Ok(acceptor) => {
+                        serve(self, acceptor, UnixServerStream, config.limits, shutdown);
+                        // Otherwise the path could not be bound to again
+                        match fs::unlink(path) {
+                            Err(err) => error!("removing {} failed: {}", path.display(), err),
+                            Ok(()) => (),
+                        }
+                    },
+                }
+            },
+            None => {
+                debug!("About to bind to {:?}", config.bind_address);
+                match TcpListener::bind(config.bind_address).listen() {
+                    Err(err) => error!("bind or listen failed :-(: {}", err),
+                    Ok(acceptor) => serve(self, acceptor, TcpServerStream, config.limits,
+                                          shutdown),
+                }
+            },
+        }
+    }
+}
+
+/// An acceptor which can be made to give up waiting for a connection, so that the server can see
+/// whether it is to shut down.
+trait PollingAcceptor<T>: Acceptor<T> {
+    fn set_timeout(&mut self, timeout_ms: Option<u64>);
+}
+
+impl PollingAcceptor<TcpStream> for TcpAcceptor {
+    fn set_timeout(&mut self, timeout_ms: Option<u64>) {
+        self.set_timeout(timeout_ms)
+    }
+}
+
+impl PollingAcceptor<UnixStream> for UnixAcceptor {
+    fn set_timeout(&mut self, timeout_ms: Option<u64>) {
+        self.set_timeout(timeout_ms)
+    }
+}
+
+/// Serve on the connections accepted by `acceptor`, made into `ServerStream`s by `wrap`, reading
+/// requests within `limits`, until `shutdown` says to stop.
+fn serve<S: Server, T: Send, A: PollingAcceptor<T>>(server: S, mut acceptor: A,
+                                                    wrap: fn(T) -> ServerStream, limits: Limits,
+                                                    shutdown: ShutdownHandle) {
+    debug!("listening");
+    let (perf_sender, perf_receiver) = channel();
+    spawn(proc() {
+        perf_dumper(perf_receiver);
+    });
+    while !shutdown.is_shutting_down() {
+        let time_start = precise_time_ns();
+        acceptor.set_timeout(Some(POLL_INTERVAL_MS));
+        let stream = match acceptor.accept() {
+            Err(ref error) if error.kind == TimedOut => continue,
+            Err(error) => {
+                debug!("accept failed: {:?}", error);
+                // Question: is this the correct thing to do? We should probably be more
+                // intelligent, for there are some accept failures that are likely to be
+                // permanent, such that continuing would be a very bad idea, such as
+                // ENOBUFS/ENOMEM; and some where it should just be ignored, e.g.
+                // ECONNABORTED. TODO.
+                continue;
             },
-            Ok(acceptor) => acceptor,
+            Ok(socket) => wrap(socket),
         };
-        debug!("listening");
-        let (perf_sender, perf_receiver) = channel();
+        let child_perf_sender = perf_sender.clone();
+        let child_self = server.clone();
+        let child_shutdown = shutdown.clone();
+        let open_connection = shutdown.open_connection(stream.clone());
         spawn(proc() {
-            perf_dumper(perf_receiver);
-        });
-        loop {
-            let time_start = precise_time_ns();
-            let stream = match acceptor.accept() {
-                Err(error) => {
-                    debug!("accept failed: {:?}", error);
-                    // Question: is this the correct thing to do? We should probably be more
-                    // intelligent, for there are some accept failures that are likely to be
-                    // permanent, such that continuing would be a very bad idea, such as
-                    // ENOBUFS/ENOMEM; and some where it should just be ignored, e.g.
-                    // ECONNABORTED. TODO.
-                    continue;
-                },
-                Ok(socket) => socket,
-            };
-            let child_perf_sender = perf_sender.clone();
-            let child_self = self.clone();
-            spawn(proc() {
-                let mut time_start = time_start;
-                let mut stream = BufferedStream::new(stream);
-                debug!("accepted connection, got {:?}", stream);
-                loop {  // A keep-alive loop, condition at end
-                    let time_spawned = precise_time_ns();
-                    let (request, err_status) = Request::load(&mut stream);
-                    let time_request_made = precise_time_ns();
-                    let mut response = ~ResponseWriter::new(&mut stream, request);
-                    let time_response_made = precise_time_ns();
-                    match err_status {
-                        Ok(()) => {
-                            child_self.handle_request(request, response);
-                            // Ensure that we actually do send a response:
-                            match response.try_write_headers() {
-                                Err(err) => {
-                                    error!("Writing headers failed: {}", err);
-                                    return;  // Presumably bad connection, so give up.
-                                },
-                                Ok(_) => (),
-                            }
-                        },
-                        Err(status) => {
-                            // Uh oh, it's a response that I as a server cannot cope with.
-                            // No good user-agent should have caused this, so for the moment
-                            // at least I am content to send no body in the response.
-                            response.status = status;
-                            response.headers.content_length = Some(0);
-                            match response.write_headers() {
-                                Err(err) => {
-                                    error!("Writing headers failed: {}", err);
-                                    return;  // Presumably bad connection, so give up.
-                                },
-                                Ok(_) => (),
-                            }
-                        },
-                    }
-                    // Ensure the request is flushed, any Transfer-Encoding completed, etc.
-                    match response.finish_response() {
+            let _open_connection = open_connection;
+            let mut time_start = time_start;
+            let mut lingering = false;
+            // The request body is read while the response is written, so they each have a handle
+            // on the connection (and a buffer) of their own.
+            let mut writer = BufferedStream::new(stream.clone());
+            let mut stream = BufferedStream::new(stream);
+            let remote_addr = stream.wrapped.peer_name();
+            debug!("accepted connection, got {:?}", stream);
+            loop {  // A keep-alive loop, conditions at either end
+                if !wait_for_request(&mut stream, &child_shutdown) {
+                    break;
+                }
+                let time_spawned = precise_time_ns();
+                let (request, err_status) = Request::load(&mut stream, remote_addr, &limits);
+                let time_request_made = precise_time_ns();
+                let mut body = RequestBody::new(&mut stream, &*request, &limits);
+                let mut response = ~ResponseWriter::new(&mut writer, request);
+                if child_shutdown.is_shutting_down() {
+                    response.headers.connection = Some(vec!(Close));
+                }
+                let time_response_made = precise_time_ns();
+                match err_status {
+                    Ok(()) => {
+                        child_self.handle_request(request, &mut body, response);
+                        // A chunked body (or its trailer) which went beyond the limits is
+                        // refused, if the handler has not already begun to respond (the
+                        // connection is closed either way, as the rest cannot be read past).
+                        let refusal = match body.error {
+                            Some(BodyTooLarge(_)) => Some(status::RequestEntityTooLarge),
+                            Some(HeadersTooLarge) => Some(status::RequestHeaderFieldsTooLarge),
+                            _ => None,
+                        };
+                        match refusal {
+                            Some(status) if !response.headers_written() => {
+                                response.status = status;
+                                response.headers = ~HeaderCollection::new();
+                                response.headers.content_length = Some(0);
+                                response.headers.connection = Some(vec!(Close));
+                            },
+                            _ => (),
+                        }
+                        // Ensure that we actually do send a response:
+                        match response.try_write_headers() {
+                            Err(err) => {
+                                error!("Writing headers failed: {}", err);
+                                return;  // Presumably bad connection, so give up.
+                            },
+                            Ok(_) => (),
+                        }
+                    },
+                    Err(status) => {
+                        // Uh oh, it's a response that I as a server cannot cope with.
+                        // No good user-agent should have caused this, so for the moment
+                        // at least I am content to send no body in the response.
+                        match request.error {
+                            Some(ref err) => debug!("refusing request ({}): {}", status, *err),
+                            None => debug!("refusing request ({})", status),
+                        }
+                        response.status = status;
+                        response.headers.content_length = Some(0);
+                        match response.write_headers() {
+                            Err(err) => {
+                                error!("Writing headers failed: {}", err);
+                                return;  // Presumably bad connection, so give up.
+                            },
+                            Ok(_) => (),
+                        }
+                    },
+                }
+                // Ensure the request is flushed, any Transfer-Encoding completed, etc.
+                match response.finish_response() {
+                    Err(err) => {
+                        error!("finishing response failed: {}", err);
+                        return;  // Presumably bad connection, so give up.
+                    },
+                    Ok(_) => (),
+                }
+                // Whatever of the body the handler didn't read stands before the next request; if
+                // there's much of it, closing the connection is cheaper than reading it all.
+                let discard = match body.remaining_len() {
+                    Some(len) => len <= limits.max_discard_len,
+                    None => false,
+                };
+                if err_status.is_ok() && discard {
+                    match body.discard() {
                         Err(err) => {
-                            error!("finishing response failed: {}", err);
-                            return;  // Presumably bad connection, so give up.
+                            error!("reading request body failed: {}", err);
+                            return;
                         },
                         Ok(_) => (),
                     }
-                    let time_finished = precise_time_ns();
-                    child_perf_sender.send((time_start, time_spawned, time_request_made, time_response_made, time_finished));
+                }
+                // (The client may also never have sent it, having been refused a 100 Continue)
+                let body_finished = body.is_finished();
+                let time_finished = precise_time_ns();
+                child_perf_sender.send((time_start, time_spawned, time_request_made, time_response_made, time_finished));
 
-                    // Subsequent requests on this connection have no spawn time
-                    time_start = time_finished;
+                // Subsequent requests on this connection have no spawn time
+                time_start = time_finished;
 
-                    if request.close_connection {
-                        break;
-                    }
+                if request.close_connection || !body_finished ||
+                        child_shutdown.is_shutting_down() {
+                    // The client may still be sending what is left of the body
+                    lingering = !body_finished;
+                    break;
                 }
-            });
+            }
+            if lingering {
+                linger(&mut stream, limits.max_discard_len);
+            }
+        });
+    }
+
+    // No more connections are accepted; those still open are given until the deadline, and then
+    // shut down
+    drop(acceptor);
+    match shutdown.wait_for_connections() {
+        0 => debug!("shut down"),
+        n => error!("shut down, cutting off {} connections still open", n),
+    }
+}
+
+/// Wait for the first byte of the next request on the connection, looking up now and again to see
+/// whether the server is shutting down; false if it is, or if the connection was closed first.
+fn wait_for_request(stream: &mut BufferedStream<ServerStream>, shutdown: &ShutdownHandle)
+        -> bool {
+    loop {
+        if shutdown.is_shutting_down() {
+            return false;
+        }
+        stream.wrapped.set_read_timeout(Some(POLL_INTERVAL_MS));
+        let result = stream.read_byte();
+        stream.wrapped.set_read_timeout(None);
+        match result {
+            Ok(b) => {
+                stream.poke_byte(b);
+                return true;
+            },
+            Err(ref err) if err.kind == TimedOut => (),
+            Err(ref err) if err.kind == EndOfFile => return false,
+            Err(err) => {
+                debug!("connection failed while idle: {}", err);
+                return false;
+            },
         }
     }
 }
 
+/// Close the connection gently, with some of the request unread: closing it outright could send
+/// the client a reset, and it would lose the response before it had read it. Instead, the writing
+/// half is shut down, and up to `max_len` bytes of what the client sends are read past until it
+/// closes its end (or takes longer than `LINGER_TIMEOUT_MS` over a read).
+fn linger(stream: &mut BufferedStream<ServerStream>, max_len: uint) {
+    match stream.wrapped.close_write() {
+        Err(err) => {
+            debug!("shutting down connection failed: {}", err);
+            return;
+        },
+        Ok(()) => (),
+    }
+    stream.wrapped.set_read_timeout(Some(LINGER_TIMEOUT_MS));
+    let mut buf = [0u8, ..4096];
+    let mut left = max_len;
+    while left > 0 {
+        match stream.read(buf) {
+            Ok(read) => left -= min(read, left),
+            Err(_) => break,
+        }
+    }
+}
+
+/// How long to wait on each read for a client to close its end of a connection being closed by
+/// `linger`.
+static LINGER_TIMEOUT_MS: u64 = 2_000;
+
 /// The necessary configuration for an HTTP server.
 ///
-/// At present, only the IP address and port to bind to are needed, but it's possible that other
-/// options may turn up later.
+/// `Config::new` provides defaults for anything but the IP address and port.
+#[deriving(Clone)]
 pub struct Config {
 	bind_address: SocketAddr,
+
+	/// A Unix domain socket to listen on instead of `bind_address`. The path must not already
+	/// exist; it is removed when the server shuts down.
+	///
+	/// The credentials of the client process (its user, group and process ID) are not made
+	/// available: that would take `SO_PEERCRED` (or `getpeereid`) on the connection's file
+	/// descriptor, which `UnixStream` does not expose. So a request over the socket has nothing
+	/// to say who sent it, and access to the socket is to be controlled by its permissions.
+	unix_socket_path: Option<Path>,
+
+	/// The limits on what will be accepted of a request.
+	limits: Limits,
+}
+
+impl Config {
+    /// A configuration for serving on the given IP address and port.
+    pub fn new(bind_address: SocketAddr) -> Config {
+        Config {
+            bind_address: bind_address,
+            unix_socket_path: None,
+            limits: Limits::new(),
+        }
+    }
+}
+
+/// By default, a method can be up to 64 bytes long.
+pub static DEFAULT_MAX_METHOD_LEN: uint = 64;
+
+/// By default, a Request-URI can be up to 8KB long.
+pub static DEFAULT_MAX_REQUEST_URI_LEN: uint = 8192;
+
+/// By default, a request can have up to 100 header fields.
+pub static DEFAULT_MAX_HEADERS: uint = 100;
+
+/// By default, a header field can be up to 8KB long.
+pub static DEFAULT_MAX_HEADER_LINE_LEN: uint = 8192;
+
+/// By default, the header fields of a request can be up to 64KB long in all.
+pub static DEFAULT_MAX_HEADER_BYTES: uint = 65536;
+
+/// By default, a request body can be up to 10MB long.
+pub static DEFAULT_MAX_BODY_LEN: uint = 10 * 1024 * 1024;
+
+/// By default, up to 64KB of a body which the handler didn't read is read past to keep the
+/// connection open.
+pub static DEFAULT_MAX_DISCARD_LEN: uint = 65536;
+
+/// The limits on what the server will accept of a request. A client which goes beyond them is
+/// answered with an error status instead of having any more of its request read; a public-facing
+/// server may well want them lower than the defaults above.
+#[deriving(Clone, Eq, Show)]
+pub struct Limits {
+    /// The longest method, in bytes; any longer is 400 (Bad Request).
+    max_method_len: uint,
+
+    /// The longest Request-URI, in bytes; any longer is 414 (Request-URI Too Long).
+    max_request_uri_len: uint,
+
+    /// The most header fields; any more is 431 (Request Header Fields Too Large). This and the two
+    /// limits below hold for the trailer of a chunked body too (see `RequestBody`).
+    max_headers: uint,
+
+    /// The longest header field, in bytes, including its line ending and any continuation lines;
+    /// any longer is 431 (Request Header Fields Too Large).
+    max_header_line_len: uint,
+
+    /// The most bytes of header fields in all; any more is 431 (Request Header Fields Too Large).
+    max_header_bytes: uint,
+
+    /// The longest body, in bytes, if there is a limit: a Content-Length greater than this is 413
+    /// (Request Entity Too Large), and reading a chunked body fails on getting this far (see
+    /// `RequestBody`), which is then 413 too unless the handler has already begun its response.
+    /// A handler which reads the body as it goes, rather than into memory, may do without a limit.
+    max_body_len: Option<uint>,
+
+    /// The most of a body left unread by the handler that will be read past, in bytes, so that
+    /// the connection can be kept open for another request. If more than this is left, or the
+    /// body is chunked and how much is left isn't known, the connection is closed instead; and
+    /// as it is closed, up to this much more is read past while the client finishes sending.
+    max_discard_len: uint,
+}
+
+impl Limits {
+    /// The limits given by the defaults above.
+    pub fn new() -> Limits {
+        Limits {
+            max_method_len: DEFAULT_MAX_METHOD_LEN,
+            max_request_uri_len: DEFAULT_MAX_REQUEST_URI_LEN,
+            max_headers: DEFAULT_MAX_HEADERS,
+            max_header_line_len: DEFAULT_MAX_HEADER_LINE_LEN,
+            max_header_bytes: DEFAULT_MAX_HEADER_BYTES,
+            max_body_len: Some(DEFAULT_MAX_BODY_LEN),
+            max_discard_len: DEFAULT_MAX_DISCARD_LEN,
+        }
+    }
+}
+
+/// A connection accepted by the server.
+#[deriving(Clone)]
+pub enum ServerStream {
+    /// A connection to `Config.bind_address`
+    TcpServerStream(TcpStream),
+    /// A connection to `Config.unix_socket_path`
+    UnixServerStream(UnixStream),
+}
+
+impl ServerStream {
+    /// The address of the client, if it is connected over TCP.
+    pub fn peer_name(&mut self) -> Option<SocketAddr> {
+        match *self {
+            TcpServerStream(ref mut stream) => stream.peer_name().ok(),
+            UnixServerStream(_) => None,
+        }
+    }
+
+    /// Shut down the reading half of the connection, so that reading from it (through any of its
+    /// clones) finds the end of the stream.
+    pub fn close_read(&mut self) -> IoResult<()> {
+        match *self {
+            TcpServerStream(ref mut stream) => stream.close_read(),
+            UnixServerStream(ref mut stream) => stream.close_read(),
+        }
+    }
+
+    /// Shut down the writing half of the connection, leaving the client to read what has been sent
+    /// and then the end of the stream.
+    pub fn close_write(&mut self) -> IoResult<()> {
+        match *self {
+            TcpServerStream(ref mut stream) => stream.close_write(),
+            UnixServerStream(ref mut stream) => stream.close_write(),
+        }
+    }
+
+    /// Set a timeout on reads from the connection; see `TcpStream::set_read_timeout`.
+    pub fn set_read_timeout(&mut self, timeout_ms: Option<u64>) {
+        match *self {
+            TcpServerStream(ref mut stream) => stream.set_read_timeout(timeout_ms),
+            UnixServerStream(ref mut stream) => stream.set_read_timeout(timeout_ms),
+        }
+    }
+}
+
+impl Reader for ServerStream {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        match *self {
+            TcpServerStream(ref mut stream) => stream.read(buf),
+            UnixServerStream(ref mut stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Writer for ServerStream {
+    fn write(&mut self, buf: &[u8]) -> IoResult<()> {
+        match *self {
+            TcpServerStream(ref mut stream) => stream.write(buf),
+            UnixServerStream(ref mut stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        match *self {
+            TcpServerStream(ref mut stream) => stream.flush(),
+            UnixServerStream(ref mut stream) => stream.flush(),
+        }
+    }
 }
 
 static PERF_DUMP_FREQUENCY : u64 = 10_000;
@@ -133,8 +488,8 @@ fn perf_dumper(perf_receiver: Receiver<(u64, u64, u64, u64, u64)>) {
     let mut td_handle = 0u64;
     let mut td_total = 0u64;
     let mut i = 0u64;
-    loop {
-        let data = perf_receiver.recv();
+    // Until the server and its connections are all gone
+    for data in perf_receiver.iter() {
         let (start, spawned, request_made, response_made, finished) = data;
         td_spawn += spawned - start;
         td_request += request_made - spawned;
@@ -2,34 +2,56 @@ use url::Url;
 use method::{Method, Options};
 use status;
 use std::from_str::FromStr;
-use std::io::{Stream, IoResult};
+use std::ascii::StrAsciiExt;
+use std::cell::Cell;
+use std::io;
+use std::io::{Stream, IoResult, IoError};
+use std::cmp::min;
+use std::uint;
 use std::io::net::ip::SocketAddr;
-use std::io::net::tcp::TcpStream;
-use std::str;
 use std::fmt;
 use rfc2616::{CR, LF, SP};
 use headers;
 use buffer::BufferedStream;
-use common::read_http_version;
+use server::Limits;
+use common::{read_http_version, read_chunk_size, read_chunk_end};
+use error::{HttpError, HttpResult, Io, MalformedStatusLine, MalformedHeader, Truncated,
+            BodyTooLarge, HeadersTooLarge};
+use headers::transfer_encoding;
 
 use headers::{HeaderLineErr, EndOfFile, EndOfHeaders, MalformedHeaderSyntax, MalformedHeaderValue};
 
-// /// Line/header can't be more than 4KB long (note that with the compacting of LWS the actual source
-// /// data could be longer than 4KB)
-// static MAX_LINE_LEN: uint = 0x1000;
-
-static MAX_REQUEST_URI_LEN: uint = 1024;
-pub static MAX_METHOD_LEN: uint = 64;
-
 pub struct RequestBuffer<'a, S> {
     /// The socket connection to read from
     stream: &'a mut BufferedStream<S>,
+
+    /// The limits to read the request within
+    priv limits: Limits,
+
+    /// How many more bytes of header lines may be read
+    priv header_bytes_left: uint,
+
+    /// Whether reading a header line was cut short by the limits
+    priv header_too_large: bool,
 }
 
 impl<'a, S: Stream> RequestBuffer<'a, S> {
+    /// A buffer with the default limits on the Request-Line, and none on header lines.
     pub fn new(stream: &'a mut BufferedStream<S>) -> RequestBuffer<'a, S> {
+        let mut limits = Limits::new();
+        limits.max_header_line_len = uint::MAX;
+        limits.max_header_bytes = uint::MAX;
+        RequestBuffer::with_limits(stream, &limits)
+    }
+
+    /// A buffer reading within the given limits (those of the body aside).
+    pub fn with_limits(stream: &'a mut BufferedStream<S>, limits: &Limits)
+            -> RequestBuffer<'a, S> {
         RequestBuffer {
             stream: stream,
+            limits: *limits,
+            header_bytes_left: limits.max_header_bytes,
+            header_too_large: false,
         }
     }
 
@@ -75,7 +97,7 @@ impl<'a, S: Stream> RequestBuffer<'a, S> {
                 break;
             }
 
-            if raw_request_uri.len() == MAX_REQUEST_URI_LEN {
+            if raw_request_uri.len() == self.limits.max_request_uri_len {
                 return Err(status::RequestUriTooLong)
             }
             raw_request_uri.push_char(next_byte as char);
@@ -128,18 +150,31 @@ impl<'a, S: Stream> RequestBuffer<'a, S> {
     ///
     /// - `EndOfHeaders`: I have no more headers to give; go forth and conquer on the body!
     /// - `EndOfFile`: socket was closed unexpectedly; probable best behavour is to drop the request
-    /// - `MalformedHeaderValue`: header's value is invalid; normally, ignore it.
-    /// - `MalformedHeaderSyntax`: bad request; you could drop it or try returning 400 Bad Request
+    /// - `MalformedHeaderValue(name, value)`: header's value is invalid; normally, ignore it.
+    /// - `MalformedHeaderSyntax(line)`: bad request; you could drop it or try returning 400 Bad
+    ///   Request
+    ///
+    /// If the line goes beyond the limits on header lines, reading stops short, with any of these
+    /// as the result; check `header_too_large` before taking it at its word.
     pub fn read_header<T: headers::HeaderEnum>(&mut self) -> Result<T, HeaderLineErr> {
-        match headers::header_enum_from_stream(&mut *self.stream) {
+        let allowed = min(self.limits.max_header_line_len, self.header_bytes_left);
+        let (result, read) = {
+            let mut reader = LimitedReader { stream: &mut *self.stream, left: allowed,
+                                             exceeded: false };
+            let result = headers::header_enum_from_stream(&mut reader);
+            self.header_too_large = reader.exceeded;
+            (result, allowed - reader.left)
+        };
+        self.header_bytes_left -= read;
+        match result {
         //match headers::HeaderEnum::from_stream(self.stream) {
             (Err(m), None) => Err(m),
             (Err(m), Some(b)) => {
-                self.stream.poke_byte(b);
+                self.poke_byte(b);
                 Err(m)
             },
             (Ok(header), Some(b)) => {
-                self.stream.poke_byte(b);
+                self.poke_byte(b);
                 Ok(header)
             }
             (Ok(header), None) => {
@@ -149,6 +184,58 @@ impl<'a, S: Stream> RequestBuffer<'a, S> {
             }
         }
     }
+
+    /// Whether the last header line read went beyond the limits on header lines.
+    pub fn header_too_large(&self) -> bool {
+        self.header_too_large
+    }
+
+    /// Put back the byte read after a header line, which belongs to the next one.
+    fn poke_byte(&mut self, b: u8) {
+        self.stream.poke_byte(b);
+        if self.header_bytes_left < self.limits.max_header_bytes {
+            self.header_bytes_left += 1;
+        }
+    }
+}
+
+/// A reader of header lines which fails once it has read the bytes allowed them.
+struct LimitedReader<'a, S> {
+    stream: &'a mut BufferedStream<S>,
+    left: uint,
+    exceeded: bool,
+}
+
+impl<'a, S: Stream> LimitedReader<'a, S> {
+    fn exceeded_err(&mut self) -> IoError {
+        self.exceeded = true;
+        IoError {
+            kind: io::OtherIoError,
+            desc: "Header fields too large",
+            detail: None,
+        }
+    }
+}
+
+impl<'a, S: Stream> Reader for LimitedReader<'a, S> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        if self.left == 0 {
+            return Err(self.exceeded_err());
+        }
+        let len = min(self.left, buf.len());
+        let read = try!(self.stream.read(buf.mut_slice_to(len)));
+        self.left -= read;
+        Ok(read)
+    }
+
+    fn read_byte(&mut self) -> IoResult<u8> {
+        if self.left == 0 {
+            return Err(self.exceeded_err());
+        }
+        let b = try!(self.stream.read_byte());
+        self.left -= 1;
+        Ok(b)
+    }
 }
 
 impl<'a, S: Stream> Reader for RequestBuffer<'a, S> {
@@ -200,9 +287,52 @@ fn test_read_request_line() {
     tt!("GET / HTTX/1.1\r\n" => Err(status::BadRequest));
 }
 
+#[test]
+fn test_request_limits() {
+    use buffer::BufferedStream;
+    use memstream::MemReaderFakeStream;
+
+    let mut limits = Limits::new();
+    limits.max_method_len = 8;
+    limits.max_request_uri_len = 16;
+    limits.max_header_line_len = 24;
+    limits.max_header_bytes = 48;
+
+    let read_request_line = |raw: &str| {
+        let mut stream = BufferedStream::new(MemReaderFakeStream::new(raw.as_bytes().to_owned()));
+        RequestBuffer::with_limits(&mut stream, &limits).read_request_line()
+    };
+    assert!(read_request_line("PROPFIND /0123456789abcde HTTP/1.1\r\n").is_ok());
+    assert_eq!(read_request_line("MKCALENDAR / HTTP/1.1\r\n"), Err(status::BadRequest));
+    assert_eq!(read_request_line("GET /0123456789abcdef HTTP/1.1\r\n"),
+               Err(status::RequestUriTooLong));
+
+    // One header line too long for a line
+    let raw = bytes!("Host: example.com\r\nX-Long: 0123456789abcdef\r\n\r\n").to_owned();
+    let mut stream = BufferedStream::new(MemReaderFakeStream::new(raw));
+    let mut buffer = RequestBuffer::with_limits(&mut stream, &limits);
+    let header: Result<headers::request::Header, HeaderLineErr> = buffer.read_header();
+    assert!(header.is_ok() && !buffer.header_too_large());
+    let _: Result<headers::request::Header, HeaderLineErr> = buffer.read_header();
+    assert!(buffer.header_too_large());
+
+    // And lines short enough, but too many bytes of them
+    let raw = bytes!("Host: example.com\r\nX-One: 1\r\nX-Two: 2\r\nX-Three: 3\r\n\r\n").to_owned();
+    let mut stream = BufferedStream::new(MemReaderFakeStream::new(raw));
+    let mut buffer = RequestBuffer::with_limits(&mut stream, &limits);
+    for _ in range(0, 3) {
+        let header: Result<headers::request::Header, HeaderLineErr> = buffer.read_header();
+        assert!(header.is_ok() && !buffer.header_too_large());
+    }
+    let _: Result<headers::request::Header, HeaderLineErr> = buffer.read_header();
+    assert!(buffer.header_too_large());
+}
+
 /// An HTTP request sent to the server.
 pub struct Request {
-    /// The originating IP address of the request.
+    /// The originating IP address of the request; None for a request over a Unix domain socket,
+    /// for which there is nothing in its place (the client's credentials are not available; see
+    /// `Config.unix_socket_path`).
     remote_addr: Option<SocketAddr>,
 
     /// The host name and IP address that the request was sent to; this must always be specified for
@@ -210,12 +340,9 @@ pub struct Request {
     /// header was not defined, and so this field will probably be None in such cases.
     //host: Option<Host>,  // Now in headers.host
 
-    /// The headers sent with the request.
+    /// The headers sent with the request. The body is not here, but read with a `RequestBody`.
     headers: ~headers::request::HeaderCollection,
 
-    /// The body of the request; empty for such methods as GET.
-    body: ~str,
-
     /// The HTTP method for the request.
     method: Method,
 
@@ -228,7 +355,29 @@ pub struct Request {
     close_connection: bool,
 
     /// The HTTP version number; typically `(1, 1)` or, less commonly, `(1, 0)`.
-    version: (uint, uint)
+    version: (uint, uint),
+
+    /// Where the request stands with regard to a 100 (Continue) response; this changes as the
+    /// body is read and the response written.
+    priv continue_state: Cell<ContinueState>,
+
+    /// What was wrong with the request, where `load` refused it as malformed or too large. (Not
+    /// every refusal has an `HttpError` to go with it: an unsupported HTTP version, for one.)
+    error: Option<HttpError>,
+}
+
+/// Whether a client has asked for a 100 (Continue) response before it sends the body (RFC 7231,
+/// §5.1.1), and what has become of it.
+#[deriving(Eq)]
+enum ContinueState {
+    /// It didn't ask (or asked in HTTP/1.0, where the expectation is to be ignored)
+    NotExpected,
+    /// It is waiting for the 100 response
+    Expected,
+    /// The body was read from, so the 100 response has been sent
+    ContinueSent,
+    /// A final response was begun instead, so the client may never send the body
+    Declined,
 }
 
 /// The URI (Request-URI in RFC 2616) as specified in the Status-Line of an HTTP request
@@ -295,25 +444,39 @@ impl fmt::Show for RequestUri {
 }
 
 impl Request {
-
-    /// Get a response from an open socket.
-    pub fn load(stream: &mut BufferedStream<TcpStream>) -> (~Request, Result<(), status::Status>) {
-        let mut buffer = RequestBuffer::new(stream);
-
-        // Start out with dummy values
-        let mut request = ~Request {
-            remote_addr: buffer.stream.wrapped.peer_name().ok(),
+    /// A request from the given client, with dummy values for everything to be read.
+    fn new(remote_addr: Option<SocketAddr>) -> ~Request {
+        ~Request {
+            remote_addr: remote_addr,
             headers: ~headers::request::HeaderCollection::new(),
-            body: ~"",
             method: Options,
             request_uri: Star,
             close_connection: true,
             version: (0, 0),
-        };
+            continue_state: Cell::new(NotExpected),
+            error: None,
+        }
+    }
+
+    /// Read the Request-Line and headers of a request from `remote_addr` from an open socket,
+    /// within the given limits; the body is left to be read with a `RequestBody`.
+    ///
+    /// If the request is to be refused, the status to refuse it with is returned, and the
+    /// request's `error` says what was wrong with it.
+    pub fn load<S: Stream>(stream: &mut BufferedStream<S>, remote_addr: Option<SocketAddr>,
+                           limits: &Limits) -> (~Request, Result<(), status::Status>) {
+        let mut buffer = RequestBuffer::with_limits(stream, limits);
+
+        // Start out with dummy values
+        let mut request = Request::new(remote_addr);
 
         let (method, request_uri, version) = match buffer.read_request_line() {
             Ok(vals) => vals,
-            Err(err) => return (request, Err(err)),
+            Err(status::BadRequest) => {
+                request.error = Some(MalformedStatusLine);
+                return (request, Err(status::BadRequest));
+            },
+            Err(status) => return (request, Err(status)),
         };
         request.method = method;
         request.request_uri = request_uri;
@@ -327,22 +490,34 @@ impl Request {
             _ => return (request, Err(status::HttpVersionNotSupported)),
         };
 
+        let mut header_count = 0u;
         loop {
-            match buffer.read_header() {
+            let header = buffer.read_header();
+            // Reading stopped short at the limits, so whatever it made of the line is meaningless
+            if buffer.header_too_large() {
+                request.error = Some(HeadersTooLarge);
+                return (request, Err(status::RequestHeaderFieldsTooLarge));
+            }
+            match header {
                 Err(EndOfFile) => fail!("client disconnected, nowhere to send response"),
                 Err(EndOfHeaders) => break,
-                Err(MalformedHeaderSyntax) => {
-                    println!("BAD REQUEST: malformed header (TODO: is this right?)");
+                Err(MalformedHeaderSyntax(line)) => {
+                    request.error = Some(MalformedHeader(line, ~""));
                     return (request, Err(status::BadRequest));
                 },
-                Err(MalformedHeaderValue) => {
-                    println!("Bad header encountered. TODO: handle this better.");
-                    // Now just ignore the header
+                Err(MalformedHeaderValue(name, value)) => {
+                    // Just ignore the header
+                    debug!("ignoring header: {}", MalformedHeader(name, value));
                 },
                 Ok(header) => {
                     request.headers.insert(header);
                 },
             }
+            header_count += 1;
+            if header_count > limits.max_headers {
+                request.error = Some(HeadersTooLarge);
+                return (request, Err(status::RequestHeaderFieldsTooLarge));
+            }
         }
 
         // HTTP/1.0 doesn't have Host, but HTTP/1.1 requires it
@@ -351,6 +526,46 @@ impl Request {
             return (request, Err(status::BadRequest));
         }
 
+        // The chunked transfer-coding is the only one understood (RFC 7230, §3.3.1); it must come
+        // last and be applied but once (§3.3.3), which leaves it alone.
+        let transfer_coding_status = match request.headers.transfer_encoding {
+            Some(ref codings) if codings.iter().any(|c| *c != transfer_encoding::Chunked) => {
+                Some(status::NotImplemented)
+            },
+            Some(ref codings) if codings.len() != 1 => Some(status::BadRequest),
+            _ => None,
+        };
+        match transfer_coding_status {
+            Some(status) => return (request, Err(status)),
+            None => (),
+        }
+
+        // A body which is known to be too long is refused before it is read; a chunked one must
+        // wait until it gets that far (see `RequestBody`).
+        match (request.headers.content_length, limits.max_body_len) {
+            (Some(length), Some(max)) if request.headers.transfer_encoding.is_none() &&
+                                         length > max => {
+                request.error = Some(BodyTooLarge(max));
+                return (request, Err(status::RequestEntityTooLarge));
+            },
+            _ => (),
+        }
+
+        // 100-continue is the only expectation there is (RFC 7231, §5.1.1); without a body, there
+        // is nothing for the client to wait to send.
+        let has_body = request.headers.transfer_encoding.is_some() ||
+                       request.headers.content_length.unwrap_or(0) > 0;
+        let expectation = request.headers.expect.as_ref().map(|e| e.trim().to_ascii_lower());
+        match expectation {
+            Some(ref e) if e.as_slice() == "100-continue" => {
+                if request.version != (1, 0) && has_body {
+                    request.continue_state.set(Expected);
+                }
+            },
+            Some(_) => return (request, Err(status::ExpectationFailed)),
+            None => (),
+        }
+
         request.close_connection = close_connection;
         match request.headers.connection {
             Some(ref h) => for v in h.iter() {
@@ -369,22 +584,455 @@ impl Request {
             None => (),
         }
 
-        // Read body if its length is specified
-        match request.headers.content_length {
-            Some(length) => {
-                match buffer.read_bytes(length) {
-                    Ok(body) => match str::from_utf8(body) {
-                        Some(body_str) => request.body = body_str.to_owned(),
-                        None => return (request, Err(status::BadRequest))
+        // With chunked, Content-Length is ignored; but a request with both may be an attempt at
+        // request smuggling, so let's not trust what follows it on the connection.
+        if request.headers.transfer_encoding.is_some() && request.headers.content_length.is_some() {
+            request.close_connection = true;
+        }
+
+        (request, Ok(()))
+    }
+
+    /// Whether the client is waiting for a 100 (Continue) response before it sends the body. The
+    /// response is sent when the body is first read from; so a handler which responds with a
+    /// final status instead (401 or 413, perhaps) without reading the body spares the client from
+    /// sending it.
+    pub fn expects_continue(&self) -> bool {
+        self.continue_state.get() == Expected
+    }
+
+    /// Send no 100 (Continue) response, as the final response is being sent instead. The client
+    /// may then never send the body, so the connection cannot be used for another request.
+    ///
+    /// `ResponseWriter` does this when it writes the headers; there is no need to do it yourself.
+    pub fn decline_continue(&self) {
+        if self.continue_state.get() == Expected {
+            self.continue_state.set(Declined);
+        }
+    }
+}
+
+/// The body of a request, read from the connection as the handler asks for it, so that it can be
+/// as large as it likes and need not be text.
+///
+/// To have the whole of it in memory instead, there are `Reader`'s `read_to_end` and (for UTF-8
+/// text, failing with `InvalidInput` otherwise) `read_to_str`. Whatever the handler does not read
+/// is read past and discarded before the next request on the connection, unless there is too much
+/// of it (see `Limits.max_discard_len`), in which case the connection is closed.
+pub struct RequestBody<'a, S> {
+    priv stream: &'a mut BufferedStream<S>,
+
+    /// That of the request; see `Request.expects_continue`
+    priv continue_state: &'a Cell<ContinueState>,
+
+    /// How the body is being read.
+    priv state: BodyState,
+
+    /// Those of the server: the body is held to `max_body_len`, and its trailer to the limits on
+    /// header fields
+    priv limits: Limits,
+
+    /// How many bytes of chunk-data have been read
+    priv len: uint,
+
+    /// The trailer headers received after the body, when the chunked transfer-coding is in use.
+    /// These are only available after the body has been read in its entirety.
+    trailers: ~headers::request::HeaderCollection,
+
+    /// What went wrong, if reading the body failed; the `IoError` which `read` returned says less.
+    error: Option<HttpError>,
+}
+
+/// The state of reading a request body, determined by the rules of RFC 7230, §3.3.3.
+#[deriving(Eq)]
+enum BodyState {
+    /// The body has a known length (from the Content-Length header); the value is the number of
+    /// bytes remaining.
+    Length(uint),
+
+    /// The chunked transfer-coding (RFC 2616, §3.6.1). The value is the number of bytes of
+    /// chunk-data remaining in the current chunk; zero means that the next chunk-size line has not
+    /// yet been read.
+    Chunked(uint),
+
+    /// The body has been read entirely; all further reads will produce EOF.
+    Finished,
+
+    /// The chunked body went beyond the limit on its length; all further reads will fail, and the
+    /// rest of it cannot be read past.
+    TooLarge(uint),
+
+    /// The trailer of the chunked body went beyond the limits on header fields; as with
+    /// `TooLarge`, all further reads will fail.
+    TrailerTooLarge,
+}
+
+impl<'a, S: Stream> RequestBody<'a, S> {
+    /// The body of a request which has just been read from the stream: in the chunked
+    /// transfer-coding if the Transfer-Encoding header says so, or else as many bytes as the
+    /// Content-Length header says, or none at all.
+    ///
+    /// If the body is chunked and goes on beyond `limits.max_body_len` bytes, reading it fails,
+    /// with `error` set to `BodyTooLarge`; the server then responds with 413 (Request Entity Too
+    /// Large) if the handler hasn't responded already. A Content-Length beyond it is for
+    /// `Request::load` to refuse. Likewise a trailer beyond the limits on header fields fails with
+    /// `HeadersTooLarge`, which is 431 (Request Header Fields Too Large).
+    pub fn new(stream: &'a mut BufferedStream<S>, request: &'a Request, limits: &Limits)
+            -> RequestBody<'a, S> {
+        let headers = &request.headers;
+        let state = match (&headers.transfer_encoding, headers.content_length) {
+            (&Some(_), _) => Chunked(0),
+            (&None, Some(0)) | (&None, None) => Finished,
+            (&None, Some(length)) => Length(length),
+        };
+        RequestBody {
+            stream: stream,
+            continue_state: &request.continue_state,
+            state: state,
+            limits: *limits,
+            len: 0,
+            trailers: ~headers::request::HeaderCollection::new(),
+            error: None,
+        }
+    }
+
+    /// Whether the body has been read in its entirety.
+    pub fn is_finished(&self) -> bool {
+        self.state == Finished
+    }
+
+    /// How many bytes of the body are left to be read, if that is known; for a chunked body, it
+    /// isn't until the last chunk has been read.
+    pub fn remaining_len(&self) -> Option<uint> {
+        match self.state {
+            Finished => Some(0),
+            Length(remaining) => Some(remaining),
+            Chunked(_) | TooLarge(_) | TrailerTooLarge => None,
+        }
+    }
+
+    /// Tell the client, which is waiting to be told, to send the body.
+    fn send_continue(&mut self) -> IoResult<()> {
+        self.continue_state.set(ContinueSent);
+        try!(self.stream.write(bytes!("HTTP/1.1 100 Continue\r\n\r\n")));
+        self.stream.flush()
+    }
+
+    /// Read the trailer of a chunked body, up to and including the empty line which ends it, within
+    /// the same limits as the header fields of the request.
+    fn read_trailers(&mut self) -> HttpResult<()> {
+        let limits = self.limits;
+        let mut buffer = RequestBuffer::with_limits(&mut *self.stream, &limits);
+        let mut trailer_count = 0u;
+        loop {
+            let header = buffer.read_header();
+            // Reading stopped short at the limits, so whatever it made of the line is meaningless
+            if buffer.header_too_large() {
+                return Err(HeadersTooLarge);
+            }
+            match header {
+                Err(EndOfFile) => return Err(Truncated),
+                Err(EndOfHeaders) => return Ok(()),
+                Err(MalformedHeaderSyntax(line)) => return Err(MalformedHeader(line, ~"")),
+                Err(MalformedHeaderValue(name, value)) => {
+                    // Just ignore the header
+                    debug!("ignoring trailer: {}", MalformedHeader(name, value));
+                },
+                Ok(header) => self.trailers.insert(header),
+            }
+            trailer_count += 1;
+            if trailer_count > limits.max_headers {
+                return Err(HeadersTooLarge);
+            }
+        }
+    }
+
+    /// Read past whatever remains of the body, so that the next request can be read.
+    ///
+    /// If the client was waiting for a 100 (Continue) response and didn't get one, it may never
+    /// send the body; then nothing is read, and the body is left unfinished.
+    pub fn discard(&mut self) -> IoResult<()> {
+        if self.continue_state.get() == Declined {
+            return Ok(());
+        }
+        let mut buf = [0u8, ..4096];
+        loop {
+            match self.read(buf) {
+                Ok(_) => (),
+                Err(ref err) if err.kind == io::EndOfFile => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl<'a, S: Stream> Reader for RequestBody<'a, S> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        match self.read_body(buf) {
+            Ok(read) => Ok(read),
+            // The end of the body, rather than anything having gone wrong
+            Err(Io(ref err)) if err.kind == io::EndOfFile => Err(err.clone()),
+            Err(err) => {
+                let io_err = err.to_io_error();
+                self.error = Some(err);
+                Err(io_err)
+            },
+        }
+    }
+}
+
+impl<'a, S: Stream> RequestBody<'a, S> {
+    /// Read from the body, according to the manner in which its end is marked.
+    fn read_body(&mut self, buf: &mut [u8]) -> HttpResult<uint> {
+        if self.state != Finished && self.continue_state.get() == Expected {
+            try!(self.send_continue().map_err(Io));
+        }
+        match self.state {
+            Finished => Err(Io(io::standard_error(io::EndOfFile))),
+            TooLarge(max) => Err(BodyTooLarge(max)),
+            TrailerTooLarge => Err(HeadersTooLarge),
+            Length(remaining) => {
+                let len = min(remaining, buf.len());
+                let read = try!(self.stream.read(buf.mut_slice_to(len))
+                                    .map_err(|err| Io(err).eof_is_truncation()));
+                self.state = if read == remaining { Finished } else { Length(remaining - read) };
+                Ok(read)
+            },
+            Chunked(0) => {
+                let size = read_chunk_size(&mut *self.stream);
+                match try!(size.map_err(|err| err.eof_is_truncation())) {
+                    0 => {
+                        // That was the last-chunk; all that remains is the trailer.
+                        let trailers = self.read_trailers();
+                        if trailers == Err(HeadersTooLarge) {
+                            self.state = TrailerTooLarge;
+                        }
+                        try!(trailers);
+                        self.state = Finished;
+                        Err(Io(io::standard_error(io::EndOfFile)))
+                    },
+                    size => {
+                        self.state = match self.limits.max_body_len {
+                            Some(max) if size > max - self.len => TooLarge(max),
+                            _ => Chunked(size),
+                        };
+                        self.read_body(buf)
                     },
-                    Err(_) => return (request, Err(status::BadRequest))
                 }
             },
-            None => ()
+            Chunked(remaining) => {
+                let len = min(remaining, buf.len());
+                let read = try!(self.stream.read(buf.mut_slice_to(len))
+                                    .map_err(|err| Io(err).eof_is_truncation()));
+                self.len += read;
+                if read == remaining {
+                    let end = read_chunk_end(&mut *self.stream);
+                    try!(end.map_err(|err| err.eof_is_truncation()));
+                    self.state = Chunked(0);
+                } else {
+                    self.state = Chunked(remaining - read);
+                }
+                Ok(read)
+            },
         }
+    }
+}
 
-        (request, Ok(()))
+#[test]
+fn test_load_transfer_coding() {
+    use buffer::BufferedStream;
+    use memstream::MemReaderFakeStream;
+
+    let load = |raw: &str| {
+        let mut stream = BufferedStream::new(MemReaderFakeStream::new(raw.as_bytes().to_owned()));
+        Request::load(&mut stream, None, &Limits::new())
+    };
+
+    let (request, result) = load("POST / HTTP/1.1\r\nHost: example.com\r\n\
+                                  Transfer-Encoding: chunked\r\n\r\n");
+    assert_eq!(result, Ok(()));
+    assert!(!request.close_connection);
+
+    // Only chunked is understood
+    let (_, result) = load("POST / HTTP/1.1\r\nHost: example.com\r\n\
+                            Transfer-Encoding: gzip, chunked\r\n\r\n");
+    assert_eq!(result, Err(status::NotImplemented));
+
+    // And it may be applied but once
+    let (_, result) = load("POST / HTTP/1.1\r\nHost: example.com\r\n\
+                            Transfer-Encoding: chunked, chunked\r\n\r\n");
+    assert_eq!(result, Err(status::BadRequest));
+
+    // With Content-Length as well, what follows the body is not to be trusted
+    let (request, result) = load("POST / HTTP/1.1\r\nHost: example.com\r\n\
+                                  Transfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\n");
+    assert_eq!(result, Ok(()));
+    assert!(request.close_connection);
+}
+
+#[test]
+fn test_load_body_too_large() {
+    use buffer::BufferedStream;
+    use memstream::MemReaderFakeStream;
+    use server::DEFAULT_MAX_BODY_LEN;
+
+    // The body is limited by default
+    let raw = bytes!("POST / HTTP/1.1\r\nHost: example.com\r\nContent-Length: 20000000\r\n\r\n");
+    let mut stream = BufferedStream::new(MemReaderFakeStream::new(raw.to_owned()));
+    let (request, result) = Request::load(&mut stream, None, &Limits::new());
+    assert_eq!(result, Err(status::RequestEntityTooLarge));
+    assert_eq!(request.error, Some(BodyTooLarge(DEFAULT_MAX_BODY_LEN)));
+}
+
+#[test]
+fn test_load_expect_continue() {
+    use buffer::BufferedStream;
+    use memstream::MemReaderFakeStream;
+
+    let load = |raw: &str| {
+        let mut stream = BufferedStream::new(MemReaderFakeStream::new(raw.as_bytes().to_owned()));
+        let (request, result) = Request::load(&mut stream, None, &Limits::new());
+        assert_eq!(result, Ok(()));
+        request
+    };
+
+    let request = load("PUT / HTTP/1.1\r\nHost: example.com\r\nExpect: 100-continue\r\n\
+                        Content-Length: 5\r\n\r\n");
+    assert!(request.expects_continue());
+
+    // Without a body, there is nothing to wait for; so declining it needn't close the connection
+    let request = load("PUT / HTTP/1.1\r\nHost: example.com\r\nExpect: 100-continue\r\n\
+                        Content-Length: 0\r\n\r\n");
+    assert!(!request.expects_continue());
+    let request = load("GET / HTTP/1.1\r\nHost: example.com\r\nExpect: 100-continue\r\n\r\n");
+    assert!(!request.expects_continue());
+}
+
+#[test]
+fn test_request_body() {
+    use buffer::BufferedStream;
+    use memstream::MemReaderFakeStream;
+
+    let mut with_length = Request::new(None);
+    with_length.headers.content_length = Some(5);
+    let raw = bytes!("Hello, world").to_owned();
+    let mut stream = BufferedStream::new(MemReaderFakeStream::new(raw));
+    {
+        let mut body = RequestBody::new(&mut stream, &*with_length, &Limits::new());
+        assert!(!body.is_finished());
+        assert_eq!(body.remaining_len(), Some(5));
+        assert_eq!(body.read_to_str(), Ok(~"Hello"));
+        assert!(body.is_finished());
+        assert_eq!(body.remaining_len(), Some(0));
+        assert_eq!(body.read_byte().unwrap_err().kind, io::EndOfFile);
+    }
+    // What follows the body is left for the next request
+    assert_eq!(stream.read_to_end(), Ok(bytes!(", world").to_owned()));
+
+    // A body cut short
+    let mut stream = BufferedStream::new(MemReaderFakeStream::new(bytes!("Hel").to_owned()));
+    let mut body = RequestBody::new(&mut stream, &*with_length, &Limits::new());
+    assert!(body.discard().is_err());
+    assert_eq!(body.error, Some(Truncated));
+
+    // No Content-Length, no body
+    let without_length = Request::new(None);
+    let mut stream = BufferedStream::new(MemReaderFakeStream::new(bytes!("GET").to_owned()));
+    let mut body = RequestBody::new(&mut stream, &*without_length, &Limits::new());
+    assert!(body.is_finished());
+    assert_eq!(body.read_to_end(), Ok(~[]));
+}
+
+#[test]
+fn test_chunked_request_body() {
+    use buffer::BufferedStream;
+    use memstream::MemReaderFakeStream;
+    use error::InvalidChunk;
+
+    let mut chunked = Request::new(None);
+    chunked.headers.transfer_encoding = Some(vec!(transfer_encoding::Chunked));
+    let raw = bytes!("5;name=value\r\nHello\r\n7\r\n, world\r\n0\r\n\
+                      X-Checksum: 1234\r\n\r\nGET").to_owned();
+    let mut stream = BufferedStream::new(MemReaderFakeStream::new(raw));
+    {
+        let mut body = RequestBody::new(&mut stream, &*chunked, &Limits::new());
+        assert_eq!(body.remaining_len(), None);
+        assert_eq!(body.read_to_str(), Ok(~"Hello, world"));
+        assert!(body.is_finished());
+        assert_eq!(body.trailers.extensions.find(&~"X-Checksum"), Some(&~"1234"));
+    }
+    assert_eq!(stream.read_to_end(), Ok(bytes!("GET").to_owned()));
+
+    let raw = bytes!("5\r\nHelloX\r\n").to_owned();
+    let mut stream = BufferedStream::new(MemReaderFakeStream::new(raw));
+    let mut body = RequestBody::new(&mut stream, &*chunked, &Limits::new());
+    assert!(body.discard().is_err());
+    assert_eq!(body.error, Some(InvalidChunk));
+
+    // A chunk which would take the body beyond its limit is not read
+    let raw = bytes!("5\r\nHello\r\n7\r\n, world\r\n0\r\n\r\n").to_owned();
+    let mut stream = BufferedStream::new(MemReaderFakeStream::new(raw));
+    let mut limits = Limits::new();
+    limits.max_body_len = Some(8);
+    let mut body = RequestBody::new(&mut stream, &*chunked, &limits);
+    let mut buf = [0u8, ..8];
+    assert_eq!(body.read(buf), Ok(5));
+    assert!(body.read(buf).is_err());
+    assert_eq!(body.error, Some(BodyTooLarge(8)));
+    assert!(body.discard().is_err());
+    assert!(!body.is_finished());
+
+    // As is a trailer beyond the limits on header fields, whether too long or too many
+    let mut limits = Limits::new();
+    limits.max_headers = 1;
+    limits.max_header_line_len = 24;
+    let raw = bytes!("5\r\nHello\r\n0\r\nX-Checksum: 12345678901234567890\r\n\r\n").to_owned();
+    let mut stream = BufferedStream::new(MemReaderFakeStream::new(raw));
+    let mut body = RequestBody::new(&mut stream, &*chunked, &limits);
+    assert!(body.read_to_end().is_err());
+    assert_eq!(body.error, Some(HeadersTooLarge));
+    assert!(body.discard().is_err());
+    assert!(!body.is_finished());
+    assert_eq!(body.remaining_len(), None);
+
+    let raw = bytes!("5\r\nHello\r\n0\r\nX-One: 1\r\nX-Two: 2\r\n\r\n").to_owned();
+    let mut stream = BufferedStream::new(MemReaderFakeStream::new(raw));
+    let mut body = RequestBody::new(&mut stream, &*chunked, &limits);
+    assert!(body.read_to_end().is_err());
+    assert_eq!(body.error, Some(HeadersTooLarge));
+    assert!(!body.is_finished());
+}
+
+#[test]
+fn test_expect_continue() {
+    use buffer::BufferedStream;
+    use connecter::Connecter;
+    use memstream::MemReaderFakeStream;
+    use client::mock::{MockServer, MockResponse, MockStream};
+
+    // The mock server plays the part of the client, sending the body once it's asked for
+    let client = MockServer::new();
+    client.respond(MockResponse::new("Hello"));
+    let stream: MockStream = Connecter::connect(client.addr()).unwrap();
+    let mut stream = BufferedStream::new(stream);
+    let mut request = Request::new(None);
+    request.headers.content_length = Some(5);
+    request.continue_state.set(Expected);
+    {
+        let mut body = RequestBody::new(&mut stream, &*request, &Limits::new());
+        assert!(request.expects_continue());
+        assert_eq!(body.read_to_str(), Ok(~"Hello"));
+        assert!(!request.expects_continue());
     }
+    assert_eq!(client.requests(), vec!(bytes!("HTTP/1.1 100 Continue\r\n\r\n").to_owned()));
+
+    // Once declined, the body is not waited for
+    request.continue_state.set(Expected);
+    request.decline_continue();
+    let mut stream = BufferedStream::new(MemReaderFakeStream::new(bytes!("Hello").to_owned()));
+    let mut body = RequestBody::new(&mut stream, &*request, &Limits::new());
+    assert!(body.discard().is_ok());
+    assert!(!body.is_finished());
 }
 
 
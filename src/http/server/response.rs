@@ -1,12 +1,12 @@
 use std::io::IoResult;
-use std::io::net::tcp::TcpStream;
 
 use buffer::BufferedStream;
-use server::Request;
+use server::{Request, ServerStream};
 use status;
 use headers::response::HeaderCollection;
 use headers::content_type::MediaType;
 use headers::transfer_encoding::Chunked;
+use headers::connection::Close;
 
 /*
  * The HTTP version tag which will be used for the response.
@@ -19,8 +19,8 @@ use headers::transfer_encoding::Chunked;
 // Maybe we could provide a response interface
 
 pub struct ResponseWriter<'a> {
-    // The place to write to (typically a TCP stream, io::net::tcp::TcpStream)
-    priv writer: &'a mut BufferedStream<TcpStream>,
+    // The place to write to (a TCP stream or Unix domain socket)
+    priv writer: &'a mut BufferedStream<ServerStream>,
     priv headers_written: bool,
     request: &'a Request,
     headers: ~HeaderCollection,
@@ -29,7 +29,8 @@ pub struct ResponseWriter<'a> {
 
 impl<'a> ResponseWriter<'a> {
     /// Create a `ResponseWriter` writing to the specified location
-    pub fn new(writer: &'a mut BufferedStream<TcpStream>, request: &'a Request) -> ResponseWriter<'a> {
+    pub fn new(writer: &'a mut BufferedStream<ServerStream>, request: &'a Request)
+            -> ResponseWriter<'a> {
         ResponseWriter {
             writer: writer,
             headers_written: false,
@@ -49,6 +50,12 @@ impl<'a> ResponseWriter<'a> {
         self.write(cbytes)
     }
 
+    /// Whether the Status-Line and headers have been written, after which the response has begun
+    /// and its status and headers cannot be changed.
+    pub fn headers_written(&self) -> bool {
+        self.headers_written
+    }
+
     /// Write the Status-Line and headers of the response, if we have not already done so.
     pub fn try_write_headers(&mut self) -> IoResult<()> {
         if !self.headers_written {
@@ -64,6 +71,9 @@ impl<'a> ResponseWriter<'a> {
     /// (``self.headers.transfer_encoding``), ensuring it is ``None`` if the Content-Length header
     /// has been specified, or to ``chunked`` if it has not, thus switching to the chunked coding.
     ///
+    /// If the client is still waiting for a 100 (Continue) response, it will not now get one (see
+    /// `Request.expects_continue`), and the connection is closed after this response.
+    ///
     /// If the headers have already been written, this will fail. See also `try_write_headers`.
     pub fn write_headers(&mut self) -> IoResult<()> {
         // This marks the beginning of the response (RFC2616 §6)
@@ -71,6 +81,11 @@ impl<'a> ResponseWriter<'a> {
             fail!("ResponseWriter.write_headers() called, but headers already written");
         }
 
+        if self.request.expects_continue() {
+            self.request.decline_continue();
+            self.headers.connection = Some(vec!(Close));
+        }
+
         // Write the Status-Line (RFC2616 §6.1)
         // XXX: might be better not to hardcode HTTP/1.1.
         // XXX: Rust's current lack of statement-duration lifetime handling prevents this from being
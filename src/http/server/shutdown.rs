@@ -0,0 +1,294 @@
+/*!
+
+Stopping a server gracefully.
+
+A server run with `Server::serve_until_shutdown` serves until its `ShutdownHandle` (or any clone of
+it) is told to `shutdown`. It then stops accepting connections and closes those which are idle,
+waiting for a new request; requests which are being handled are allowed to finish, with their
+connections closed after the response, until the grace period runs out. Then any connection which
+has still not finished is shut down, so that the handler's next read or write fails, and
+`serve_until_shutdown` returns (having removed the socket file, if it was listening on a Unix
+domain socket).
+
+```rust
+use std::io::net::ip::{SocketAddr, Ipv4Addr};
+use http::server::{Config, Server, Request, RequestBody, ResponseWriter, ServerStream};
+use http::server::ShutdownHandle;
+
+#[deriving(Clone)]
+struct HelloServer;
+
+impl Server for HelloServer {
+    fn get_config(&self) -> Config {
+        Config::new(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 })
+    }
+
+    fn handle_request(&self, _r: &Request, _b: &mut RequestBody<ServerStream>,
+                      w: &mut ResponseWriter) {
+        w.write(bytes!("Hello, World!\n")).unwrap();
+    }
+}
+
+let shutdown = ShutdownHandle::new();
+let handle = shutdown.clone();
+let (done_sender, done_receiver) = channel();
+spawn(proc() {
+    HelloServer.serve_until_shutdown(handle);
+    done_sender.send(());
+});
+// ... and when it's time to stop, give requests a few seconds to finish:
+shutdown.shutdown(5_000);
+done_receiver.recv();
+```
+
+*/
+
+use std::io::timer;
+use collections::hashmap::HashMap;
+use sync::{Arc, Mutex};
+use time::precise_time_ns;
+
+use server::ServerStream;
+
+/// How often, in milliseconds, the server looks up from waiting for a connection or a request to
+/// see whether it is to shut down.
+pub static POLL_INTERVAL_MS: u64 = 100;
+
+/// Where a server stands with regard to shutting down.
+struct ShutdownState {
+    /// Once shutdown has been asked for, the time (by `precise_time_ns`) by which the open
+    /// connections must finish
+    deadline_ns: Option<u64>,
+
+    /// The connections which are open, by the number which each was given, with a handle on each
+    /// with which to shut it down at the deadline
+    connections: HashMap<uint, ServerStream>,
+
+    /// The number to give the next connection
+    next_connection: uint,
+}
+
+/// A handle with which to shut down a server, shared between its clones.
+pub struct ShutdownHandle {
+    priv state: Arc<Mutex<ShutdownState>>,
+}
+
+impl ShutdownHandle {
+    /// A handle for a server which is not (yet) to shut down.
+    pub fn new() -> ShutdownHandle {
+        ShutdownHandle {
+            state: Arc::new(Mutex::new(ShutdownState {
+                deadline_ns: None,
+                connections: HashMap::new(),
+                next_connection: 0,
+            })),
+        }
+    }
+
+    /// Tell the server to shut down, giving requests which are being handled up to `grace_ms`
+    /// milliseconds to finish. This returns at once; it is `serve_until_shutdown` returning that
+    /// marks the server as shut down.
+    ///
+    /// Shutting down again can bring the deadline forward, but not put it back.
+    pub fn shutdown(&self, grace_ms: u64) {
+        let deadline_ns = precise_time_ns() + grace_ms * 1_000_000;
+        let mut state = self.state.lock();
+        state.deadline_ns = match state.deadline_ns {
+            Some(earlier) if earlier < deadline_ns => Some(earlier),
+            _ => Some(deadline_ns),
+        };
+    }
+
+    /// Whether the server has been told to shut down.
+    pub fn is_shutting_down(&self) -> bool {
+        self.state.lock().deadline_ns.is_some()
+    }
+
+    /// The number of connections which the server has open.
+    pub fn connection_count(&self) -> uint {
+        self.state.lock().connections.len()
+    }
+
+    /// Count a connection as open until the value returned is dropped (even in task failure),
+    /// keeping `stream` (a clone of the connection) to shut it down with if it is still open at the
+    /// deadline. The server does this for each connection that it accepts.
+    pub fn open_connection(&self, stream: ServerStream) -> OpenConnection {
+        let mut state = self.state.lock();
+        let id = state.next_connection;
+        state.next_connection += 1;
+        state.connections.insert(id, stream);
+        OpenConnection {
+            state: self.state.clone(),
+            id: id,
+        }
+    }
+
+    /// Having been told to shut down, wait until every connection is closed or the deadline has
+    /// passed, whichever comes first, and return the number of connections still open. Those are
+    /// shut down, so that their tasks fail to read or write any more and finish in their turn.
+    pub fn wait_for_connections(&self) -> uint {
+        loop {
+            let mut state = self.state.lock();
+            let deadline_ns = state.deadline_ns
+                                   .expect("wait_for_connections called before shutdown");
+            if state.connections.len() == 0 {
+                return 0;
+            }
+            if precise_time_ns() >= deadline_ns {
+                for (_, stream) in state.connections.mut_iter() {
+                    // It may already have been closed at the other end; that's just as well
+                    let _ = stream.close_read();
+                    let _ = stream.close_write();
+                }
+                return state.connections.len();
+            }
+            drop(state);
+            timer::sleep(POLL_INTERVAL_MS);
+        }
+    }
+}
+
+impl Clone for ShutdownHandle {
+    fn clone(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            state: self.state.clone(),
+        }
+    }
+}
+
+/// A connection counted as open by a `ShutdownHandle`, until this is dropped.
+pub struct OpenConnection {
+    priv state: Arc<Mutex<ShutdownState>>,
+    priv id: uint,
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.state.lock().connections.remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Listener, Acceptor, Writer};
+    use std::io::net::ip::{SocketAddr, Ipv4Addr};
+    use std::io::net::tcp::{TcpListener, TcpStream};
+    use std::io::timer;
+    use std::os;
+    use std::str;
+    use time::precise_time_ns;
+    use server::{Config, Server, Request, RequestBody, ResponseWriter, ServerStream};
+    use server::TcpServerStream;
+    use super::ShutdownHandle;
+
+    #[deriving(Clone)]
+    struct HelloServer {
+        addr: SocketAddr,
+        socket: Option<Path>,
+    }
+
+    impl Server for HelloServer {
+        fn get_config(&self) -> Config {
+            let mut config = Config::new(self.addr);
+            config.unix_socket_path = self.socket.clone();
+            config
+        }
+
+        fn handle_request(&self, _request: &Request, _body: &mut RequestBody<ServerStream>,
+                          response: &mut ResponseWriter) {
+            response.headers.content_length = Some(5);
+            response.write(bytes!("Hello")).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_shutdown() {
+        // A port which was free a moment ago
+        let addr = {
+            let listener = TcpListener::bind(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 0 })
+                                      .unwrap();
+            listener.socket_name().unwrap()
+        };
+        let shutdown = ShutdownHandle::new();
+        let handle = shutdown.clone();
+        let (done_sender, done_receiver) = channel();
+        spawn(proc() {
+            HelloServer { addr: addr, socket: None }.serve_until_shutdown(handle);
+            done_sender.send(());
+        });
+
+        // Once it is listening
+        let mut stream = None;
+        while stream.is_none() {
+            stream = TcpStream::connect(addr).ok();
+            timer::sleep(10);
+        }
+        let mut stream = stream.unwrap();
+        stream.write(bytes!("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")).unwrap();
+        let mut response = Vec::new();
+        while !response.as_slice().ends_with(bytes!("Hello")) {
+            response.push(stream.read_byte().unwrap());
+        }
+        assert!(str::from_utf8(response.as_slice()).unwrap().starts_with("HTTP/1.1 200 OK\r\n"));
+
+        // The connection is now idle, so it is closed, and the server stops
+        shutdown.shutdown(10_000);
+        assert_eq!(stream.read_to_end(), Ok(~[]));
+        done_receiver.recv();
+        assert_eq!(shutdown.connection_count(), 0);
+        assert!(TcpStream::connect(addr).is_err());
+    }
+
+    #[test]
+    fn test_unix_socket_removed() {
+        let path = os::tmpdir().join(format!("rust-http-test-{}.sock", precise_time_ns()));
+        let server = HelloServer {
+            addr: SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 0 },
+            socket: Some(path.clone()),
+        };
+        let shutdown = ShutdownHandle::new();
+        let handle = shutdown.clone();
+        let (done_sender, done_receiver) = channel();
+        spawn(proc() {
+            server.serve_until_shutdown(handle);
+            done_sender.send(());
+        });
+        while !path.exists() {
+            timer::sleep(10);
+        }
+        shutdown.shutdown(0);
+        done_receiver.recv();
+        assert!(!path.exists());
+    }
+
+    /// A connection over TCP: the server's end of it, and the client's.
+    fn connection() -> (ServerStream, TcpStream) {
+        let listener = TcpListener::bind(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 0 })
+                                  .unwrap();
+        let addr = listener.socket_name().unwrap();
+        let mut acceptor = listener.listen().unwrap();
+        let client = TcpStream::connect(addr).unwrap();
+        (TcpServerStream(acceptor.accept().unwrap()), client)
+    }
+
+    #[test]
+    fn test_connection_count() {
+        let (first_stream, _first_client) = connection();
+        let (second_stream, mut second_client) = connection();
+        let shutdown = ShutdownHandle::new();
+        let first = shutdown.open_connection(first_stream);
+        let second = shutdown.clone().open_connection(second_stream);
+        assert_eq!(shutdown.connection_count(), 2);
+        drop(first);
+        assert_eq!(shutdown.connection_count(), 1);
+
+        // The one left open is shut down at the deadline
+        assert!(!shutdown.is_shutting_down());
+        shutdown.shutdown(0);
+        assert!(shutdown.is_shutting_down());
+        assert_eq!(shutdown.wait_for_connections(), 1);
+        assert_eq!(second_client.read_to_end(), Ok(~[]));
+        drop(second);
+        assert_eq!(shutdown.wait_for_connections(), 0);
+    }
+}